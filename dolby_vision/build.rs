@@ -0,0 +1,4 @@
+fn main() {
+    // `cargo_c` is set by cargo-c when building the C API
+    println!("cargo:rustc-check-cfg=cfg(cargo_c)");
+}
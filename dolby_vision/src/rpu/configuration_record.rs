@@ -0,0 +1,223 @@
+use anyhow::{bail, ensure, Result};
+
+#[cfg(feature = "serde_feature")]
+use serde::Serialize;
+
+use super::dovi_rpu::DoviRpu;
+
+/// Size of the `dvcC`/`dvvC`/`dvwC` box payload
+pub const CONFIGURATION_RECORD_SIZE: usize = 24;
+
+/// Maximum (pixels per second, width) for each Dolby Vision level, starting at level 1
+const DV_LEVELS: [(u64, u64); 13] = [
+    (1280 * 720 * 24, 1280),
+    (1280 * 720 * 30, 1280),
+    (1920 * 1080 * 24, 1920),
+    (1920 * 1080 * 30, 2560),
+    (1920 * 1080 * 60, 3840),
+    (3840 * 2160 * 24, 3840),
+    (3840 * 2160 * 30, 3840),
+    (3840 * 2160 * 48, 3840),
+    (3840 * 2160 * 60, 3840),
+    (3840 * 2160 * 120, 3840),
+    (3840 * 2160 * 120, 7680),
+    (7680 * 4320 * 60, 7680),
+    (7680 * 4320 * 120, 7680),
+];
+
+/// Dolby Vision decoder configuration record, as stored in ISOBMFF/Matroska
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde_feature", derive(Serialize))]
+pub struct DoviDecoderConfigurationRecord {
+    pub dv_version_major: u8,
+    pub dv_version_minor: u8,
+    pub dv_profile: u8,
+    pub dv_level: u8,
+    pub rpu_present_flag: bool,
+    pub el_present_flag: bool,
+    pub bl_present_flag: bool,
+    pub dv_bl_signal_compatibility_id: u8,
+}
+
+impl DoviDecoderConfigurationRecord {
+    /// Derives the configuration record for a single track stream carrying the RPU.
+    ///
+    /// `transfer_characteristics` is the base layer VUI value, used to tell profiles 8.1, 8.2 and 8.4 apart.
+    /// Without it, or when unspecified (2), profile 8 is assumed to be 8.1 if the RPU signals PQ, 8.2 otherwise.
+    pub fn from_rpu(
+        rpu: &DoviRpu,
+        dv_level: u8,
+        transfer_characteristics: Option<u8>,
+    ) -> Result<DoviDecoderConfigurationRecord> {
+        ensure!(
+            (1..=13).contains(&dv_level),
+            "Invalid Dolby Vision level {}",
+            dv_level
+        );
+
+        let dv_bl_signal_compatibility_id = match rpu.dovi_profile {
+            4 => 2,
+            5 => 0,
+            7 => 6,
+            8 => match transfer_characteristics {
+                Some(16) => 1,
+                Some(18) => 4,
+                Some(tc) if tc != 2 => 2,
+                _ => {
+                    let pq = rpu
+                        .vdr_dm_data
+                        .as_ref()
+                        .map_or(true, |vdr_dm_data| vdr_dm_data.signal_eotf == 65535);
+
+                    if pq {
+                        1
+                    } else {
+                        2
+                    }
+                }
+            },
+            profile => bail!("Unsupported Dolby Vision profile {}", profile),
+        };
+
+        Ok(DoviDecoderConfigurationRecord {
+            dv_version_major: 1,
+            dv_version_minor: 0,
+            dv_profile: rpu.dovi_profile,
+            dv_level,
+            rpu_present_flag: true,
+            el_present_flag: matches!(rpu.dovi_profile, 4 | 7),
+            bl_present_flag: true,
+            dv_bl_signal_compatibility_id,
+        })
+    }
+
+    /// Lowest Dolby Vision level supporting the resolution and frame rate
+    pub fn level_from_stream(width: u64, height: u64, fps: f64) -> Option<u8> {
+        let pixel_rate = (width * height) as f64 * fps;
+
+        DV_LEVELS
+            .iter()
+            .position(|(max_pps, max_width)| pixel_rate <= *max_pps as f64 && width <= *max_width)
+            .map(|i| i as u8 + 1)
+    }
+
+    pub fn parse(data: &[u8]) -> Result<DoviDecoderConfigurationRecord> {
+        ensure!(
+            data.len() >= CONFIGURATION_RECORD_SIZE,
+            "Invalid configuration record size {}",
+            data.len()
+        );
+
+        Ok(DoviDecoderConfigurationRecord {
+            dv_version_major: data[0],
+            dv_version_minor: data[1],
+            dv_profile: data[2] >> 1,
+            dv_level: ((data[2] & 0x01) << 5) | (data[3] >> 3),
+            rpu_present_flag: data[3] & 0x04 != 0,
+            el_present_flag: data[3] & 0x02 != 0,
+            bl_present_flag: data[3] & 0x01 != 0,
+            dv_bl_signal_compatibility_id: data[4] >> 4,
+        })
+    }
+
+    /// Box payload, the reserved bits are zero
+    pub fn write(&self) -> [u8; CONFIGURATION_RECORD_SIZE] {
+        let mut data = [0; CONFIGURATION_RECORD_SIZE];
+
+        data[0] = self.dv_version_major;
+        data[1] = self.dv_version_minor;
+        data[2] = (self.dv_profile << 1) | (self.dv_level >> 5);
+        data[3] = (self.dv_level << 3)
+            | ((self.rpu_present_flag as u8) << 2)
+            | ((self.el_present_flag as u8) << 1)
+            | self.bl_present_flag as u8;
+        data[4] = self.dv_bl_signal_compatibility_id << 4;
+
+        data
+    }
+
+    /// Box type for the profile: `dvcC` up to profile 7, `dvvC` up to 10 and `dvwC` after
+    pub fn box_type(&self) -> &'static str {
+        match self.dv_profile {
+            0..=7 => "dvcC",
+            8..=10 => "dvvC",
+            _ => "dvwC",
+        }
+    }
+
+    /// RFC 6381 codec string, `dvhe` for in-band parameter sets (`hev1`), `dvh1` otherwise (`hvc1`)
+    pub fn codec_string(&self, in_band_parameter_sets: bool) -> String {
+        format!(
+            "{}.{:02}.{:02}",
+            if in_band_parameter_sets {
+                "dvhe"
+            } else {
+                "dvh1"
+            },
+            self.dv_profile,
+            self.dv_level
+        )
+    }
+
+    /// HLS `SUPPLEMENTAL-CODECS` brand of the backwards compatible base layer
+    pub fn compatibility_brand(&self) -> Option<&'static str> {
+        match self.dv_bl_signal_compatibility_id {
+            1 => Some("db1p"),
+            2 => Some("db2g"),
+            4 => Some("db4h"),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DoviDecoderConfigurationRecord;
+
+    #[test]
+    fn configuration_record_roundtrip() {
+        let record = DoviDecoderConfigurationRecord {
+            dv_version_major: 1,
+            dv_version_minor: 0,
+            dv_profile: 8,
+            dv_level: 6,
+            rpu_present_flag: true,
+            el_present_flag: false,
+            bl_present_flag: true,
+            dv_bl_signal_compatibility_id: 1,
+        };
+
+        let data = record.write();
+        assert_eq!(&data[..5], &[1, 0, 0x10, 0x35, 0x10]);
+        assert!(data[5..].iter().all(|b| *b == 0));
+
+        assert_eq!(
+            DoviDecoderConfigurationRecord::parse(&data).unwrap(),
+            record
+        );
+        assert_eq!(record.box_type(), "dvvC");
+        assert_eq!(record.codec_string(false), "dvh1.08.06");
+        assert_eq!(record.compatibility_brand(), Some("db1p"));
+
+        let levels = [
+            (1920, 1080, 24000.0 / 1001.0, 3),
+            (1920, 1080, 60.0, 5),
+            (3840, 2160, 24000.0 / 1001.0, 6),
+            (3840, 2160, 30000.0 / 1001.0, 7),
+            (3840, 2160, 60.0, 9),
+            (7680, 4320, 24.0, 11),
+        ];
+
+        for (width, height, fps, level) in levels.iter() {
+            assert_eq!(
+                DoviDecoderConfigurationRecord::level_from_stream(*width, *height, *fps),
+                Some(*level)
+            );
+        }
+
+        assert_eq!(
+            DoviDecoderConfigurationRecord::level_from_stream(7680, 4320, 240.0),
+            None
+        );
+    }
+}
@@ -5,8 +5,7 @@ use bitvec_helpers::{bitvec_reader::BitVecReader, bitvec_writer::BitVecWriter};
 use serde::{Deserialize, Serialize};
 
 use super::{level6::MAX_PQ_LUMINANCE, ExtMetadataBlock, ExtMetadataBlockInfo};
-
-pub const PRESET_TARGET_DISPLAYS: &[u8] = &[1, 16, 18, 21, 27, 28, 37, 38, 42, 48, 49];
+use crate::rpu::extension_metadata::target_display::TargetDisplay;
 
 /// Custom target display information
 #[repr(C)]
@@ -41,7 +40,7 @@ impl ExtMetadataBlockLevel10 {
     }
 
     pub fn validate(&self) -> Result<()> {
-        ensure!(!PRESET_TARGET_DISPLAYS.contains(&self.target_display_index));
+        ensure!(TargetDisplay::preset(self.target_display_index).is_none());
         ensure!(self.target_max_pq <= MAX_PQ_LUMINANCE);
         ensure!(self.target_min_pq <= MAX_PQ_LUMINANCE);
 
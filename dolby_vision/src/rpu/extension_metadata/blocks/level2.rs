@@ -6,7 +6,7 @@ use serde::{Deserialize, Serialize};
 
 use crate::utils::nits_to_pq;
 
-use super::{ExtMetadataBlock, ExtMetadataBlockInfo, MAX_12_BIT_VALUE};
+use super::{ExtMetadataBlock, ExtMetadataBlockInfo, ExtMetadataBlockLevel8, MAX_12_BIT_VALUE};
 
 /// Creative intent trim passes per target display peak brightness
 #[repr(C)]
@@ -73,6 +73,49 @@ impl ExtMetadataBlockLevel2 {
             ..Default::default()
         }
     }
+
+    /// Converts a CM v4.0 L8 trim to a L2 trim for the target display peak brightness
+    pub fn from_level8(level8: &ExtMetadataBlockLevel8, target_max_pq: u16) -> Self {
+        Self {
+            target_max_pq,
+            trim_slope: level8.trim_slope,
+            trim_offset: level8.trim_offset,
+            trim_power: level8.trim_power,
+            trim_chroma_weight: level8.trim_chroma_weight,
+            trim_saturation_gain: level8.trim_saturation_gain,
+            ms_weight: level8.ms_weight as i16,
+        }
+    }
+
+    /// Linearly interpolates the trims of two L2 blocks in the PQ domain, for the target PQ.
+    /// A negative `ms_weight` is only kept when both trims have it.
+    pub fn interpolate(lower: &Self, upper: &Self, target_max_pq: u16) -> Self {
+        let range = upper.target_max_pq as f64 - lower.target_max_pq as f64;
+        let t = if range > 0.0 {
+            ((target_max_pq as f64 - lower.target_max_pq as f64) / range).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+
+        let lerp = |a: u16, b: u16| (a as f64 + (b as f64 - a as f64) * t).round() as u16;
+
+        let ms_weight = if lower.ms_weight < 0 && upper.ms_weight < 0 {
+            -1
+        } else {
+            let ms_weight = |v: i16| if v < 0 { 2048 } else { v as u16 };
+            lerp(ms_weight(lower.ms_weight), ms_weight(upper.ms_weight)) as i16
+        };
+
+        Self {
+            target_max_pq,
+            trim_slope: lerp(lower.trim_slope, upper.trim_slope),
+            trim_offset: lerp(lower.trim_offset, upper.trim_offset),
+            trim_power: lerp(lower.trim_power, upper.trim_power),
+            trim_chroma_weight: lerp(lower.trim_chroma_weight, upper.trim_chroma_weight),
+            trim_saturation_gain: lerp(lower.trim_saturation_gain, upper.trim_saturation_gain),
+            ms_weight,
+        }
+    }
 }
 
 impl ExtMetadataBlockInfo for ExtMetadataBlockLevel2 {
@@ -4,7 +4,7 @@ use bitvec_helpers::{bitvec_reader::BitVecReader, bitvec_writer::BitVecWriter};
 #[cfg(feature = "serde_feature")]
 use serde::{Deserialize, Serialize};
 
-use super::{ExtMetadataBlock, ExtMetadataBlockInfo, MAX_12_BIT_VALUE};
+use super::{ExtMetadataBlock, ExtMetadataBlockInfo, ExtMetadataBlockLevel2, MAX_12_BIT_VALUE};
 
 /// Creative intent trim passes per target display peak brightness
 /// For CM v4.0, L8 metadata only is present and used to compute L2
@@ -58,6 +58,26 @@ impl ExtMetadataBlockLevel8 {
 
         Ok(())
     }
+
+    /// Converts a CM v2.9 L2 trim to a L8 trim for the specified target display.
+    /// The trims use the same representation, except for the unset (-1) L2 `ms_weight`.
+    pub fn from_level2(level2: &ExtMetadataBlockLevel2, target_display_index: u8) -> Self {
+        let ms_weight = if level2.ms_weight < 0 {
+            2048
+        } else {
+            level2.ms_weight as u16
+        };
+
+        Self {
+            target_display_index,
+            trim_slope: level2.trim_slope,
+            trim_offset: level2.trim_offset,
+            trim_power: level2.trim_power,
+            trim_chroma_weight: level2.trim_chroma_weight,
+            trim_saturation_gain: level2.trim_saturation_gain,
+            ms_weight,
+        }
+    }
 }
 
 impl ExtMetadataBlockInfo for ExtMetadataBlockLevel8 {
@@ -26,6 +26,13 @@ impl ExtMetadataBlockLevel9 {
 
         Ok(())
     }
+
+    /// DCI-P3 D65 mastering display primaries
+    pub fn default_dci_p3() -> Self {
+        Self {
+            source_primary_index: 0,
+        }
+    }
 }
 
 impl ExtMetadataBlockInfo for ExtMetadataBlockLevel9 {
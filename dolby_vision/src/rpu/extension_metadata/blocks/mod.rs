@@ -169,6 +169,24 @@ impl ExtMetadataBlock {
         }
     }
 
+    /// Validates the block values, for the levels with restricted values
+    pub fn validate(&self) -> Result<()> {
+        match self {
+            ExtMetadataBlock::Level1(b) => b.validate(),
+            ExtMetadataBlock::Level2(b) => b.validate(),
+            ExtMetadataBlock::Level3(b) => b.validate(),
+            ExtMetadataBlock::Level4(b) => b.validate(),
+            ExtMetadataBlock::Level5(b) => b.validate(),
+            ExtMetadataBlock::Level6(b) => b.validate(),
+            ExtMetadataBlock::Level8(b) => b.validate(),
+            ExtMetadataBlock::Level10(b) => b.validate(),
+            ExtMetadataBlock::Level11(b) => b.validate(),
+            ExtMetadataBlock::Level9(_)
+            | ExtMetadataBlock::Level254(_)
+            | ExtMetadataBlock::Reserved(_) => Ok(()),
+        }
+    }
+
     pub fn validate_correct_dm_data<T: WithExtMetadataBlocks>(&self) -> Result<()> {
         let level = self.level();
 
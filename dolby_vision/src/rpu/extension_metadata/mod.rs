@@ -7,9 +7,11 @@ use serde::{Deserialize, Serialize};
 pub mod blocks;
 pub mod cmv29;
 pub mod cmv40;
+pub mod target_display;
 
 pub use cmv29::CmV29DmData;
 pub use cmv40::CmV40DmData;
+pub use target_display::{TargetDisplay, TargetTrim};
 
 use blocks::ExtMetadataBlock;
 
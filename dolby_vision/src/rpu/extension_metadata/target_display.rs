@@ -0,0 +1,288 @@
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{bail, format_err, Result};
+
+#[cfg(feature = "serde_feature")]
+use serde::{Deserialize, Serialize};
+
+use super::blocks::ExtMetadataBlockLevel10;
+use crate::utils::{nits_f64_to_pq, pq_to_nits};
+
+/// Predefined target displays, by target display index.
+///
+/// These targets do not require a L10 block to be used in CM v4.0 L8 trims.
+pub const PRESET_TARGET_DISPLAY_LIST: &[(u8, TargetDisplay)] = &[
+    (1, TargetDisplay::sdr(100, 0.005)),
+    (16, TargetDisplay::pq(108, TargetPrimaries::DciP3D65)),
+    (18, TargetDisplay::pq(300, TargetPrimaries::DciP3D65)),
+    (21, TargetDisplay::pq(500, TargetPrimaries::DciP3D65)),
+    (27, TargetDisplay::pq(600, TargetPrimaries::DciP3D65)),
+    (28, TargetDisplay::pq(1000, TargetPrimaries::Bt2020)),
+    (37, TargetDisplay::pq(2000, TargetPrimaries::DciP3D65)),
+    (38, TargetDisplay::pq(2000, TargetPrimaries::Bt2020)),
+    (42, TargetDisplay::pq(4000, TargetPrimaries::DciP3D65)),
+    (48, TargetDisplay::pq(1000, TargetPrimaries::DciP3D65)),
+    (49, TargetDisplay::pq(4000, TargetPrimaries::Bt2020)),
+];
+
+/// Target display primaries, as signalled in L10 `target_primary_index`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TargetPrimaries {
+    DciP3D65,
+    Bt709,
+    Bt2020,
+}
+
+/// Target display transfer function
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TargetEotf {
+    Bt1886,
+    Pq,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TargetDisplay {
+    pub peak_nits: u16,
+    pub min_nits: f64,
+    pub primaries: TargetPrimaries,
+    pub eotf: TargetEotf,
+}
+
+/// Trim values for a target display described by name, e.g. `1000-nit P3 PQ`.
+///
+/// Converted to a L2 trim, and a L8 trim for CM v4.0.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde_feature", derive(Deserialize, Serialize))]
+pub struct TargetTrim {
+    pub target: String,
+
+    #[cfg_attr(feature = "serde_feature", serde(default = "TargetTrim::default_trim"))]
+    pub trim_slope: u16,
+
+    #[cfg_attr(feature = "serde_feature", serde(default = "TargetTrim::default_trim"))]
+    pub trim_offset: u16,
+
+    #[cfg_attr(feature = "serde_feature", serde(default = "TargetTrim::default_trim"))]
+    pub trim_power: u16,
+
+    #[cfg_attr(feature = "serde_feature", serde(default = "TargetTrim::default_trim"))]
+    pub trim_chroma_weight: u16,
+
+    #[cfg_attr(feature = "serde_feature", serde(default = "TargetTrim::default_trim"))]
+    pub trim_saturation_gain: u16,
+
+    #[cfg_attr(feature = "serde_feature", serde(default = "TargetTrim::default_trim"))]
+    pub ms_weight: u16,
+}
+
+impl TargetPrimaries {
+    pub fn from_index(index: u8) -> Option<Self> {
+        match index {
+            0 => Some(Self::DciP3D65),
+            1 => Some(Self::Bt709),
+            2 => Some(Self::Bt2020),
+            _ => None,
+        }
+    }
+
+    pub fn index(&self) -> u8 {
+        match self {
+            Self::DciP3D65 => 0,
+            Self::Bt709 => 1,
+            Self::Bt2020 => 2,
+        }
+    }
+}
+
+impl TargetDisplay {
+    const fn sdr(peak_nits: u16, min_nits: f64) -> Self {
+        Self {
+            peak_nits,
+            min_nits,
+            primaries: TargetPrimaries::Bt709,
+            eotf: TargetEotf::Bt1886,
+        }
+    }
+
+    const fn pq(peak_nits: u16, primaries: TargetPrimaries) -> Self {
+        Self {
+            peak_nits,
+            min_nits: 0.0,
+            primaries,
+            eotf: TargetEotf::Pq,
+        }
+    }
+
+    /// Predefined target display for the index, if any
+    pub fn preset(target_display_index: u8) -> Option<Self> {
+        PRESET_TARGET_DISPLAY_LIST
+            .iter()
+            .find(|(index, _)| *index == target_display_index)
+            .map(|(_, display)| *display)
+    }
+
+    /// Index of the predefined target display matching exactly, if any
+    pub fn preset_index(&self) -> Option<u8> {
+        PRESET_TARGET_DISPLAY_LIST
+            .iter()
+            .find(|(_, display)| {
+                display.peak_nits == self.peak_nits
+                    && display.primaries == self.primaries
+                    && display.eotf == self.eotf
+            })
+            .map(|(index, _)| *index)
+    }
+
+    /// Index of the predefined target display for a L2 target PQ, if any.
+    /// P3 D65 targets are preferred over other primaries with the same peak brightness.
+    pub fn preset_index_for_max_pq(target_max_pq: u16) -> Option<u8> {
+        let mut candidates = PRESET_TARGET_DISPLAY_LIST
+            .iter()
+            .filter(|(_, display)| (display.max_pq() as i32 - target_max_pq as i32).abs() <= 1);
+
+        let first = candidates.clone().next();
+
+        candidates
+            .find(|(_, display)| display.primaries == TargetPrimaries::DciP3D65)
+            .or(first)
+            .map(|(index, _)| *index)
+    }
+
+    /// Custom target display described by a L10 block
+    pub fn from_level10(level10: &ExtMetadataBlockLevel10) -> Self {
+        let min_nits = pq_to_nits(level10.target_min_pq as f64 / 4095.0);
+
+        Self {
+            peak_nits: Self::peak_nits_from_max_pq(level10.target_max_pq),
+            min_nits: (min_nits * 10000.0).round() / 10000.0,
+            primaries: TargetPrimaries::from_index(level10.target_primary_index)
+                .unwrap_or(TargetPrimaries::DciP3D65),
+            eotf: TargetEotf::Pq,
+        }
+    }
+
+    /// Peak brightness in nits for a 12-bit PQ value.
+    /// Round values are preferred when they are encoded to the same PQ value.
+    pub fn peak_nits_from_max_pq(max_pq: u16) -> u16 {
+        let nits = pq_to_nits(max_pq as f64 / 4095.0);
+
+        [100.0, 10.0, 1.0]
+            .iter()
+            .map(|step| ((nits / step).round() * step) as u16)
+            .find(|n| (nits_f64_to_pq(*n as f64) * 4095.0).round() as u16 == max_pq)
+            .unwrap_or_else(|| nits.round() as u16)
+    }
+
+    /// L10 block describing this target display
+    pub fn to_level10(&self, target_display_index: u8) -> ExtMetadataBlockLevel10 {
+        ExtMetadataBlockLevel10 {
+            target_display_index,
+            target_max_pq: self.max_pq(),
+            target_min_pq: self.min_pq(),
+            target_primary_index: self.primaries.index(),
+        }
+    }
+
+    /// Peak brightness as 12-bit PQ
+    pub fn max_pq(&self) -> u16 {
+        (nits_f64_to_pq(self.peak_nits as f64) * 4095.0).round() as u16
+    }
+
+    /// Minimum brightness as 12-bit PQ
+    pub fn min_pq(&self) -> u16 {
+        (nits_f64_to_pq(self.min_nits) * 4095.0).round() as u16
+    }
+}
+
+impl fmt::Display for TargetDisplay {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let primaries = match self.primaries {
+            TargetPrimaries::DciP3D65 => "P3, D65",
+            TargetPrimaries::Bt709 => "BT.709",
+            TargetPrimaries::Bt2020 => "BT.2020",
+        };
+
+        let eotf = match self.eotf {
+            TargetEotf::Bt1886 => "BT.1886",
+            TargetEotf::Pq => "ST.2084",
+        };
+
+        write!(f, "{}-nit, {}, {}, Full", self.peak_nits, primaries, eotf)
+    }
+}
+
+/// Parses target displays such as `1000-nit P3 PQ` or `100-nit, BT.709, BT.1886, Full`.
+///
+/// The primaries default to P3 D65 and the EOTF to PQ, except for BT.709 which defaults to BT.1886.
+impl FromStr for TargetDisplay {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut peak_nits = None;
+        let mut primaries = None;
+        let mut eotf = None;
+
+        for part in s
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|e| !e.is_empty())
+        {
+            let part = part.to_lowercase();
+
+            if let Some(nits) = ["-nits", "-nit", "nits", "nit"]
+                .iter()
+                .find_map(|suffix| part.strip_suffix(suffix))
+            {
+                peak_nits = Some(
+                    nits.parse::<u16>()
+                        .map_err(|_| format_err!("Invalid target peak brightness: {}", part))?,
+                );
+                continue;
+            }
+
+            match part.as_str() {
+                "p3" | "dci-p3" | "p3-d65" => primaries = Some(TargetPrimaries::DciP3D65),
+                "bt.709" | "bt709" | "709" => primaries = Some(TargetPrimaries::Bt709),
+                "bt.2020" | "bt2020" | "2020" => primaries = Some(TargetPrimaries::Bt2020),
+                "pq" | "st.2084" | "st2084" => eotf = Some(TargetEotf::Pq),
+                "bt.1886" | "bt1886" | "gamma" | "sdr" => eotf = Some(TargetEotf::Bt1886),
+                "d65" | "full" => (),
+                _ => bail!("Unknown target display property '{}' in '{}'", part, s),
+            }
+        }
+
+        let peak_nits =
+            peak_nits.ok_or_else(|| format_err!("Missing target peak brightness in '{}'", s))?;
+        let primaries = primaries.unwrap_or(TargetPrimaries::DciP3D65);
+        let eotf = eotf.unwrap_or(if primaries == TargetPrimaries::Bt709 {
+            TargetEotf::Bt1886
+        } else {
+            TargetEotf::Pq
+        });
+
+        // Use the predefined minimum brightness when the target is a preset
+        let min_nits = PRESET_TARGET_DISPLAY_LIST
+            .iter()
+            .map(|(_, display)| display)
+            .find(|d| d.peak_nits == peak_nits && d.primaries == primaries && d.eotf == eotf)
+            .map_or(0.0, |d| d.min_nits);
+
+        Ok(Self {
+            peak_nits,
+            min_nits,
+            primaries,
+            eotf,
+        })
+    }
+}
+
+impl TargetTrim {
+    #[cfg(feature = "serde_feature")]
+    fn default_trim() -> u16 {
+        2048
+    }
+
+    pub fn target_display(&self) -> Result<TargetDisplay> {
+        self.target.parse()
+    }
+}
@@ -11,7 +11,10 @@ use serde::{Deserialize, Serialize};
 
 use crate::rpu::dovi_rpu::DoviRpu;
 
-use super::{extension_metadata::blocks, vdr_dm_data::CmVersion};
+use super::{
+    extension_metadata::{blocks, TargetTrim},
+    vdr_dm_data::CmVersion,
+};
 use blocks::*;
 
 const OUT_NAL_HEADER: &[u8] = &[0, 0, 0, 1];
@@ -41,6 +44,13 @@ pub struct GenerateConfig {
 
     /// Defaults to 1000,0.0001
     pub level6: ExtMetadataBlockLevel6,
+
+    /// Source primaries, CM v4.0 only
+    #[cfg_attr(
+        feature = "serde_feature",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub level9: Option<ExtMetadataBlockLevel9>,
 }
 
 #[derive(Default, Debug, Clone)]
@@ -55,6 +65,10 @@ pub struct VideoShot {
     #[cfg_attr(feature = "serde_feature", serde(default))]
     pub metadata_blocks: Vec<ExtMetadataBlock>,
 
+    /// Trims by target display description, applied after the metadata blocks
+    #[cfg_attr(feature = "serde_feature", serde(default))]
+    pub target_trims: Vec<TargetTrim>,
+
     #[cfg_attr(feature = "serde_feature", serde(default))]
     pub frame_edits: Vec<ShotFrameEdit>,
 }
@@ -64,6 +78,9 @@ pub struct VideoShot {
 pub struct ShotFrameEdit {
     pub edit_offset: usize,
     pub metadata_blocks: Vec<ExtMetadataBlock>,
+
+    #[cfg_attr(feature = "serde_feature", serde(default))]
+    pub target_trims: Vec<TargetTrim>,
 }
 
 impl GenerateConfig {
@@ -87,6 +104,10 @@ impl GenerateConfig {
                         vdr_dm_data.replace_metadata_block(block.clone())?;
                     }
 
+                    for trim in &shot.target_trims {
+                        vdr_dm_data.set_target_trim(trim)?;
+                    }
+
                     let frame_edit = shot.frame_edits.iter().find(|e| e.edit_offset == i);
 
                     // Set different metadata for this frame
@@ -94,6 +115,10 @@ impl GenerateConfig {
                         for block in &edit.metadata_blocks {
                             vdr_dm_data.replace_metadata_block(block.clone())?;
                         }
+
+                        for trim in &edit.target_trims {
+                            vdr_dm_data.set_target_trim(trim)?;
+                        }
                     }
                 }
 
@@ -104,7 +129,7 @@ impl GenerateConfig {
         Ok(list)
     }
 
-    pub fn encode_option_rpus(rpus: &mut Vec<Option<DoviRpu>>) -> Vec<Vec<u8>> {
+    pub fn encode_option_rpus(rpus: &mut [Option<DoviRpu>]) -> Vec<Vec<u8>> {
         let encoded_rpus = rpus
             .iter_mut()
             .filter_map(|e| e.as_mut())
@@ -115,7 +140,7 @@ impl GenerateConfig {
         encoded_rpus
     }
 
-    pub fn encode_rpus(rpus: &mut Vec<DoviRpu>) -> Vec<Vec<u8>> {
+    pub fn encode_rpus(rpus: &mut [DoviRpu]) -> Vec<Vec<u8>> {
         let encoded_rpus = rpus
             .iter_mut()
             .map(|e| e.write_hevc_unspec62_nalu())
@@ -162,11 +187,13 @@ impl Default for GenerateConfig {
                 max_content_light_level: 0,
                 max_frame_average_light_level: 0,
             },
+            level9: None,
         }
     }
 }
 
 #[cfg(all(test, feature = "xml"))]
+#[allow(clippy::bool_assert_comparison)]
 mod tests {
     use anyhow::Result;
     use std::path::PathBuf;
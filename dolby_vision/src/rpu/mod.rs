@@ -1,3 +1,4 @@
+pub mod configuration_record;
 pub mod dovi_rpu;
 pub mod extension_metadata;
 pub mod generate;
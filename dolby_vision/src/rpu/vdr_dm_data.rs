@@ -1,4 +1,4 @@
-use anyhow::{bail, ensure, Result};
+use anyhow::{bail, ensure, format_err, Result};
 use bitvec_helpers::{bitvec_reader::BitVecReader, bitvec_writer::BitVecWriter};
 
 #[cfg(feature = "serde_feature")]
@@ -6,8 +6,10 @@ use serde::{Deserialize, Serialize};
 
 use super::dovi_rpu::DoviRpu;
 use super::extension_metadata::blocks::{
-    ExtMetadataBlock, ExtMetadataBlockLevel11, ExtMetadataBlockLevel2,
+    ExtMetadataBlock, ExtMetadataBlockLevel10, ExtMetadataBlockLevel11, ExtMetadataBlockLevel2,
+    ExtMetadataBlockLevel8, ExtMetadataBlockLevel9,
 };
+use super::extension_metadata::target_display::{TargetDisplay, TargetPrimaries};
 use super::extension_metadata::*;
 use super::generate::GenerateConfig;
 use super::profiles::profile81::Profile81;
@@ -421,6 +423,332 @@ impl VdrDmData {
         }
     }
 
+    /// Upgrades CM v2.9 metadata to CM v4.0.
+    ///
+    /// Every L2 trim is converted to a L8 trim, using a predefined target display when possible.
+    /// Otherwise, a L10 custom target display is added for the trim's peak brightness.
+    /// Existing L8 trims are kept, and L9 is only added when missing.
+    pub fn convert_to_cmv40(&mut self, source_primary_index: u8) -> Result<()> {
+        ensure!(
+            self.cmv29_metadata.is_some(),
+            "Did not find CM v2.9 DM data"
+        );
+
+        if self.cmv40_metadata.is_none() {
+            self.cmv40_metadata = Some(DmData::V40(CmV40DmData::new_with_l254()));
+        }
+
+        let level2_blocks: Vec<ExtMetadataBlockLevel2> = self
+            .level_blocks_iter(2)
+            .filter_map(|b| match b {
+                ExtMetadataBlock::Level2(b) => Some(b.clone()),
+                _ => None,
+            })
+            .collect();
+
+        for level2 in &level2_blocks {
+            let target_display_index =
+                self.target_display_index_for_max_pq(level2.target_max_pq)?;
+
+            let has_trim = self.level_blocks_iter(8).any(|b| match b {
+                ExtMetadataBlock::Level8(b) => b.target_display_index == target_display_index,
+                _ => false,
+            });
+
+            if !has_trim {
+                self.replace_metadata_block(ExtMetadataBlock::Level8(
+                    ExtMetadataBlockLevel8::from_level2(level2, target_display_index),
+                ))?;
+            }
+        }
+
+        if self.get_block(9).is_none() {
+            self.add_metadata_block(ExtMetadataBlock::Level9(ExtMetadataBlockLevel9 {
+                source_primary_index,
+            }))?;
+        }
+
+        if let Some(cmv40) = &self.cmv40_metadata {
+            cmv40.validate()?;
+        }
+
+        Ok(())
+    }
+
+    /// Downgrades CM v4.0 metadata to CM v2.9 only.
+    ///
+    /// Every L8 trim is converted to a L2 trim, replacing the existing L2 trim for the same target.
+    /// The target display is resolved from the predefined targets or the L10 custom targets.
+    /// Fails on trims for unknown target displays. The CM v4.0 DM data is dropped.
+    pub fn convert_to_cmv29(&mut self) -> Result<()> {
+        ensure!(
+            self.cmv29_metadata.is_some(),
+            "Did not find CM v2.9 DM data"
+        );
+
+        let level2_blocks = self
+            .level_blocks_iter(8)
+            .filter_map(|b| match b {
+                ExtMetadataBlock::Level8(b) => Some(b),
+                _ => None,
+            })
+            .map(|level8| {
+                let target_display_index = level8.target_display_index;
+
+                match self.target_display_max_pq(target_display_index) {
+                    Some(target_max_pq) => {
+                        Ok(ExtMetadataBlockLevel2::from_level8(level8, target_max_pq))
+                    }
+                    None => bail!(
+                        "Cannot convert L8 trim: unknown target display index {}",
+                        target_display_index
+                    ),
+                }
+            })
+            .collect::<Result<Vec<ExtMetadataBlockLevel2>>>()?;
+
+        self.cmv40_metadata = None;
+
+        for level2 in level2_blocks {
+            self.replace_metadata_block(ExtMetadataBlock::Level2(level2))?;
+        }
+
+        if let Some(cmv29) = &self.cmv29_metadata {
+            cmv29.validate()?;
+        }
+
+        Ok(())
+    }
+
+    /// Peak brightness of a target display, from the predefined or L10 custom targets
+    pub fn target_display_max_pq(&self, target_display_index: u8) -> Option<u16> {
+        TargetDisplay::preset(target_display_index)
+            .map(|display| display.max_pq())
+            .or_else(|| {
+                self.custom_target_display(target_display_index)
+                    .map(|level10| level10.target_max_pq)
+            })
+    }
+
+    /// Target display for the index, from the predefined or L10 custom targets
+    pub fn target_display(&self, target_display_index: u8) -> Option<TargetDisplay> {
+        TargetDisplay::preset(target_display_index).or_else(|| {
+            self.custom_target_display(target_display_index)
+                .map(TargetDisplay::from_level10)
+        })
+    }
+
+    fn custom_target_display(&self, target_display_index: u8) -> Option<&ExtMetadataBlockLevel10> {
+        self.level_blocks_iter(10).find_map(|b| match b {
+            ExtMetadataBlock::Level10(b) if b.target_display_index == target_display_index => {
+                Some(b)
+            }
+            _ => None,
+        })
+    }
+
+    /// Sets the trims for a target display described by name.
+    ///
+    /// A L2 trim is set for the target's peak brightness.
+    /// With CM v4.0, a L8 trim is also set, adding a L10 block if the target is not predefined.
+    /// Fails on trim values out of the L2 range, which is also valid for L8.
+    pub fn set_target_trim(&mut self, trim: &TargetTrim) -> Result<()> {
+        let display = trim.target_display()?;
+
+        let level2 = ExtMetadataBlockLevel2 {
+            target_max_pq: display.max_pq(),
+            trim_slope: trim.trim_slope,
+            trim_offset: trim.trim_offset,
+            trim_power: trim.trim_power,
+            trim_chroma_weight: trim.trim_chroma_weight,
+            trim_saturation_gain: trim.trim_saturation_gain,
+            // Out of range values are rejected by the validation, instead of wrapping
+            ms_weight: trim.ms_weight.min(i16::MAX as u16) as i16,
+        };
+
+        let level2_block = ExtMetadataBlock::Level2(level2.clone());
+        level2_block
+            .validate()
+            .map_err(|e| format_err!("Invalid trim for target '{}': {}", trim.target, e))?;
+
+        self.replace_metadata_block(level2_block)?;
+
+        if self.cmv40_metadata.is_some() {
+            let target_display_index = match display.preset_index() {
+                Some(index) => index,
+                None => self.custom_target_display_index(display.to_level10(0))?,
+            };
+
+            self.replace_metadata_block(ExtMetadataBlock::Level8(
+                ExtMetadataBlockLevel8::from_level2(&level2, target_display_index),
+            ))?;
+        }
+
+        Ok(())
+    }
+
+    /// Trims present in the metadata, as L2 blocks sorted by target PQ.
+    /// With CM v4.0, the L8 trims take precedence over L2 trims for the same target.
+    pub fn trims_by_target_pq(&self) -> Vec<ExtMetadataBlockLevel2> {
+        let mut trims: Vec<ExtMetadataBlockLevel2> = self
+            .level_blocks_iter(8)
+            .filter_map(|b| match b {
+                ExtMetadataBlock::Level8(b) => Some(b),
+                _ => None,
+            })
+            .filter_map(|level8| {
+                self.target_display_max_pq(level8.target_display_index)
+                    .map(|target_max_pq| ExtMetadataBlockLevel2::from_level8(level8, target_max_pq))
+            })
+            .collect();
+
+        let level2_trims: Vec<ExtMetadataBlockLevel2> = self
+            .level_blocks_iter(2)
+            .filter_map(|b| match b {
+                ExtMetadataBlock::Level2(b) => Some(b.clone()),
+                _ => None,
+            })
+            .filter(|level2| {
+                !trims
+                    .iter()
+                    .any(|b| (b.target_max_pq as i32 - level2.target_max_pq as i32).abs() <= 1)
+            })
+            .collect();
+
+        trims.extend(level2_trims);
+        trims.sort_by_key(|b| b.target_max_pq);
+
+        trims
+    }
+
+    /// Derives the trim for any target PQ from the trims present, interpolating in the PQ domain.
+    ///
+    /// Between two trims, the values are interpolated linearly.
+    /// Above the highest trim, the values go towards neutral trims at the source max PQ.
+    /// Below the lowest trim, the lowest trim is used.
+    pub fn interpolated_trim(&self, target_max_pq: u16) -> ExtMetadataBlockLevel2 {
+        let mut trims = self.trims_by_target_pq();
+
+        let neutral = ExtMetadataBlockLevel2 {
+            target_max_pq: self.source_max_pq,
+            ..Default::default()
+        };
+
+        if trims
+            .last()
+            .map_or(true, |b| b.target_max_pq < neutral.target_max_pq)
+        {
+            trims.push(neutral);
+        }
+
+        let upper_idx = trims.iter().position(|b| b.target_max_pq >= target_max_pq);
+
+        let (lower, upper) = match upper_idx {
+            Some(0) => (&trims[0], &trims[0]),
+            Some(i) => (&trims[i - 1], &trims[i]),
+            None => {
+                let last = &trims[trims.len() - 1];
+                (last, last)
+            }
+        };
+
+        ExtMetadataBlockLevel2::interpolate(lower, upper, target_max_pq)
+    }
+
+    /// Adds an interpolated trim for the target PQ, if no trim exists for it already.
+    ///
+    /// A L2 trim is added, as well as a L8 trim for CM v4.0.
+    /// Returns whether a trim was added.
+    pub fn add_interpolated_trim(&mut self, target_max_pq: u16) -> Result<bool> {
+        let matches_target = |pq: u16| (pq as i32 - target_max_pq as i32).abs() <= 1;
+
+        let has_level2 = self.level_blocks_iter(2).any(|b| match b {
+            ExtMetadataBlock::Level2(b) => matches_target(b.target_max_pq),
+            _ => false,
+        });
+
+        let has_level8 = self.level_blocks_iter(8).any(|b| match b {
+            ExtMetadataBlock::Level8(b) => self
+                .target_display_max_pq(b.target_display_index)
+                .map_or(false, matches_target),
+            _ => false,
+        });
+
+        let add_level2 = self.cmv29_metadata.is_some() && !has_level2;
+        let add_level8 = self.cmv40_metadata.is_some() && !has_level8;
+
+        if !add_level2 && !add_level8 {
+            return Ok(false);
+        }
+
+        let level2 = self.interpolated_trim(target_max_pq);
+
+        if add_level2 {
+            self.replace_metadata_block(ExtMetadataBlock::Level2(level2.clone()))?;
+        }
+
+        if add_level8 {
+            let target_display_index = self.target_display_index_for_max_pq(target_max_pq)?;
+
+            self.replace_metadata_block(ExtMetadataBlock::Level8(
+                ExtMetadataBlockLevel8::from_level2(&level2, target_display_index),
+            ))?;
+        }
+
+        Ok(true)
+    }
+
+    /// Returns the index of the target display for a L2 target PQ.
+    /// Without a matching predefined target, a custom P3 D65 PQ target is used.
+    fn target_display_index_for_max_pq(&mut self, target_max_pq: u16) -> Result<u8> {
+        match TargetDisplay::preset_index_for_max_pq(target_max_pq) {
+            Some(index) => Ok(index),
+            None => self.custom_target_display_index(ExtMetadataBlockLevel10 {
+                target_display_index: 0,
+                target_max_pq,
+                target_min_pq: 0,
+                target_primary_index: TargetPrimaries::DciP3D65.index(),
+            }),
+        }
+    }
+
+    /// Returns the index of the L10 custom target display matching the target.
+    /// The L10 block is created if it doesn't exist.
+    fn custom_target_display_index(&mut self, target: ExtMetadataBlockLevel10) -> Result<u8> {
+        let mut used_indices = Vec::new();
+
+        for block in self.level_blocks_iter(10) {
+            if let ExtMetadataBlock::Level10(b) = block {
+                if b.target_max_pq == target.target_max_pq
+                    && b.target_min_pq == target.target_min_pq
+                    && b.target_primary_index == target.target_primary_index
+                {
+                    return Ok(b.target_display_index);
+                }
+
+                used_indices.push(b.target_display_index);
+            }
+        }
+
+        for block in self.level_blocks_iter(8) {
+            if let ExtMetadataBlock::Level8(b) = block {
+                used_indices.push(b.target_display_index);
+            }
+        }
+
+        let target_display_index = (1..=u8::MAX)
+            .rev()
+            .find(|i| TargetDisplay::preset(*i).is_none() && !used_indices.contains(i))
+            .ok_or_else(|| format_err!("No custom target display index available"))?;
+
+        self.replace_metadata_block(ExtMetadataBlock::Level10(ExtMetadataBlockLevel10 {
+            target_display_index,
+            ..target
+        }))?;
+
+        Ok(target_display_index)
+    }
+
     pub fn set_scene_cut(&mut self, is_scene_cut: bool) {
         self.scene_refresh_flag = is_scene_cut as u64;
     }
@@ -435,7 +763,7 @@ impl VdrDmData {
         }
     }
 
-    /// Sets static metadata (L5/L6/L11) and source levels
+    /// Sets static metadata (L5/L6/L9/L11) and source levels
     pub fn from_config(config: &GenerateConfig) -> Result<VdrDmData> {
         let mut vdr_dm_data = Profile81::dm_data();
 
@@ -465,6 +793,11 @@ impl VdrDmData {
     pub fn set_static_metadata(&mut self, config: &GenerateConfig) -> Result<()> {
         self.replace_metadata_block(ExtMetadataBlock::Level5(config.level5.clone()))?;
         self.replace_metadata_block(ExtMetadataBlock::Level6(config.level6.clone()))?;
+
+        if let (Some(level9), Some(_)) = (&config.level9, &self.cmv40_metadata) {
+            self.replace_metadata_block(ExtMetadataBlock::Level9(level9.clone()))?;
+        }
+
         self.replace_metadata_block(ExtMetadataBlock::Level11(
             ExtMetadataBlockLevel11::default_reference_cinema(),
         ))?;
@@ -14,12 +14,27 @@ pub const ST2084_C3: f64 = (2392.0 / 4096.0) * 32.0;
 /// Helper function to calculate PQ codes from nits (cd/m2) values
 #[inline(always)]
 pub fn nits_to_pq(nits: u16) -> f64 {
-    let y = nits as f64 / ST2084_Y_MAX;
+    nits_f64_to_pq(nits as f64)
+}
+
+/// Helper function to calculate PQ codes from fractional nits (cd/m2) values
+#[inline(always)]
+pub fn nits_f64_to_pq(nits: f64) -> f64 {
+    let y = nits / ST2084_Y_MAX;
 
     ((ST2084_C1 + ST2084_C2 * y.powf(ST2084_M1)) / (1.0 + ST2084_C3 * y.powf(ST2084_M1)))
         .powf(ST2084_M2)
 }
 
+/// Helper function to calculate nits (cd/m2) values from PQ codes
+#[inline(always)]
+pub fn pq_to_nits(pq: f64) -> f64 {
+    let v = pq.powf(1.0 / ST2084_M2);
+    let y = ((v - ST2084_C1).max(0.0) / (ST2084_C2 - ST2084_C3 * v)).powf(1.0 / ST2084_M1);
+
+    y * ST2084_Y_MAX
+}
+
 /// Serializing a bitvec as a vec of bits
 #[cfg(feature = "serde_feature")]
 pub fn bitvec_ser_bits<S: Serializer>(bitvec: &BitVec<Msb0, u8>, s: S) -> Result<S::Ok, S::Error> {
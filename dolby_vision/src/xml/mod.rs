@@ -2,6 +2,7 @@
 mod parser;
 
 #[cfg(test)]
+#[allow(clippy::needless_borrow)]
 mod tests;
 
 pub use parser::{CmXmlParser, XmlParserOpts};
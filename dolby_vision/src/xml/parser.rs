@@ -277,6 +277,7 @@ impl CmXmlParser {
                     shot.frame_edits.push(ShotFrameEdit {
                         edit_offset,
                         metadata_blocks: self.parse_shot_trims(&frame)?,
+                        ..Default::default()
                     });
                 }
 
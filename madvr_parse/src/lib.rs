@@ -6,10 +6,13 @@ use std::path::Path;
 use byteorder::{ReadBytesExt, WriteBytesExt, LE};
 
 mod utils;
-use utils::nits_to_pq;
+use utils::{nits_to_pq, pq_to_nits};
 
 pub const MAGIC_CODE: &str = "mvr+";
 
+/// Latest known file layout
+pub const LATEST_VERSION: u32 = 6;
+
 #[derive(Debug, Default)]
 pub struct MadVRMeasurements {
     pub header: MadVRHeader,
@@ -91,7 +94,23 @@ impl MadVRMeasurements {
         };
 
         measurements.scenes = MadVRScene::parse_scenes(&measurements.header, &mut reader)?;
-        measurements.frames = MadVRFrame::parse_frames(&measurements.header, &mut reader)?;
+
+        let frame_count = if measurements.is_complete() {
+            measurements.header.frame_count as usize
+        } else {
+            // Only the frames measured before the interruption
+            let remaining = data[4..].len() - (reader.position() as usize);
+            let frame_size = MadVRFrame::frame_size(&measurements.header);
+
+            (measurements.header.frame_count as usize).min(remaining / frame_size)
+        };
+
+        measurements.frames =
+            MadVRFrame::parse_frames(&measurements.header, frame_count, &mut reader)?;
+
+        if !measurements.is_complete() {
+            measurements.clip_scenes_to_frames();
+        }
 
         if measurements.header.flags == 3 {
             let remaining = data[4..].len() - (reader.position() as usize);
@@ -108,6 +127,63 @@ impl MadVRMeasurements {
         Ok(measurements)
     }
 
+    /// Incomplete files are from an interrupted measurement pass
+    pub fn is_complete(&self) -> bool {
+        self.header.flags != 0
+    }
+
+    /// Number of frames of the video not measured
+    pub fn missing_frames(&self) -> usize {
+        (self.header.frame_count as usize).saturating_sub(self.frames.len())
+    }
+
+    /// Keeps the scenes starting in the measured frames, ending at the last measured frame.
+    /// Scene ends and peaks not written yet are computed from the frames.
+    fn clip_scenes_to_frames(&mut self) {
+        let frame_count = self.frames.len() as u32;
+
+        if frame_count == 0 {
+            self.scenes.clear();
+            return;
+        }
+
+        // Scene starts must increase, unwritten entries are left as zero
+        let mut scenes: Vec<MadVRScene> = Vec::with_capacity(self.scenes.len());
+
+        for scene in self.scenes.drain(..) {
+            let is_next = scenes.last().map_or(true, |last| scene.start > last.start);
+
+            if is_next && scene.start < frame_count {
+                scenes.push(scene);
+            }
+        }
+
+        if scenes.is_empty() {
+            scenes.push(MadVRScene::default());
+        }
+
+        let next_starts: Vec<u32> = scenes.iter().skip(1).map(|s| s.start).collect();
+
+        for (i, s) in scenes.iter_mut().enumerate() {
+            let last_frame = next_starts.get(i).map_or(frame_count, |start| *start) - 1;
+
+            s.end = if s.length > 0 {
+                s.end.min(last_frame)
+            } else {
+                last_frame
+            };
+            s.length = (s.end - s.start + 1) as usize;
+
+            if s.peak_nits == 0 {
+                let frames = &self.frames[s.start as usize..=s.end as usize];
+                s.max_pq = frames.iter().map(|f| f.peak_pq_2020).fold(0.0, f64::max);
+                s.peak_nits = pq_to_nits(s.max_pq).round() as u32;
+            }
+        }
+
+        self.scenes = scenes;
+    }
+
     fn compute_max_scene_avg(&mut self) -> Result<()> {
         let frame_count = self.frames.len();
 
@@ -159,8 +235,6 @@ impl MadVRHeader {
             ..Default::default()
         };
 
-        ensure!(header.flags != 0, "incomplete measurement file");
-
         if header.version >= 5 {
             header.maxfall = reader.read_u32::<LE>()?;
             header.avgfall = reader.read_u32::<LE>()?;
@@ -170,11 +244,40 @@ impl MadVRHeader {
             }
         }
 
+        // Skip the fields of newer versions, assuming the latest known layout otherwise
+        if header.version > LATEST_VERSION {
+            let known_size = header.known_size();
+
+            ensure!(
+                header.header_size >= known_size,
+                "invalid header size {} for version {}",
+                header.header_size,
+                header.version
+            );
+
+            let mut unknown = vec![0; (header.header_size - known_size) as usize];
+            reader.read_exact(&mut unknown)?;
+        }
+
         Ok(header)
     }
 
+    /// Size of the known header fields after the magic code, in bytes
+    fn known_size(&self) -> u32 {
+        match self.version {
+            0..=4 => 6 * 4,
+            5 => 8 * 4,
+            _ => 9 * 4,
+        }
+    }
+
     fn write(&self, writer: &mut dyn Write) -> Result<()> {
         ensure!(self.flags != 0, "can only write complete measurement files");
+        ensure!(
+            self.version <= LATEST_VERSION,
+            "can only write measurement files up to version {}",
+            LATEST_VERSION
+        );
 
         writer.write_u32::<LE>(self.version)?;
         writer.write_u32::<LE>(self.header_size)?;
@@ -210,9 +313,13 @@ impl MadVRScene {
         }
 
         for s in scenes.iter_mut() {
-            s.end = reader.read_u32::<LE>()? - 1;
+            // Not written yet in incomplete files
+            let end = reader.read_u32::<LE>()?;
 
-            s.length = (s.end - s.start + 1) as usize;
+            if end > s.start {
+                s.end = end - 1;
+                s.length = (s.end - s.start + 1) as usize;
+            }
         }
 
         for s in scenes.iter_mut() {
@@ -259,13 +366,14 @@ impl MadVRScene {
 }
 
 impl MadVRFrame {
-    fn parse_frames(header: &MadVRHeader, reader: &mut dyn Read) -> Result<Vec<MadVRFrame>> {
-        let mut frames = Vec::new();
-
-        let sdr_peak_pq = nits_to_pq(100);
-        let hdr_peak_pq = 1.0;
+    fn parse_frames(
+        header: &MadVRHeader,
+        frame_count: usize,
+        reader: &mut dyn Read,
+    ) -> Result<Vec<MadVRFrame>> {
+        let mut frames = Vec::with_capacity(frame_count);
 
-        for _ in 0..header.frame_count {
+        for _ in 0..frame_count {
             let mut frame = if header.version >= 6 {
                 MadVRFrame {
                     peak_pq_2020: (reader.read_u16::<LE>()? as f64) / 64000.0,
@@ -281,13 +389,6 @@ impl MadVRFrame {
             };
 
             if header.version >= 5 {
-                let sdr_step: f64 = sdr_peak_pq / 64.0;
-                let hdr_step: f64 = (hdr_peak_pq - sdr_peak_pq) / 192.0;
-
-                // Value is in the middle of the histogram bin
-                let sdr_step = sdr_step + (sdr_step / 2.0);
-                let hdr_step = hdr_step + (hdr_step / 2.0);
-
                 frame.lum_histogram = MadVRFrame::parse_histogram(256, reader)?;
                 frame.hue_histogram = Some(MadVRFrame::parse_histogram(31, reader)?);
 
@@ -296,27 +397,16 @@ impl MadVRFrame {
                     .iter()
                     .enumerate()
                     .filter(|(i, p)| !(*i == 0 && **p > 2.0 && **p < 30.0)) // Filter out black bars
-                    .map(|(i, percent)| {
-                        let pq_value = if i <= 64 {
-                            (i as f64) * sdr_step
-                        } else {
-                            sdr_peak_pq + (((i - 63) as f64) * hdr_step)
-                        };
-
-                        pq_value * (percent / 100.0)
-                    })
+                    .map(|(i, percent)| frame.lum_histogram_bin_pq(i) * (percent / 100.0))
                     .sum();
             } else {
-                let step = hdr_peak_pq / 31.0;
-                let step = step + (step / 2.0);
-
                 frame.lum_histogram = MadVRFrame::parse_histogram(31, reader)?;
 
                 frame.avg_pq = frame
                     .lum_histogram
                     .iter()
                     .enumerate()
-                    .map(|(i, percent)| ((i as f64) * step) * (percent / 100.0))
+                    .map(|(i, percent)| frame.lum_histogram_bin_pq(i) * (percent / 100.0))
                     .sum();
             }
 
@@ -330,6 +420,73 @@ impl MadVRFrame {
         Ok(frames)
     }
 
+    /// Size of a frame measurement, in bytes
+    fn frame_size(header: &MadVRHeader) -> usize {
+        let peaks = if header.version >= 6 { 3 } else { 1 };
+        let histograms = if header.version >= 5 { 256 + 31 } else { 31 };
+
+        (peaks + histograms) * 2
+    }
+
+    /// PQ value of a bin of the frame luminance histogram.
+    pub fn lum_histogram_bin_pq(&self, bin: usize) -> f64 {
+        MadVRFrame::histogram_bin_pq(self.lum_histogram.len(), bin)
+    }
+
+    /// PQ value of a bin of a luminance histogram with `bin_count` bins.
+    ///
+    /// Version 5+ histograms have 64 bins up to 100 nits, then 192 bins up to 10 000 nits.
+    /// Older histograms have 31 bins over the whole range.
+    pub fn histogram_bin_pq(bin_count: usize, bin: usize) -> f64 {
+        let hdr_peak_pq = 1.0;
+
+        if bin_count == 256 {
+            let sdr_peak_pq = nits_to_pq(100);
+
+            let sdr_step: f64 = sdr_peak_pq / 64.0;
+            let hdr_step: f64 = (hdr_peak_pq - sdr_peak_pq) / 192.0;
+
+            // Value is in the middle of the histogram bin
+            let sdr_step = sdr_step + (sdr_step / 2.0);
+            let hdr_step = hdr_step + (hdr_step / 2.0);
+
+            if bin <= 64 {
+                (bin as f64) * sdr_step
+            } else {
+                sdr_peak_pq + (((bin - 63) as f64) * hdr_step)
+            }
+        } else {
+            let step = hdr_peak_pq / 31.0;
+            let step = step + (step / 2.0);
+
+            (bin as f64) * step
+        }
+    }
+
+    /// Lowest PQ of the luminance histogram with at least `min_percent` of the frame below it.
+    /// The first bin is ignored when it looks like black bars.
+    pub fn lum_histogram_min_pq(&self, min_percent: f64) -> f64 {
+        let bins = || {
+            self.lum_histogram
+                .iter()
+                .enumerate()
+                .filter(|(i, p)| !(*i == 0 && **p > 2.0 && **p < 30.0))
+        };
+
+        let percent_sum: f64 = bins().map(|(_, percent)| percent).sum();
+        let mut cumulative = 0.0;
+
+        for (i, percent) in bins() {
+            cumulative += percent;
+
+            if percent_sum > 0.0 && cumulative * 100.0 / percent_sum >= min_percent {
+                return self.lum_histogram_bin_pq(i);
+            }
+        }
+
+        0.0
+    }
+
     fn parse_histogram(length: usize, reader: &mut dyn Read) -> Result<Vec<f64>> {
         let mut histogram: Vec<f64> = Vec::new();
 
@@ -12,3 +12,10 @@ pub fn nits_to_pq(nits: u32) -> f64 {
     ((ST2084_C1 + ST2084_C2 * y.powf(ST2084_M1)) / (1.0 + ST2084_C3 * y.powf(ST2084_M1)))
         .powf(ST2084_M2)
 }
+
+#[inline(always)]
+pub fn pq_to_nits(pq: f64) -> f64 {
+    let v = pq.powf(1.0 / ST2084_M2);
+
+    ST2084_Y_MAX * ((v - ST2084_C1).max(0.0) / (ST2084_C2 - ST2084_C3 * v)).powf(1.0 / ST2084_M1)
+}
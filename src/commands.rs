@@ -1,6 +1,9 @@
 use std::path::PathBuf;
 use structopt::StructOpt;
 
+use crate::dovi::generator::MadVRMode;
+use crate::dovi::muxer::FrameRate;
+
 #[derive(StructOpt, Debug)]
 #[structopt(name = "dovi_tool", about = "Stuff about Dolby Vision")]
 pub enum Command {
@@ -118,13 +121,25 @@ pub enum Command {
         #[structopt(
             long,
             short = "o",
-            help = "Converted single layer output file location",
+            help = "Converted single layer output file location. Muxed to Matroska or MP4 with a .mkv or .mp4 extension",
             parse(from_os_str)
         )]
         output: Option<PathBuf>,
 
         #[structopt(short = "d", long, help = "Discard the EL stream")]
         discard: bool,
+
+        #[structopt(
+            long,
+            help = "Insert or replace the HDR10 mastering display and content light level SEIs, from the RPU L6/L9 metadata"
+        )]
+        hdr10_sei: bool,
+
+        #[structopt(
+            long,
+            help = "Frame rate of the Matroska/MP4 output timestamps, e.g. 24000/1001 or 23.976. Defaults to the SPS timing info"
+        )]
+        fps: Option<FrameRate>,
     },
 
     InjectRpu {
@@ -143,10 +158,22 @@ pub enum Command {
         #[structopt(
             long,
             short = "o",
-            help = "Output HEVC file location",
+            help = "Output HEVC file location. Muxed to Matroska or MP4 with a .mkv or .mp4 extension",
             parse(from_os_str)
         )]
         output: Option<PathBuf>,
+
+        #[structopt(
+            long,
+            help = "Insert or replace the HDR10 mastering display and content light level SEIs, from the RPU L6/L9 metadata"
+        )]
+        hdr10_sei: bool,
+
+        #[structopt(
+            long,
+            help = "Frame rate of the Matroska/MP4 output timestamps, e.g. 24000/1001 or 23.976. Defaults to the SPS timing info"
+        )]
+        fps: Option<FrameRate>,
     },
 
     Info {
@@ -166,6 +193,28 @@ pub enum Command {
             help = "Frame number to show info for"
         )]
         frame: Option<usize>,
+
+        #[structopt(
+            long,
+            help = "HEVC video to derive the Dolby Vision configuration record and codec strings from",
+            parse(from_os_str)
+        )]
+        hevc: Option<PathBuf>,
+
+        #[structopt(
+            long,
+            requires = "hevc",
+            help = "Frame rate to use when the HEVC video has no timing info"
+        )]
+        fps: Option<f64>,
+
+        #[structopt(
+            long,
+            requires = "hevc",
+            help = "Output file for the binary dvcC/dvvC box payload",
+            parse(from_os_str)
+        )]
+        config_out: Option<PathBuf>,
     },
 
     Generate {
@@ -205,6 +254,20 @@ pub enum Command {
         #[structopt(long, help = "Canvas height for L5 metadata generation")]
         canvas_height: Option<u16>,
 
+        #[structopt(
+            long,
+            help = "HEVC file to read the canvas size from, and validate the generated metadata against",
+            parse(from_os_str)
+        )]
+        hevc: Option<PathBuf>,
+
+        #[structopt(
+            long,
+            requires = "hevc",
+            help = "Replaces L6 and the L9 source primaries with the HDR10 SEIs of the --hevc file"
+        )]
+        l6_from_sei: bool,
+
         #[structopt(
             long,
             help = "madVR measurement file to generate from",
@@ -218,6 +281,69 @@ pub enum Command {
             help = "madVR source: use custom per-frame target nits if available"
         )]
         use_custom_targets: bool,
+
+        #[structopt(
+            long,
+            default_value = "scene",
+            possible_values = &["scene", "frame"],
+            help = "madVR source: L1 from the scene measurements, or per frame with the min and average from the histograms"
+        )]
+        madvr_mode: MadVRMode,
+
+        #[structopt(
+            long,
+            conflicts_with = "l6-from-sei",
+            help = "madVR source: set the L9 source primaries from the out of gamut peaks (version 6 files)"
+        )]
+        madvr_l9: bool,
+
+        #[structopt(
+            long,
+            help = "Smooths the L1 metadata with a moving average over this many frames"
+        )]
+        smooth_l1_window: Option<usize>,
+
+        #[structopt(
+            long,
+            requires = "smooth-l1-window",
+            help = "L1 smoothing: smooth over scene cuts, instead of restarting at every scene"
+        )]
+        smooth_across_scenes: bool,
+
+        #[structopt(
+            long,
+            help = "Generates L4 metadata from a temporal filter over L1, with this time constant in frames"
+        )]
+        l4_window: Option<usize>,
+
+        #[structopt(
+            long,
+            help = "10-bit PQ video to measure L1 metadata from: Y4M, or raw YUV420P10LE with --video-width/height. - for stdin",
+            conflicts_with_all = &["xml", "hdr10plus-json", "madvr-file"],
+            parse(from_os_str)
+        )]
+        video: Option<PathBuf>,
+
+        #[structopt(
+            long,
+            help = "Video source: raw YUV frame width",
+            requires = "video-height"
+        )]
+        video_width: Option<usize>,
+
+        #[structopt(
+            long,
+            help = "Video source: raw YUV frame height",
+            requires = "video-width"
+        )]
+        video_height: Option<usize>,
+
+        #[structopt(
+            long,
+            default_value = "0.3",
+            help = "Video source: histogram difference threshold (0 to 1) for scene cut detection"
+        )]
+        scene_threshold: f64,
     },
 
     Export {
@@ -234,9 +360,253 @@ pub enum Command {
             name = "output",
             long,
             short = "o",
-            help = "Output JSON file name",
+            help = "Output file name: JSON, or CSV/TSV with a .csv or .tsv extension",
+            parse(from_os_str)
+        )]
+        output: Option<PathBuf>,
+
+        #[structopt(
+            long,
+            use_delimiter = true,
+            help = "CSV/TSV column groups, comma separated: frame, scene_cut, profile, cm_version, l1, l2, l3, l4, l5, l6, l8, l9, l11"
+        )]
+        columns: Option<Vec<String>>,
+    },
+
+    Remove {
+        #[structopt(
+            name = "input",
+            long,
+            short = "i",
+            help = "Sets the input file to use",
+            conflicts_with = "stdin",
+            parse(from_os_str)
+        )]
+        input: Option<PathBuf>,
+
+        #[structopt(
+            help = "Uses stdin as input data",
+            conflicts_with = "input",
+            parse(from_os_str)
+        )]
+        stdin: Option<PathBuf>,
+
+        #[structopt(
+            long,
+            short = "o",
+            help = "Output HEVC file location, without the Dolby Vision NALUs",
+            parse(from_os_str)
+        )]
+        output: Option<PathBuf>,
+    },
+
+    DetectLetterbox {
+        #[structopt(
+            long,
+            short = "v",
+            help = "10-bit video to detect the bars from: Y4M, or raw YUV420P10LE with --video-width/height. - for stdin",
+            parse(from_os_str)
+        )]
+        video: PathBuf,
+
+        #[structopt(long, help = "Raw YUV frame width", requires = "video-height")]
+        video_width: Option<usize>,
+
+        #[structopt(long, help = "Raw YUV frame height", requires = "video-width")]
+        video_height: Option<usize>,
+
+        #[structopt(
+            long,
+            short = "o",
+            help = "Editor JSON output file location, defaults to letterbox_edits.json without --rpu-in",
+            parse(from_os_str)
+        )]
+        json_out: Option<PathBuf>,
+
+        #[structopt(
+            long,
+            help = "RPU file to apply the active area offsets to",
+            parse(from_os_str)
+        )]
+        rpu_in: Option<PathBuf>,
+
+        #[structopt(
+            long,
+            help = "Edited RPU output file location, defaults to RPU_letterbox.bin",
+            requires = "rpu-in",
+            parse(from_os_str)
+        )]
+        rpu_out: Option<PathBuf>,
+
+        #[structopt(
+            long,
+            default_value = "0.3",
+            help = "Histogram difference threshold (0 to 1) for scene cut detection"
+        )]
+        scene_threshold: f64,
+
+        #[structopt(
+            long,
+            default_value = "80",
+            help = "Max 10-bit luma value of the black bars"
+        )]
+        black_level: u16,
+
+        #[structopt(
+            long,
+            default_value = "128",
+            help = "Frames with a lower max 10-bit luma value are ignored"
+        )]
+        dark_level: u16,
+
+        #[structopt(
+            long,
+            default_value = "4",
+            help = "Bar size changes between scenes up to this amount of pixels are ignored"
+        )]
+        tolerance: u16,
+    },
+
+    Split {
+        #[structopt(
+            name = "input",
+            long,
+            short = "i",
+            help = "Sets the input RPU file to use",
+            parse(from_os_str)
+        )]
+        input: PathBuf,
+
+        #[structopt(
+            long,
+            use_delimiter = true,
+            required_unless = "scene-cuts",
+            conflicts_with = "scene-cuts",
+            help = "First frames of the chunks, comma separated"
+        )]
+        frames: Vec<usize>,
+
+        #[structopt(long, help = "Start a new chunk every N scene cuts")]
+        scene_cuts: Option<usize>,
+
+        #[structopt(
+            long,
+            short = "o",
+            help = "Output directory of the chunks, defaults to the current directory",
+            parse(from_os_str)
+        )]
+        output_dir: Option<PathBuf>,
+
+        #[structopt(long, help = "Split even if chunks don't start on a scene cut")]
+        force: bool,
+    },
+
+    Concat {
+        #[structopt(
+            name = "inputs",
+            required = true,
+            help = "RPU files to join, in order",
+            parse(from_os_str)
+        )]
+        inputs: Vec<PathBuf>,
+
+        #[structopt(
+            long,
+            short = "o",
+            help = "Output RPU file location, defaults to RPU_concat.bin",
             parse(from_os_str)
         )]
         output: Option<PathBuf>,
     },
+
+    Sync {
+        #[structopt(
+            name = "input",
+            long,
+            short = "i",
+            help = "Sets the input RPU file to align",
+            parse(from_os_str)
+        )]
+        input: PathBuf,
+
+        #[structopt(
+            long,
+            short = "r",
+            required_unless = "madvr-file",
+            conflicts_with = "madvr-file",
+            help = "RPU file of the target video",
+            parse(from_os_str)
+        )]
+        reference_rpu: Option<PathBuf>,
+
+        #[structopt(
+            long,
+            help = "madVR measurement file of the target video",
+            parse(from_os_str)
+        )]
+        madvr_file: Option<PathBuf>,
+
+        #[structopt(
+            long,
+            short = "o",
+            help = "Editor JSON output file location, defaults to sync_edits.json",
+            parse(from_os_str)
+        )]
+        json_out: Option<PathBuf>,
+
+        #[structopt(long, default_value = "2400", help = "Max offset to search, in frames")]
+        max_offset: usize,
+    },
+
+    ToHdr10plus {
+        #[structopt(
+            name = "input",
+            long,
+            short = "i",
+            help = "Sets the input RPU file to use",
+            parse(from_os_str)
+        )]
+        input: PathBuf,
+
+        #[structopt(
+            long,
+            short = "o",
+            help = "Output file: HDR10+ JSON with a .json extension, otherwise ST 2094-40 SEI NAL units. Defaults to hdr10plus_metadata.json",
+            parse(from_os_str)
+        )]
+        output: Option<PathBuf>,
+
+        #[structopt(
+            long,
+            default_value = "400",
+            help = "Target display peak brightness (nits) of the tone mapping curve"
+        )]
+        target_nits: u16,
+    },
+
+    ToMadvr {
+        #[structopt(
+            name = "input",
+            long,
+            short = "i",
+            help = "Sets the input RPU file to use",
+            parse(from_os_str)
+        )]
+        input: PathBuf,
+
+        #[structopt(
+            long,
+            short = "o",
+            help = "madVR measurement file output. Defaults to madvr.measurements",
+            parse(from_os_str)
+        )]
+        output: Option<PathBuf>,
+
+        #[structopt(
+            long,
+            default_value = "400",
+            help = "Target display peak brightness (nits) of the trims used for the custom per-frame target nits"
+        )]
+        target_nits: u16,
+    },
 }
@@ -1,8 +1,13 @@
-use anyhow::{bail, Result};
+use anyhow::{bail, ensure, Result};
 use indicatif::ProgressBar;
 use std::path::PathBuf;
 
-use super::{input_format, io, CliOptions, Format};
+use hevc_parser::hevc::NAL_UNSPEC62;
+
+use super::hevc_info::find_first_nal;
+use super::muxer::OutputContainer;
+use super::sei::Hdr10Metadata;
+use super::{input_format, io, CliOptions, DoviRpu, Format};
 
 use io::{DoviReader, DoviWriter};
 
@@ -56,9 +61,39 @@ impl Converter {
     }
 
     fn convert_raw_hevc(&self, pb: Option<&ProgressBar>, options: CliOptions) -> Result<()> {
+        let hdr10_sei = options.hdr10_sei;
+        let fps = options.fps;
+
         let mut dovi_reader = DoviReader::new(options);
-        let mut dovi_writer = DoviWriter::new(None, None, None, Some(&self.output));
 
-        dovi_reader.read_write_from_io(&self.format, &self.input, pb, &mut dovi_writer)
+        if hdr10_sei {
+            dovi_reader.set_hdr10_sei_nals(self.hdr10_sei_nals()?);
+        }
+
+        let container = OutputContainer::from_path(&self.output);
+
+        container.write(&self.output, fps, |annexb_output| {
+            let mut dovi_writer = DoviWriter::new(None, None, None, Some(annexb_output));
+
+            dovi_reader.read_write_from_io(&self.format, &self.input, pb, &mut dovi_writer)
+        })
+    }
+
+    /// HDR10 SEIs from the L6 metadata of the first RPU in the input
+    fn hdr10_sei_nals(&self) -> Result<Vec<Vec<u8>>> {
+        ensure!(
+            self.format == Format::Raw,
+            "HDR10 SEI insertion requires an input file"
+        );
+
+        let rpu = match find_first_nal(&self.input, NAL_UNSPEC62)? {
+            Some(data) => DoviRpu::parse_unspec62_nalu(&data)?,
+            None => bail!("No RPU found in the input"),
+        };
+
+        match Hdr10Metadata::from_rpu(&rpu) {
+            Some(metadata) => Ok(metadata.sei_nals()),
+            None => bail!("No L6 metadata in the RPU to insert HDR10 SEIs from"),
+        }
     }
 }
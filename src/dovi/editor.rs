@@ -1,14 +1,22 @@
 use std::fs::File;
-use std::{collections::HashMap, path::PathBuf};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
 
-use anyhow::{bail, ensure, Result};
+use anyhow::{bail, ensure, format_err, Result};
 use dolby_vision::rpu::extension_metadata::blocks::{
-    ExtMetadataBlock, ExtMetadataBlockLevel11, ExtMetadataBlockLevel5, ExtMetadataBlockLevel6,
+    ExtMetadataBlock, ExtMetadataBlockLevel1, ExtMetadataBlockLevel11, ExtMetadataBlockLevel5,
+    ExtMetadataBlockLevel6, ExtMetadataBlockLevel9,
 };
-use dolby_vision::rpu::extension_metadata::{CmV40DmData, DmData};
+use dolby_vision::rpu::extension_metadata::{CmV40DmData, TargetTrim, WithExtMetadataBlocks};
 use dolby_vision::rpu::generate::GenerateConfig;
+use dolby_vision::rpu::vdr_dm_data::VdrDmData;
+use dolby_vision::utils::nits_to_pq;
 use serde::{Deserialize, Serialize};
 
+use super::muxer::FrameRate;
+use super::temporal::TemporalConfig;
 use super::{parse_rpu_file, write_rpu_file, DoviRpu};
 
 pub struct Editor {
@@ -27,18 +35,42 @@ pub struct EditConfig {
     #[serde(default)]
     convert_to_cmv4: bool,
 
+    #[serde(default)]
+    convert_to_cmv29: bool,
+
     #[serde(default)]
     remove_mapping: bool,
 
     #[serde(skip_serializing_if = "Option::is_none")]
     active_area: Option<ActiveArea>,
 
+    #[serde(skip_serializing_if = "Option::is_none")]
+    target_trims: Option<HashMap<String, Vec<TargetTrim>>>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    add_target_trims: Option<Vec<u16>>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    metadata_edits: Option<Vec<MetadataEdit>>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    scene_cuts: Option<SceneCuts>,
+
     #[serde(skip_serializing_if = "Option::is_none")]
     remove: Option<Vec<String>>,
 
     #[serde(skip_serializing_if = "Option::is_none")]
     duplicate: Option<Vec<DuplicateMetadata>>,
 
+    #[serde(skip_serializing_if = "Option::is_none")]
+    frame_rate_conversion: Option<FrameRateConversion>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    merge: Option<Vec<MergeRpu>>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temporal: Option<TemporalConfig>,
+
     #[serde(default)]
     min_pq: Option<u16>,
 
@@ -46,6 +78,7 @@ pub struct EditConfig {
     max_pq: Option<u16>,
 
     level6: Option<ExtMetadataBlockLevel6>,
+    level9: Option<ExtMetadataBlockLevel9>,
     level11: Option<ExtMetadataBlockLevel11>,
 }
 
@@ -73,6 +106,76 @@ pub struct ActiveAreaOffsets {
     bottom: u16,
 }
 
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct MetadataEdit {
+    range: String,
+
+    #[serde(default)]
+    remove: Vec<u8>,
+
+    #[serde(default)]
+    replace: Vec<ExtMetadataBlock>,
+
+    #[serde(default)]
+    adjust: Vec<BlockAdjustment>,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct BlockAdjustment {
+    level: u8,
+    field: String,
+
+    // Only adjust the L2 trim for this target
+    #[serde(skip_serializing_if = "Option::is_none")]
+    target_max_pq: Option<u16>,
+
+    // Only adjust the L8/L10 block for this target
+    #[serde(skip_serializing_if = "Option::is_none")]
+    target_display_index: Option<u8>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    set: Option<i64>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    delta: Option<i64>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    scale: Option<f64>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    min: Option<i64>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct SceneCuts {
+    // Frames or ranges to clear the scene cuts from
+    #[serde(skip_serializing_if = "Option::is_none")]
+    clear: Option<Vec<String>>,
+
+    // Rebuilds the scene cuts from the L1 metadata changes
+    #[serde(default)]
+    from_l1: bool,
+
+    // Frame list or EDL file to import the scene cuts from
+    #[serde(skip_serializing_if = "Option::is_none")]
+    import: Option<PathBuf>,
+
+    // Frame rate of the EDL timecodes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    edl_fps: Option<f64>,
+
+    // Frames starting a new shot, keeping the metadata
+    #[serde(skip_serializing_if = "Option::is_none")]
+    set: Option<Vec<usize>>,
+
+    // Ranges of frames to merge into a single shot
+    #[serde(skip_serializing_if = "Option::is_none")]
+    merge: Option<Vec<String>>,
+}
+
 #[derive(Serialize, Deserialize, Default, Debug)]
 pub struct DuplicateMetadata {
     source: usize,
@@ -80,6 +183,42 @@ pub struct DuplicateMetadata {
     length: usize,
 }
 
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct MergeRpu {
+    // RPU file to copy the metadata from, with the same number of frames
+    rpu: PathBuf,
+
+    // Frames or ranges to merge, defaults to all
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ranges: Option<Vec<String>>,
+
+    // Extension metadata levels to copy
+    #[serde(default)]
+    levels: Vec<u8>,
+
+    // Copies the whole DM metadata
+    #[serde(default)]
+    vdr_dm_data: bool,
+
+    // Copies the header, mapping and NLQ data
+    #[serde(default)]
+    mapping: bool,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct FrameRateConversion {
+    // Frame rates as fractions or decimal numbers, e.g. "24000/1001" or "59.94"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    source_fps: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    target_fps: Option<String>,
+
+    // Number of output frames for every source frame, repeated over the frames
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cadence: Option<Vec<usize>>,
+}
+
 impl Editor {
     pub fn edit(input: PathBuf, json_path: PathBuf, rpu_out: Option<PathBuf>) -> Result<()> {
         let out_path = if let Some(out_path) = rpu_out {
@@ -113,13 +252,22 @@ impl Editor {
         }
 
         // Override to CM v4.0
-        if !config.convert_to_cmv4 && config.level11.is_some() {
+        if !config.convert_to_cmv4 && (config.level9.is_some() || config.level11.is_some()) {
             config.convert_to_cmv4 = true;
         }
 
+        ensure!(
+            !(config.convert_to_cmv4 && config.convert_to_cmv29),
+            "Cannot convert to both CM v2.9 and CM v4.0"
+        );
+
         if let Some(ref mut rpus) = editor.rpus {
             config.execute(rpus)?;
 
+            if config.frame_rate_conversion.is_some() {
+                *rpus = config.convert_frame_rate(std::mem::take(rpus))?;
+            }
+
             let mut data = GenerateConfig::encode_option_rpus(rpus);
 
             if let Some(ref mut to_duplicate) = config.duplicate {
@@ -141,14 +289,22 @@ impl Editor {
 }
 
 impl EditConfig {
-    fn execute(&self, rpus: &mut Vec<Option<DoviRpu>>) -> Result<()> {
+    pub(crate) fn execute(&self, rpus: &mut [Option<DoviRpu>]) -> Result<()> {
         // Drop metadata frames
         if let Some(ranges) = &self.remove {
             self.remove_frames(ranges, rpus)?;
         }
 
+        if let Some(to_merge) = &self.merge {
+            for merge in to_merge {
+                merge.execute(rpus)?;
+            }
+        }
+
         if self.convert_to_cmv4 {
             self.add_cmv4_dm_data(rpus)?;
+        } else if self.convert_to_cmv29 {
+            self.remove_cmv4_dm_data(rpus)?;
         }
 
         // Convert with mode
@@ -160,6 +316,28 @@ impl EditConfig {
             active_area.execute(rpus)?;
         }
 
+        if let Some(target_trims) = &self.target_trims {
+            self.set_target_trims(target_trims, rpus)?;
+        }
+
+        if let Some(target_nits) = &self.add_target_trims {
+            self.add_interpolated_trims(target_nits, rpus)?;
+        }
+
+        if let Some(metadata_edits) = &self.metadata_edits {
+            self.apply_metadata_edits(metadata_edits, rpus)?;
+        }
+
+        if let Some(scene_cuts) = &self.scene_cuts {
+            scene_cuts.execute(rpus)?;
+        }
+
+        if let Some(temporal) = &self.temporal {
+            let mut frames: Vec<&mut DoviRpu> =
+                rpus.iter_mut().filter_map(|e| e.as_mut()).collect();
+            temporal.execute(&mut frames)?;
+        }
+
         if self.min_pq.is_some() || self.max_pq.is_some() {
             self.change_source_levels(rpus);
         }
@@ -172,6 +350,10 @@ impl EditConfig {
             self.set_level6_metadata(rpus, l6)?;
         }
 
+        if let Some(l9) = &self.level9 {
+            self.set_level9_metadata(rpus, l9)?;
+        }
+
         if let Some(l11) = &self.level11 {
             self.set_level11_metadata(rpus, l11)?;
         }
@@ -179,7 +361,20 @@ impl EditConfig {
         Ok(())
     }
 
-    fn convert_with_mode(&self, rpus: &mut Vec<Option<DoviRpu>>) -> Result<()> {
+    /// Maps the frames to the target frame rate, dropping the removed frames
+    pub(crate) fn convert_frame_rate(
+        &self,
+        rpus: Vec<Option<DoviRpu>>,
+    ) -> Result<Vec<Option<DoviRpu>>> {
+        let rpus: Vec<DoviRpu> = rpus.into_iter().flatten().collect();
+
+        match &self.frame_rate_conversion {
+            Some(conversion) => Ok(conversion.execute(&rpus)?.into_iter().map(Some).collect()),
+            None => Ok(rpus.into_iter().map(Some).collect()),
+        }
+    }
+
+    fn convert_with_mode(&self, rpus: &mut [Option<DoviRpu>]) -> Result<()> {
         println!("Converting with mode {}...", self.mode);
         let list = rpus.iter_mut().filter_map(|e| e.as_mut());
 
@@ -214,7 +409,27 @@ impl EditConfig {
         }
     }
 
-    fn remove_frames(&self, ranges: &[String], rpus: &mut Vec<Option<DoviRpu>>) -> Result<()> {
+    /// Parses "all", a single frame or an inclusive range of frames
+    fn frame_range(range: &str, rpus_len: usize) -> Result<(usize, usize)> {
+        let (start, end) = if range.to_lowercase() == "all" {
+            (0, rpus_len - 1)
+        } else if let Ok(index) = range.parse::<usize>() {
+            (index, index)
+        } else {
+            EditConfig::range_string_to_tuple(range)?
+        };
+
+        ensure!(
+            start <= end && end < rpus_len,
+            "Invalid range: {}, {} available RPUs",
+            range,
+            rpus_len
+        );
+
+        Ok((start, end))
+    }
+
+    fn remove_frames(&self, ranges: &[String], rpus: &mut [Option<DoviRpu>]) -> Result<()> {
         let mut amount = 0;
 
         for range in ranges {
@@ -266,7 +481,117 @@ impl EditConfig {
         Ok(())
     }
 
-    fn change_source_levels(&self, rpus: &mut Vec<Option<DoviRpu>>) {
+    fn set_target_trims(
+        &self,
+        target_trims: &HashMap<String, Vec<TargetTrim>>,
+        rpus: &mut [Option<DoviRpu>],
+    ) -> Result<()> {
+        println!("Setting target display trims...");
+
+        let mut edits = target_trims
+            .iter()
+            .map(|(range, trims)| {
+                let (start, end) = EditConfig::frame_range(range, rpus.len())?;
+                Ok((start, end, trims))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        // Apply "all" and wider ranges first, so that specific ranges override them
+        edits.sort_by_key(|(start, end, _)| (*start, std::cmp::Reverse(*end)));
+
+        for (start, end, trims) in edits {
+            for rpu in rpus[start..=end].iter_mut().filter_map(|e| e.as_mut()) {
+                rpu.modified = true;
+
+                if let Some(ref mut vdr_dm_data) = rpu.vdr_dm_data {
+                    for trim in trims {
+                        vdr_dm_data.set_target_trim(trim)?;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn add_interpolated_trims(
+        &self,
+        target_nits: &[u16],
+        rpus: &mut [Option<DoviRpu>],
+    ) -> Result<()> {
+        println!("Adding interpolated trims for {:?} nits...", target_nits);
+
+        let target_pqs: Vec<u16> = target_nits
+            .iter()
+            .map(|nits| (nits_to_pq(*nits) * 4095.0).round() as u16)
+            .collect();
+
+        for rpu in rpus.iter_mut().filter_map(|e| e.as_mut()) {
+            if let Some(ref mut vdr_dm_data) = rpu.vdr_dm_data {
+                for target_max_pq in &target_pqs {
+                    if vdr_dm_data.add_interpolated_trim(*target_max_pq)? {
+                        rpu.modified = true;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn apply_metadata_edits(
+        &self,
+        metadata_edits: &[MetadataEdit],
+        rpus: &mut [Option<DoviRpu>],
+    ) -> Result<()> {
+        println!("Editing metadata blocks...");
+
+        for edit in metadata_edits {
+            let (start, end) = EditConfig::frame_range(&edit.range, rpus.len())?;
+            let mut adjusted = vec![false; edit.adjust.len()];
+
+            for rpu in rpus[start..=end].iter_mut().filter_map(|e| e.as_mut()) {
+                rpu.modified = true;
+
+                if let Some(ref mut vdr_dm_data) = rpu.vdr_dm_data {
+                    for level in &edit.remove {
+                        vdr_dm_data.remove_metadata_level(*level);
+                    }
+
+                    for block in &edit.replace {
+                        vdr_dm_data.replace_metadata_block(block.clone())?;
+                    }
+
+                    for (adjustment, adjusted) in edit.adjust.iter().zip(adjusted.iter_mut()) {
+                        for block in vdr_dm_data.level_blocks_iter_mut(adjustment.level) {
+                            if adjustment.matches(block) {
+                                adjustment.apply(block)?;
+                                *adjusted = true;
+                            }
+                        }
+                    }
+                }
+            }
+
+            if let Some(adjustment) = edit
+                .adjust
+                .iter()
+                .zip(adjusted)
+                .find_map(|(adjustment, adjusted)| if adjusted { None } else { Some(adjustment) })
+            {
+                bail!(
+                    "Adjustment of L{} '{}' did not match any block in range {}",
+                    adjustment.level,
+                    adjustment.field,
+                    edit.range
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    fn change_source_levels(&self, rpus: &mut [Option<DoviRpu>]) {
         rpus.iter_mut().filter_map(|e| e.as_mut()).for_each(|rpu| {
             rpu.modified = true;
 
@@ -278,7 +603,7 @@ impl EditConfig {
 
     fn set_level6_metadata(
         &self,
-        rpus: &mut Vec<Option<DoviRpu>>,
+        rpus: &mut [Option<DoviRpu>],
         level6: &ExtMetadataBlockLevel6,
     ) -> Result<()> {
         for rpu in rpus.iter_mut().filter_map(|e| e.as_mut()) {
@@ -292,13 +617,36 @@ impl EditConfig {
         Ok(())
     }
 
-    fn add_cmv4_dm_data(&self, rpus: &mut Vec<Option<DoviRpu>>) -> Result<()> {
+    fn add_cmv4_dm_data(&self, rpus: &mut [Option<DoviRpu>]) -> Result<()> {
+        println!("Converting to CM v4.0...");
+
+        let level9 = self
+            .level9
+            .clone()
+            .unwrap_or_else(ExtMetadataBlockLevel9::default_dci_p3);
+
         for rpu in rpus.iter_mut().filter_map(|e| e.as_mut()) {
             rpu.modified = true;
 
             if let Some(ref mut vdr_dm_data) = rpu.vdr_dm_data {
-                if vdr_dm_data.cmv40_metadata.is_none() {
-                    vdr_dm_data.cmv40_metadata = Some(DmData::V40(CmV40DmData::new_with_l254()));
+                vdr_dm_data.convert_to_cmv40(level9.source_primary_index)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn remove_cmv4_dm_data(&self, rpus: &mut [Option<DoviRpu>]) -> Result<()> {
+        println!("Converting to CM v2.9...");
+
+        for (i, rpu) in rpus.iter_mut().enumerate() {
+            if let Some(rpu) = rpu {
+                rpu.modified = true;
+
+                if let Some(ref mut vdr_dm_data) = rpu.vdr_dm_data {
+                    vdr_dm_data
+                        .convert_to_cmv29()
+                        .map_err(|e| format_err!("Frame {}: {}", i, e))?;
                 }
             }
         }
@@ -306,9 +654,25 @@ impl EditConfig {
         Ok(())
     }
 
+    fn set_level9_metadata(
+        &self,
+        rpus: &mut [Option<DoviRpu>],
+        level9: &ExtMetadataBlockLevel9,
+    ) -> Result<()> {
+        for rpu in rpus.iter_mut().filter_map(|e| e.as_mut()) {
+            rpu.modified = true;
+
+            if let Some(ref mut vdr_dm_data) = rpu.vdr_dm_data {
+                vdr_dm_data.replace_metadata_block(ExtMetadataBlock::Level9(level9.clone()))?;
+            }
+        }
+
+        Ok(())
+    }
+
     fn set_level11_metadata(
         &self,
-        rpus: &mut Vec<Option<DoviRpu>>,
+        rpus: &mut [Option<DoviRpu>],
         level11: &ExtMetadataBlockLevel11,
     ) -> Result<()> {
         for rpu in rpus.iter_mut().filter_map(|e| e.as_mut()) {
@@ -322,7 +686,7 @@ impl EditConfig {
         Ok(())
     }
 
-    fn remove_mapping(&self, rpus: &mut Vec<Option<DoviRpu>>) {
+    fn remove_mapping(&self, rpus: &mut [Option<DoviRpu>]) {
         println!("Removing polynomial/MMR mapping...");
         let list = rpus.iter_mut().filter_map(|e| e.as_mut());
 
@@ -332,8 +696,541 @@ impl EditConfig {
     }
 }
 
+impl BlockAdjustment {
+    fn matches(&self, block: &ExtMetadataBlock) -> bool {
+        match block {
+            ExtMetadataBlock::Level2(b) => self
+                .target_max_pq
+                .map_or(true, |target_max_pq| b.target_max_pq == target_max_pq),
+            ExtMetadataBlock::Level8(b) => self
+                .target_display_index
+                .map_or(true, |index| b.target_display_index == index),
+            ExtMetadataBlock::Level10(b) => self
+                .target_display_index
+                .map_or(true, |index| b.target_display_index == index),
+            _ => true,
+        }
+    }
+
+    /// Sets, offsets or scales a numeric field of the block, then clamps and validates it
+    fn apply(&self, block: &mut ExtMetadataBlock) -> Result<()> {
+        let mut value = serde_json::to_value(&block)?;
+
+        let field = value
+            .as_object_mut()
+            .and_then(|b| b.values_mut().next())
+            .and_then(|b| b.get_mut(&self.field))
+            .ok_or_else(|| format_err!("Invalid field '{}' for L{}", self.field, self.level))?;
+
+        let original = field
+            .as_i64()
+            .ok_or_else(|| format_err!("Field '{}' is not an integer", self.field))?;
+
+        let mut new_value = self.set.unwrap_or(original);
+
+        if let Some(scale) = self.scale {
+            new_value = (new_value as f64 * scale).round() as i64;
+        }
+
+        if let Some(delta) = self.delta {
+            new_value += delta;
+        }
+
+        if let Some(min) = self.min {
+            new_value = new_value.max(min);
+        }
+
+        if let Some(max) = self.max {
+            new_value = new_value.min(max);
+        }
+
+        *field = new_value.into();
+
+        let invalid_value = |e: anyhow::Error| {
+            format_err!(
+                "Invalid value {} for L{} '{}': {}",
+                new_value,
+                self.level,
+                self.field,
+                e
+            )
+        };
+
+        let adjusted: ExtMetadataBlock =
+            serde_json::from_value(value).map_err(|e| invalid_value(e.into()))?;
+        adjusted.validate().map_err(invalid_value)?;
+
+        *block = adjusted;
+
+        Ok(())
+    }
+}
+
+impl SceneCuts {
+    // Shot level metadata made consistent when merging shots
+    const SHOT_LEVELS: &'static [u8] = &[2, 3, 8, 10];
+
+    fn execute(&self, rpus: &mut [Option<DoviRpu>]) -> Result<()> {
+        if let Some(ranges) = &self.clear {
+            for range in ranges {
+                let (start, end) = EditConfig::frame_range(range, rpus.len())?;
+                SceneCuts::set_scene_cuts(&mut rpus[start..=end], false);
+            }
+        }
+
+        if self.from_l1 {
+            self.cuts_from_l1(rpus);
+        }
+
+        if let Some(path) = &self.import {
+            let frames = self.import_cuts(path)?;
+            println!("Imported {} scene cuts", frames.len());
+
+            SceneCuts::set_cuts_at_frames(&frames, rpus)?;
+        }
+
+        if let Some(frames) = &self.set {
+            SceneCuts::set_cuts_at_frames(frames, rpus)?;
+        }
+
+        if let Some(ranges) = &self.merge {
+            for range in ranges {
+                let (start, end) = EditConfig::frame_range(range, rpus.len())?;
+                SceneCuts::merge_shots(&mut rpus[start..=end])?;
+            }
+        }
+
+        let count = rpus
+            .iter()
+            .filter_map(|e| e.as_ref())
+            .filter_map(|rpu| rpu.vdr_dm_data.as_ref())
+            .filter(|vdr_dm_data| vdr_dm_data.scene_refresh_flag == 1)
+            .count();
+
+        println!("Scene cuts: {}", count);
+
+        Ok(())
+    }
+
+    fn set_scene_cuts(rpus: &mut [Option<DoviRpu>], is_scene_cut: bool) {
+        for rpu in rpus.iter_mut().filter_map(|e| e.as_mut()) {
+            rpu.modified = true;
+
+            if let Some(ref mut vdr_dm_data) = rpu.vdr_dm_data {
+                vdr_dm_data.set_scene_cut(is_scene_cut);
+            }
+        }
+    }
+
+    fn set_cuts_at_frames(frames: &[usize], rpus: &mut [Option<DoviRpu>]) -> Result<()> {
+        for frame in frames {
+            ensure!(
+                *frame < rpus.len(),
+                "Invalid scene cut frame {}, {} available RPUs",
+                frame,
+                rpus.len()
+            );
+
+            SceneCuts::set_scene_cuts(&mut rpus[*frame..=*frame], true);
+        }
+
+        Ok(())
+    }
+
+    /// Sets a scene cut on every frame where the L1 metadata changes, and clears the others
+    fn cuts_from_l1(&self, rpus: &mut [Option<DoviRpu>]) {
+        println!("Rebuilding scene cuts from L1 metadata...");
+
+        let mut previous_l1 = None;
+
+        for rpu in rpus.iter_mut().filter_map(|e| e.as_mut()) {
+            rpu.modified = true;
+
+            if let Some(ref mut vdr_dm_data) = rpu.vdr_dm_data {
+                let l1 = match vdr_dm_data.get_block(1) {
+                    Some(ExtMetadataBlock::Level1(b)) => Some((b.min_pq, b.max_pq, b.avg_pq)),
+                    _ => None,
+                };
+
+                let is_scene_cut = previous_l1.is_none() || l1 != previous_l1;
+                vdr_dm_data.set_scene_cut(is_scene_cut);
+
+                previous_l1 = l1;
+            }
+        }
+    }
+
+    fn import_cuts(&self, path: &Path) -> Result<Vec<usize>> {
+        let content = std::fs::read_to_string(path)?;
+
+        let is_edl = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map_or(false, |ext| ext.to_lowercase() == "edl");
+
+        if is_edl {
+            let fps = self
+                .edl_fps
+                .ok_or_else(|| format_err!("edl_fps is required to import an EDL"))?;
+
+            SceneCuts::parse_edl(&content, fps)
+        } else {
+            content
+                .split(|c: char| c == ',' || c.is_whitespace())
+                .filter(|e| !e.is_empty())
+                .map(|e| {
+                    e.parse::<usize>()
+                        .map_err(|_| format_err!("Invalid scene cut frame: {}", e))
+                })
+                .collect()
+        }
+    }
+
+    /// Parses the record in timecodes of a CMX3600 EDL, relative to the first event.
+    /// Drop-frame timecodes (`;` frame separator) are supported for 29.97 and 59.94 fps.
+    fn parse_edl(content: &str, fps: f64) -> Result<Vec<usize>> {
+        let timebase = fps.round() as usize;
+
+        let timecode_to_frame = |tc: &str| -> Result<usize> {
+            let parts = tc
+                .split(|c| c == ':' || c == ';')
+                .map(|e| e.parse::<usize>())
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|_| format_err!("Invalid EDL timecode: {}", tc))?;
+
+            ensure!(parts.len() == 4, "Invalid EDL timecode: {}", tc);
+
+            let frame = ((parts[0] * 60 + parts[1]) * 60 + parts[2]) * timebase + parts[3];
+
+            if !tc.contains(';') {
+                return Ok(frame);
+            }
+
+            // Drop-frame: frame numbers are skipped every minute, except every tenth minute
+            ensure!(
+                timebase == 30 || timebase == 60,
+                "Drop-frame EDL timecode {} requires 29.97 or 59.94 fps, got {}",
+                tc,
+                fps
+            );
+
+            let dropped_per_minute = timebase / 15;
+            let minutes = parts[0] * 60 + parts[1];
+
+            ensure!(
+                parts[1] % 10 == 0 || parts[2] > 0 || parts[3] >= dropped_per_minute,
+                "Invalid drop-frame EDL timecode: {}",
+                tc
+            );
+
+            Ok(frame - dropped_per_minute * (minutes - minutes / 10))
+        };
+
+        let mut record_ins = Vec::new();
+
+        for line in content.lines() {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+
+            // Event lines start with the event number and end with 4 timecodes
+            if fields.len() >= 8 && fields[0].parse::<usize>().is_ok() {
+                record_ins.push(timecode_to_frame(fields[fields.len() - 2])?);
+            }
+        }
+
+        let first = record_ins.iter().min().copied().unwrap_or(0);
+
+        let mut frames: Vec<usize> = record_ins.iter().map(|f| f - first).collect();
+        frames.sort_unstable();
+        frames.dedup();
+
+        Ok(frames)
+    }
+
+    /// Merges the frames into a single shot.
+    /// L1 is aggregated over the frames, other shot metadata is copied from the first frame.
+    fn merge_shots(rpus: &mut [Option<DoviRpu>]) -> Result<()> {
+        let l1_blocks: Vec<ExtMetadataBlockLevel1> = rpus
+            .iter()
+            .filter_map(|e| e.as_ref())
+            .filter_map(|rpu| rpu.vdr_dm_data.as_ref())
+            .filter_map(|vdr_dm_data| match vdr_dm_data.get_block(1) {
+                Some(ExtMetadataBlock::Level1(b)) => Some(b.clone()),
+                _ => None,
+            })
+            .collect();
+
+        let merged_l1 = if l1_blocks.is_empty() {
+            None
+        } else {
+            let min_pq = l1_blocks.iter().map(|b| b.min_pq).min().unwrap_or(0);
+            let max_pq = l1_blocks.iter().map(|b| b.max_pq).max().unwrap_or(0);
+            let avg_pq = (l1_blocks.iter().map(|b| b.avg_pq as f64).sum::<f64>()
+                / l1_blocks.len() as f64)
+                .round() as u16;
+
+            Some(ExtMetadataBlockLevel1::from_stats(min_pq, max_pq, avg_pq))
+        };
+
+        let shot_blocks: Vec<ExtMetadataBlock> = rpus
+            .iter()
+            .filter_map(|e| e.as_ref())
+            .find_map(|rpu| rpu.vdr_dm_data.as_ref())
+            .map(|vdr_dm_data| {
+                SceneCuts::SHOT_LEVELS
+                    .iter()
+                    .flat_map(|level| vdr_dm_data.level_blocks_iter(*level).cloned())
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        for (i, rpu) in rpus.iter_mut().filter_map(|e| e.as_mut()).enumerate() {
+            rpu.modified = true;
+
+            if let Some(ref mut vdr_dm_data) = rpu.vdr_dm_data {
+                vdr_dm_data.set_scene_cut(i == 0);
+
+                if let Some(l1) = &merged_l1 {
+                    vdr_dm_data.replace_metadata_block(ExtMetadataBlock::Level1(l1.clone()))?;
+                }
+
+                for level in SceneCuts::SHOT_LEVELS {
+                    vdr_dm_data.remove_metadata_level(*level);
+                }
+
+                for block in &shot_blocks {
+                    vdr_dm_data.add_metadata_block(block.clone())?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl MergeRpu {
+    fn execute(&self, rpus: &mut [Option<DoviRpu>]) -> Result<()> {
+        println!("Merging metadata from {}...", self.rpu.display());
+
+        ensure!(
+            self.vdr_dm_data || self.mapping || !self.levels.is_empty(),
+            "merge: nothing to merge from {}",
+            self.rpu.display()
+        );
+        ensure!(
+            !self.vdr_dm_data || self.levels.is_empty(),
+            "merge: levels cannot be used with vdr_dm_data"
+        );
+
+        let other = match parse_rpu_file(&self.rpu)? {
+            Some(other) => other,
+            None => bail!("No RPUs parsed from {}", self.rpu.display()),
+        };
+
+        ensure!(
+            other.len() == rpus.len(),
+            "merge: {} has {} frames, expected {}. The RPUs can be aligned with the sync command",
+            self.rpu.display(),
+            other.len(),
+            rpus.len()
+        );
+
+        // L8 trims can use L10 custom target displays
+        let mut levels = self.levels.clone();
+        if levels.contains(&8) && !levels.contains(&10) {
+            levels.push(10);
+        }
+
+        // CM v4.0 levels in CM v2.9 RPUs: every frame is converted, not to mix CM versions
+        if levels
+            .iter()
+            .any(|level| CmV40DmData::ALLOWED_BLOCK_LEVELS.contains(level))
+        {
+            let mut converted = 0;
+
+            for (rpu, other) in rpus.iter_mut().zip(&other) {
+                let rpu = match rpu {
+                    Some(rpu) => rpu,
+                    None => continue,
+                };
+
+                if let Some(ref mut vdr_dm_data) = rpu.vdr_dm_data {
+                    if vdr_dm_data.cmv40_metadata.is_none() {
+                        let source_primary_index =
+                            merge_source_primary_index(vdr_dm_data, other.vdr_dm_data.as_ref());
+
+                        vdr_dm_data.convert_to_cmv40(source_primary_index)?;
+                        rpu.modified = true;
+                        converted += 1;
+                    }
+                }
+            }
+
+            if converted > 0 {
+                println!("Converted {} CM v2.9 frames to CM v4.0", converted);
+            }
+        }
+
+        let all = vec![String::from("all")];
+
+        for range in self.ranges.as_ref().unwrap_or(&all) {
+            let (start, end) = EditConfig::frame_range(range, rpus.len())?;
+
+            for (rpu, other) in rpus[start..=end].iter_mut().zip(&other[start..=end]) {
+                if let Some(rpu) = rpu {
+                    self.merge_frame(rpu, other, &levels)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn merge_frame(&self, rpu: &mut DoviRpu, other: &DoviRpu, levels: &[u8]) -> Result<()> {
+        rpu.modified = true;
+
+        if self.mapping {
+            let vdr_dm_metadata_present_flag = rpu.header.vdr_dm_metadata_present_flag;
+
+            rpu.dovi_profile = other.dovi_profile;
+            rpu.header = other.header.clone();
+            rpu.header.vdr_dm_metadata_present_flag = vdr_dm_metadata_present_flag;
+            rpu.rpu_data_mapping = other.rpu_data_mapping.clone();
+            rpu.rpu_data_nlq = other.rpu_data_nlq.clone();
+        }
+
+        if self.vdr_dm_data {
+            rpu.vdr_dm_data = other.vdr_dm_data.clone();
+            rpu.header.vdr_dm_metadata_present_flag = rpu.vdr_dm_data.is_some();
+        }
+
+        if levels.is_empty() {
+            return Ok(());
+        }
+
+        let (vdr_dm_data, other_dm_data) = match (&mut rpu.vdr_dm_data, &other.vdr_dm_data) {
+            (Some(vdr_dm_data), Some(other_dm_data)) => (vdr_dm_data, other_dm_data),
+            _ => bail!("merge: no DM metadata to merge the levels"),
+        };
+
+        let mut source = other_dm_data.clone();
+
+        // CM v4.0 levels from a CM v2.9 RPU, the L2 trims are converted to L8
+        if source.cmv40_metadata.is_none()
+            && levels
+                .iter()
+                .any(|level| CmV40DmData::ALLOWED_BLOCK_LEVELS.contains(level))
+        {
+            source
+                .convert_to_cmv40(merge_source_primary_index(vdr_dm_data, Some(other_dm_data)))?;
+        }
+
+        for level in levels {
+            vdr_dm_data.remove_metadata_level(*level);
+
+            for block in source.level_blocks_iter(*level) {
+                vdr_dm_data.add_metadata_block(block.clone())?;
+            }
+        }
+
+        vdr_dm_data.validate()
+    }
+}
+
+/// L9 source primaries of the base RPU, then of the merged RPU, P3 D65 by default
+fn merge_source_primary_index(vdr_dm_data: &VdrDmData, other_dm_data: Option<&VdrDmData>) -> u8 {
+    [Some(vdr_dm_data), other_dm_data]
+        .iter()
+        .flatten()
+        .find_map(|dm_data| match dm_data.get_block(9) {
+            Some(ExtMetadataBlock::Level9(level9)) => Some(level9.source_primary_index),
+            _ => None,
+        })
+        .unwrap_or_else(|| ExtMetadataBlockLevel9::default_dci_p3().source_primary_index)
+}
+
+impl FrameRateConversion {
+    fn execute(&self, rpus: &[DoviRpu]) -> Result<Vec<DoviRpu>> {
+        let frame_map = self.frame_map(rpus.len())?;
+
+        println!(
+            "Converting frame rate: {} frames to {} frames",
+            rpus.len(),
+            frame_map.len()
+        );
+
+        let is_scene_cut = |rpu: &DoviRpu| {
+            rpu.vdr_dm_data
+                .as_ref()
+                .map_or(false, |vdr_dm_data| vdr_dm_data.scene_refresh_flag == 1)
+        };
+
+        let mut converted = Vec::with_capacity(frame_map.len());
+        let mut previous: Option<usize> = None;
+
+        for source in frame_map {
+            let mut rpu = rpus[source].clone();
+
+            // Repeated frames continue the shot, and cuts on dropped frames move to the next one
+            let first = previous.map_or(0, |p| p + 1);
+            let scene_cut =
+                previous != Some(source) && rpus[first..=source].iter().any(is_scene_cut);
+
+            if scene_cut != is_scene_cut(&rpu) {
+                if let Some(ref mut vdr_dm_data) = rpu.vdr_dm_data {
+                    vdr_dm_data.set_scene_cut(scene_cut);
+                    rpu.modified = true;
+                }
+            }
+
+            converted.push(rpu);
+            previous = Some(source);
+        }
+
+        Ok(converted)
+    }
+
+    /// Source frame of every output frame
+    fn frame_map(&self, frame_count: usize) -> Result<Vec<usize>> {
+        if let Some(cadence) = &self.cadence {
+            ensure!(
+                self.source_fps.is_none() && self.target_fps.is_none(),
+                "Frame rate conversion: cadence and frame rates cannot be used together"
+            );
+            ensure!(
+                cadence.iter().sum::<usize>() > 0,
+                "Frame rate conversion: invalid cadence {:?}",
+                cadence
+            );
+
+            return Ok((0..frame_count)
+                .zip(cadence.iter().cycle())
+                .flat_map(|(frame, repeat)| std::iter::repeat(frame).take(*repeat))
+                .collect());
+        }
+
+        let (source_fps, target_fps) = match (&self.source_fps, &self.target_fps) {
+            (Some(source), Some(target)) => {
+                (source.parse::<FrameRate>()?, target.parse::<FrameRate>()?)
+            }
+            _ => {
+                bail!("Frame rate conversion: source_fps and target_fps or a cadence are required")
+            }
+        };
+
+        // Output frame k shows the source frame at k * source / target
+        let num = source_fps.num as u128 * target_fps.den as u128;
+        let den = source_fps.den as u128 * target_fps.num as u128;
+
+        let output_count = (frame_count as u128 * den + num - 1) / num;
+
+        Ok((0..output_count)
+            .map(|k| (k * num / den) as usize)
+            .collect())
+    }
+}
+
 impl ActiveArea {
-    fn execute(&self, rpus: &mut Vec<Option<DoviRpu>>) -> Result<()> {
+    fn execute(&self, rpus: &mut [Option<DoviRpu>]) -> Result<()> {
         if self.crop {
             self.crop(rpus)?;
         }
@@ -351,7 +1248,7 @@ impl ActiveArea {
         Ok(())
     }
 
-    fn crop(&self, rpus: &mut Vec<Option<DoviRpu>>) -> Result<()> {
+    fn crop(&self, rpus: &mut [Option<DoviRpu>]) -> Result<()> {
         println!("Cropping...");
         for rpu in rpus.iter_mut().filter_map(|e| e.as_mut()) {
             rpu.crop()?;
@@ -360,11 +1257,7 @@ impl ActiveArea {
         Ok(())
     }
 
-    fn do_edits(
-        &self,
-        edits: &HashMap<String, u16>,
-        rpus: &mut Vec<Option<DoviRpu>>,
-    ) -> Result<()> {
+    fn do_edits(&self, edits: &HashMap<String, u16>, rpus: &mut [Option<DoviRpu>]) -> Result<()> {
         if let Some(presets) = &self.presets {
             println!("Editing active area offsets...");
 
@@ -378,7 +1271,7 @@ impl ActiveArea {
 
                 let preset_id = *edit.1;
 
-                if end as usize > rpus.len() {
+                if end > rpus.len() {
                     bail!("Invalid range: {} > {} available RPUs", end, rpus.len());
                 }
 
@@ -408,7 +1301,7 @@ impl ActiveArea {
         Ok(())
     }
 
-    fn drop_specific_l5(&self, drop_opt: &str, rpus: &mut Vec<Option<DoviRpu>>) -> Result<()> {
+    fn drop_specific_l5(&self, drop_opt: &str, rpus: &mut [Option<DoviRpu>]) -> Result<()> {
         let param = drop_opt.to_lowercase();
 
         println!("Dropping L5 metadata with opt '{}'", param);
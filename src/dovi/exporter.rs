@@ -1,32 +1,110 @@
+use std::collections::BTreeSet;
 use std::fs::File;
-use std::io::BufWriter;
-use std::path::PathBuf;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
 
-use anyhow::Result;
+use anyhow::{bail, Result};
 use serde::ser::SerializeSeq;
 use serde::Serializer;
 
-use crate::dovi::parse_rpu_file;
+use dolby_vision::rpu::extension_metadata::blocks::ExtMetadataBlock;
+use dolby_vision::rpu::extension_metadata::TargetDisplay;
+use dolby_vision::utils::pq_to_nits;
+
+use crate::dovi::{parse_rpu_file, rpu_to_json_with_targets};
 
 use super::DoviRpu;
 
+/// Column groups of the CSV/TSV export, in order
+const COLUMN_GROUPS: &[&str] = &[
+    "frame",
+    "scene_cut",
+    "profile",
+    "cm_version",
+    "l1",
+    "l2",
+    "l3",
+    "l4",
+    "l5",
+    "l6",
+    "l8",
+    "l9",
+    "l11",
+];
+
+const TRIM_COLUMNS: &[&str] = &[
+    "slope",
+    "offset",
+    "power",
+    "chroma_weight",
+    "saturation_gain",
+    "ms_weight",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ExportFormat {
+    Json,
+    Csv,
+    Tsv,
+}
+
 pub struct Exporter {
     input: PathBuf,
     output: PathBuf,
+    format: ExportFormat,
+    columns: Vec<String>,
     rpus: Option<Vec<DoviRpu>>,
 }
 
+/// Trim targets present in the RPUs, for the L2 and L8 columns
+struct TrimTargets {
+    /// L2 target peak brightness, in nits
+    level2: BTreeSet<u16>,
+
+    /// L8 target display index
+    level8: BTreeSet<u8>,
+}
+
 impl Exporter {
-    pub fn export(input: PathBuf, output: Option<PathBuf>) -> Result<()> {
+    pub fn export(
+        input: PathBuf,
+        output: Option<PathBuf>,
+        columns: Option<Vec<String>>,
+    ) -> Result<()> {
         let out_path = if let Some(out_path) = output {
             out_path
         } else {
             PathBuf::from("RPU_export.json".to_string())
         };
 
+        let format = ExportFormat::from_path(&out_path);
+
+        let columns = match columns {
+            Some(columns) => {
+                if format == ExportFormat::Json {
+                    bail!("Columns can only be selected for a CSV or TSV export");
+                }
+
+                for column in &columns {
+                    if !COLUMN_GROUPS.contains(&column.as_str()) {
+                        bail!(
+                            "Invalid column {}, available columns: {}",
+                            column,
+                            COLUMN_GROUPS.join(", ")
+                        );
+                    }
+                }
+
+                columns
+            }
+            None => COLUMN_GROUPS.iter().map(|c| c.to_string()).collect(),
+        };
+
         let mut exporter = Exporter {
             input,
             output: out_path,
+            format,
+            columns,
             rpus: None,
         };
 
@@ -47,15 +125,286 @@ impl Exporter {
                 File::create(&self.output).expect("Can't create file"),
             );
 
-            let mut ser = serde_json::Serializer::new(writer);
-            let mut seq = ser.serialize_seq(Some(rpus.len()))?;
+            match self.format {
+                ExportFormat::Json => {
+                    let mut ser = serde_json::Serializer::new(writer);
+                    let mut seq = ser.serialize_seq(Some(rpus.len()))?;
 
-            for rpu in rpus {
-                seq.serialize_element(&rpu)?;
+                    for rpu in rpus {
+                        seq.serialize_element(&rpu_to_json_with_targets(rpu)?)?;
+                    }
+                    seq.end()?;
+                }
+                ExportFormat::Csv => self.write_table(rpus, writer, ",")?,
+                ExportFormat::Tsv => self.write_table(rpus, writer, "\t")?,
             }
-            seq.end()?;
         }
 
         Ok(())
     }
+
+    /// Writes one row per frame, with empty cells for missing metadata
+    fn write_table<W: Write>(
+        &self,
+        rpus: &[DoviRpu],
+        mut writer: W,
+        delimiter: &str,
+    ) -> Result<()> {
+        let targets = TrimTargets::from_rpus(rpus);
+
+        let header: Vec<String> = self
+            .columns
+            .iter()
+            .flat_map(|group| targets.headers(group))
+            .collect();
+        writeln!(writer, "{}", header.join(delimiter))?;
+
+        for (frame, rpu) in rpus.iter().enumerate() {
+            let row: Vec<String> = self
+                .columns
+                .iter()
+                .flat_map(|group| targets.cells(group, frame, rpu))
+                .collect();
+            writeln!(writer, "{}", row.join(delimiter))?;
+        }
+
+        writer.flush()?;
+
+        Ok(())
+    }
+}
+
+impl ExportFormat {
+    fn from_path(path: &Path) -> ExportFormat {
+        let extension = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| ext.to_ascii_lowercase());
+
+        match extension.as_deref() {
+            Some("csv") => ExportFormat::Csv,
+            Some("tsv") => ExportFormat::Tsv,
+            _ => ExportFormat::Json,
+        }
+    }
+}
+
+impl TrimTargets {
+    fn from_rpus(rpus: &[DoviRpu]) -> TrimTargets {
+        let mut targets = TrimTargets {
+            level2: BTreeSet::new(),
+            level8: BTreeSet::new(),
+        };
+
+        for vdr_dm_data in rpus.iter().filter_map(|rpu| rpu.vdr_dm_data.as_ref()) {
+            for block in vdr_dm_data.level_blocks_iter(2) {
+                if let ExtMetadataBlock::Level2(b) = block {
+                    targets
+                        .level2
+                        .insert(TargetDisplay::peak_nits_from_max_pq(b.target_max_pq));
+                }
+            }
+
+            for block in vdr_dm_data.level_blocks_iter(8) {
+                if let ExtMetadataBlock::Level8(b) = block {
+                    targets.level8.insert(b.target_display_index);
+                }
+            }
+        }
+
+        targets
+    }
+
+    fn headers(&self, group: &str) -> Vec<String> {
+        let names: Vec<String> = match group {
+            "frame" | "scene_cut" | "profile" | "cm_version" => return vec![group.to_string()],
+            "l1" => [
+                "min_pq", "avg_pq", "max_pq", "min_nits", "avg_nits", "max_nits",
+            ]
+            .iter()
+            .map(|c| c.to_string())
+            .collect(),
+            "l2" => trim_headers(self.level2.iter()),
+            "l3" => ["min_pq_offset", "max_pq_offset", "avg_pq_offset"]
+                .iter()
+                .map(|c| c.to_string())
+                .collect(),
+            "l4" => vec!["anchor_pq".into(), "anchor_power".into()],
+            "l5" => ["left", "right", "top", "bottom"]
+                .iter()
+                .map(|c| c.to_string())
+                .collect(),
+            "l6" => ["max_mdl", "min_mdl", "max_cll", "max_fall"]
+                .iter()
+                .map(|c| c.to_string())
+                .collect(),
+            "l8" => trim_headers(self.level8.iter()),
+            "l9" => vec!["source_primary_index".into()],
+            "l11" => ["content_type", "whitepoint", "reference_mode_flag"]
+                .iter()
+                .map(|c| c.to_string())
+                .collect(),
+            _ => Vec::new(),
+        };
+
+        names
+            .iter()
+            .map(|name| format!("{}_{}", group, name))
+            .collect()
+    }
+
+    fn cells(&self, group: &str, frame: usize, rpu: &DoviRpu) -> Vec<String> {
+        let vdr_dm_data = rpu.vdr_dm_data.as_ref();
+        let block = |level: u8| vdr_dm_data.and_then(|vdr_dm_data| vdr_dm_data.get_block(level));
+
+        match group {
+            "frame" => vec![frame.to_string()],
+            "scene_cut" => vec![cell(
+                vdr_dm_data.map(|vdr_dm_data| vdr_dm_data.scene_refresh_flag),
+            )],
+            "profile" => vec![rpu.dovi_profile.to_string()],
+            "cm_version" => vec![cell(vdr_dm_data.map(|vdr_dm_data| {
+                if vdr_dm_data.cmv40_metadata.is_some() {
+                    "4.0"
+                } else {
+                    "2.9"
+                }
+            }))],
+            "l1" => match block(1) {
+                Some(ExtMetadataBlock::Level1(b)) => {
+                    let pq = [b.min_pq, b.avg_pq, b.max_pq];
+                    let nits = pq
+                        .iter()
+                        .map(|pq| format!("{:.4}", pq_to_nits(*pq as f64 / 4095.0)));
+
+                    pq.iter().map(|pq| pq.to_string()).chain(nits).collect()
+                }
+                _ => empty_cells(6),
+            },
+            "l2" => self
+                .level2
+                .iter()
+                .flat_map(|target_nits| {
+                    let trim = vdr_dm_data.and_then(|vdr_dm_data| {
+                        vdr_dm_data
+                            .level_blocks_iter(2)
+                            .find_map(|block| match block {
+                                ExtMetadataBlock::Level2(b)
+                                    if TargetDisplay::peak_nits_from_max_pq(b.target_max_pq)
+                                        == *target_nits =>
+                                {
+                                    Some(b)
+                                }
+                                _ => None,
+                            })
+                    });
+
+                    match trim {
+                        Some(b) => vec![
+                            b.trim_slope.to_string(),
+                            b.trim_offset.to_string(),
+                            b.trim_power.to_string(),
+                            b.trim_chroma_weight.to_string(),
+                            b.trim_saturation_gain.to_string(),
+                            b.ms_weight.to_string(),
+                        ],
+                        None => empty_cells(TRIM_COLUMNS.len()),
+                    }
+                })
+                .collect(),
+            "l3" => match block(3) {
+                Some(ExtMetadataBlock::Level3(b)) => vec![
+                    b.min_pq_offset.to_string(),
+                    b.max_pq_offset.to_string(),
+                    b.avg_pq_offset.to_string(),
+                ],
+                _ => empty_cells(3),
+            },
+            "l4" => match block(4) {
+                Some(ExtMetadataBlock::Level4(b)) => {
+                    vec![b.anchor_pq.to_string(), b.anchor_power.to_string()]
+                }
+                _ => empty_cells(2),
+            },
+            "l5" => match block(5) {
+                Some(ExtMetadataBlock::Level5(b)) => vec![
+                    b.active_area_left_offset.to_string(),
+                    b.active_area_right_offset.to_string(),
+                    b.active_area_top_offset.to_string(),
+                    b.active_area_bottom_offset.to_string(),
+                ],
+                _ => empty_cells(4),
+            },
+            "l6" => match block(6) {
+                Some(ExtMetadataBlock::Level6(b)) => vec![
+                    b.max_display_mastering_luminance.to_string(),
+                    b.min_display_mastering_luminance.to_string(),
+                    b.max_content_light_level.to_string(),
+                    b.max_frame_average_light_level.to_string(),
+                ],
+                _ => empty_cells(4),
+            },
+            "l8" => self
+                .level8
+                .iter()
+                .flat_map(|index| {
+                    let trim = vdr_dm_data.and_then(|vdr_dm_data| {
+                        vdr_dm_data
+                            .level_blocks_iter(8)
+                            .find_map(|block| match block {
+                                ExtMetadataBlock::Level8(b) if b.target_display_index == *index => {
+                                    Some(b)
+                                }
+                                _ => None,
+                            })
+                    });
+
+                    match trim {
+                        Some(b) => vec![
+                            b.trim_slope.to_string(),
+                            b.trim_offset.to_string(),
+                            b.trim_power.to_string(),
+                            b.trim_chroma_weight.to_string(),
+                            b.trim_saturation_gain.to_string(),
+                            b.ms_weight.to_string(),
+                        ],
+                        None => empty_cells(TRIM_COLUMNS.len()),
+                    }
+                })
+                .collect(),
+            "l9" => match block(9) {
+                Some(ExtMetadataBlock::Level9(b)) => vec![b.source_primary_index.to_string()],
+                _ => empty_cells(1),
+            },
+            "l11" => match block(11) {
+                Some(ExtMetadataBlock::Level11(b)) => vec![
+                    b.content_type.to_string(),
+                    b.whitepoint.to_string(),
+                    (b.reference_mode_flag as u8).to_string(),
+                ],
+                _ => empty_cells(3),
+            },
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// Trim columns by target, e.g. `600_slope`
+fn trim_headers<T: ToString>(targets: impl Iterator<Item = T>) -> Vec<String> {
+    targets
+        .flat_map(|target| {
+            let target = target.to_string();
+            TRIM_COLUMNS
+                .iter()
+                .map(move |column| format!("{}_{}", target, column))
+        })
+        .collect()
+}
+
+fn cell<T: ToString>(value: Option<T>) -> String {
+    value.map(|v| v.to_string()).unwrap_or_default()
+}
+
+fn empty_cells(count: usize) -> Vec<String> {
+    vec![String::new(); count]
 }
@@ -1,13 +1,21 @@
-use anyhow::{bail, Result};
+use anyhow::{bail, ensure, format_err, Result};
 use serde_json::Value;
 use std::fs::File;
 use std::io::{stdout, Read, Write};
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
 use crate::commands::Command;
+
+use super::hevc_info::SpsInfo;
+use super::sei::Hdr10SeiScan;
+use super::temporal::TemporalConfig;
+use super::video_analysis::{self, YuvReader};
+use super::{write_rpu_file, DoviRpu};
 use dolby_vision::rpu::extension_metadata::blocks::{
-    ExtMetadataBlock, ExtMetadataBlockLevel1, ExtMetadataBlockLevel6,
+    ExtMetadataBlock, ExtMetadataBlockLevel1, ExtMetadataBlockLevel6, ExtMetadataBlockLevel9,
 };
+use dolby_vision::rpu::extension_metadata::target_display::TargetPrimaries;
 use dolby_vision::rpu::generate::{GenerateConfig, ShotFrameEdit, VideoShot};
 use dolby_vision::utils::nits_to_pq;
 use dolby_vision::xml::{CmXmlParser, XmlParserOpts};
@@ -19,10 +27,44 @@ pub struct Generator {
     xml_path: Option<PathBuf>,
     canvas_width: Option<u16>,
     canvas_height: Option<u16>,
+    hevc_path: Option<PathBuf>,
+    l6_from_sei: bool,
+    sps: Option<SpsInfo>,
     madvr_path: Option<PathBuf>,
-    use_custom_targets: bool,
+    madvr_opts: MadVROptions,
+    temporal: TemporalConfig,
+    video_path: Option<PathBuf>,
+    video_dimensions: Option<(usize, usize)>,
+    scene_threshold: f64,
+}
+
+/// How the L1 metadata is derived from madVR measurements
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MadVRMode {
+    /// Scene peak and max frame average, for every frame of the scene
+    Scene,
+
+    /// Per-frame peak, with the average and min from the frame histograms
+    Frame,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct MadVROptions {
+    pub mode: MadVRMode,
+
+    /// Use the custom per-frame target nits as L1 max, if available
+    pub use_custom_targets: bool,
+
+    /// Set the L9 source primaries from the out of gamut peaks
+    pub level9_from_gamut: bool,
 }
 
+/// Share of the frame below the histogram min, in percent
+const MADVR_MIN_PERCENT: f64 = 0.1;
+
+/// Out of gamut peaks below this are ignored for L9
+const MADVR_GAMUT_PEAK_NITS: u16 = 1;
+
 impl Generator {
     pub fn generate(cmd: Command) -> Result<()> {
         if let Command::Generate {
@@ -32,8 +74,19 @@ impl Generator {
             xml,
             canvas_width,
             canvas_height,
+            hevc,
+            l6_from_sei,
             madvr_file,
             use_custom_targets,
+            madvr_mode,
+            madvr_l9,
+            smooth_l1_window,
+            smooth_across_scenes,
+            l4_window,
+            video,
+            video_width,
+            video_height,
+            scene_threshold,
         } = cmd
         {
             let out_path = if let Some(out_path) = rpu_out {
@@ -42,38 +95,80 @@ impl Generator {
                 PathBuf::from("RPU_generated.bin".to_string())
             };
 
+            let sps = if let Some(hevc_path) = &hevc {
+                let sps = SpsInfo::from_hevc_file(hevc_path)?;
+                sps.print_summary();
+
+                Some(sps)
+            } else {
+                None
+            };
+
+            // Default the canvas to the picture size
+            let (sps_width, sps_height) = match &sps {
+                Some(sps) => {
+                    let (width, height) = sps.display_size();
+                    (Some(width as u16), Some(height as u16))
+                }
+                None => (None, None),
+            };
+
             let generator = Generator {
                 json_path: json_file,
                 rpu_out: out_path,
                 hdr10plus_path: hdr10plus_json,
                 xml_path: xml,
-                canvas_width,
-                canvas_height,
+                canvas_width: canvas_width.or(sps_width),
+                canvas_height: canvas_height.or(sps_height),
+                hevc_path: hevc,
+                l6_from_sei,
+                sps,
                 madvr_path: madvr_file,
-                use_custom_targets,
+                madvr_opts: MadVROptions {
+                    mode: madvr_mode,
+                    use_custom_targets,
+                    level9_from_gamut: madvr_l9,
+                },
+                temporal: TemporalConfig {
+                    smooth_l1_window,
+                    smooth_across_scenes,
+                    level4_window: l4_window,
+                },
+                video_path: video,
+                video_dimensions: video_width.zip(video_height),
+                scene_threshold,
             };
 
-            let config = if let Some(json_path) = &generator.json_path {
+            let mut config = if let Some(json_path) = &generator.json_path {
                 let json_file = File::open(json_path)?;
                 let mut config: GenerateConfig = serde_json::from_reader(&json_file)?;
 
-                if let Some(hdr10plus_path) = &generator.hdr10plus_path {
+                if let Some(video_path) = &generator.video_path {
+                    generator.generate_metadata_from_video(video_path, &mut config)?;
+                } else if let Some(hdr10plus_path) = &generator.hdr10plus_path {
                     parse_hdr10plus_for_l1(hdr10plus_path, &mut config)?;
                 } else if let Some(madvr_path) = &generator.madvr_path {
-                    generate_metadata_from_madvr(
-                        madvr_path,
-                        generator.use_custom_targets,
-                        &mut config,
-                    )?;
+                    generate_metadata_from_madvr(madvr_path, &generator.madvr_opts, &mut config)?;
                 }
 
                 config
             } else if let Some(xml_path) = &generator.xml_path {
                 generator.config_from_xml(xml_path)?
+            } else if let Some(video_path) = &generator.video_path {
+                let mut config = GenerateConfig::default();
+                generator.generate_metadata_from_video(video_path, &mut config)?;
+
+                config
             } else {
                 bail!("Missing configuration or XML file!");
             };
 
+            if let Some(hevc_path) = &generator.hevc_path {
+                if generator.l6_from_sei {
+                    set_static_metadata_from_seis(hevc_path, &mut config)?;
+                }
+            }
+
             generator.execute(&config)?;
 
             println!("Done.");
@@ -85,7 +180,19 @@ impl Generator {
     fn execute(&self, config: &GenerateConfig) -> Result<()> {
         println!("Generating metadata...");
 
-        config.write_rpus(&self.rpu_out)?;
+        let mut rpus = config.generate_rpu_list()?;
+
+        if self.temporal.is_enabled() {
+            let mut frames: Vec<&mut DoviRpu> = rpus.iter_mut().collect();
+            self.temporal.execute(&mut frames)?;
+        }
+
+        if let Some(sps) = &self.sps {
+            sps.validate_rpus(&rpus);
+        }
+
+        let data = GenerateConfig::encode_rpus(&mut rpus);
+        write_rpu_file(&self.rpu_out, data)?;
 
         println!("Generated metadata for {} frames", config.length);
 
@@ -104,6 +211,40 @@ impl Generator {
 
         Ok(parser.config)
     }
+
+    fn generate_metadata_from_video(
+        &self,
+        video_path: &Path,
+        config: &mut GenerateConfig,
+    ) -> Result<()> {
+        println!("Measuring video frames...");
+        stdout().flush().ok();
+
+        let mut reader = YuvReader::open(video_path, self.video_dimensions)?;
+        let measurements = video_analysis::measure_video(&mut reader)?;
+
+        ensure!(!measurements.is_empty(), "No video frames to measure");
+
+        let scene_cuts = if config.shots.is_empty() {
+            video_analysis::detect_scene_cuts(&measurements, self.scene_threshold)
+        } else {
+            println!(
+                "Using the {} shots of the configuration",
+                config.shots.len()
+            );
+            Vec::new()
+        };
+
+        video_analysis::generate_shots(&measurements, &scene_cuts, config)?;
+
+        println!(
+            "Measured {} frames, {} scenes",
+            measurements.len(),
+            config.shots.len()
+        );
+
+        Ok(())
+    }
 }
 
 fn parse_hdr10plus_for_l1(hdr10plus_path: &Path, config: &mut GenerateConfig) -> Result<()> {
@@ -194,9 +335,89 @@ fn parse_hdr10plus_for_l1(hdr10plus_path: &Path, config: &mut GenerateConfig) ->
     Ok(())
 }
 
+/// Sets L6 and the L9 primaries from the HDR10 SEIs of the HEVC file.
+/// A warning is printed for every configuration value replaced by a different one.
+pub fn set_static_metadata_from_seis(hevc_path: &Path, config: &mut GenerateConfig) -> Result<()> {
+    let scan = Hdr10SeiScan::scan_file(hevc_path)?;
+
+    let replace = |name: &str, value: &mut u16, sei_value: u16| {
+        if *value != sei_value {
+            println!(
+                "Warning: replacing {} {} with {} from the HDR10 SEIs",
+                name, value, sei_value
+            );
+            *value = sei_value;
+        }
+    };
+
+    let level6 = &mut config.level6;
+
+    if let Some(mdcv) = &scan.mastering_display {
+        replace(
+            "L6 max mastering display luminance",
+            &mut level6.max_display_mastering_luminance,
+            (mdcv.max_luminance as f64 / 10_000.0).round() as u16,
+        );
+        replace(
+            "L6 min mastering display luminance",
+            &mut level6.min_display_mastering_luminance,
+            mdcv.min_luminance.min(u16::MAX as u32) as u16,
+        );
+
+        match mdcv.primaries() {
+            Some(primaries) => {
+                let source_primary_index = primaries.index();
+
+                if let Some(level9) = &config.level9 {
+                    if level9.source_primary_index != source_primary_index {
+                        println!(
+                            "Warning: replacing L9 source primaries {} with {} from the HDR10 SEIs",
+                            level9.source_primary_index, source_primary_index
+                        );
+                    }
+                }
+
+                config.level9 = Some(ExtMetadataBlockLevel9 {
+                    source_primary_index,
+                })
+            }
+            None => println!("Warning: mastering display primaries don't match a L9 preset"),
+        }
+    }
+
+    if let Some(cll) = &scan.content_light_level {
+        replace(
+            "L6 MaxCLL",
+            &mut level6.max_content_light_level,
+            cll.max_content_light_level,
+        );
+        replace(
+            "L6 MaxFALL",
+            &mut level6.max_frame_average_light_level,
+            cll.max_pic_average_light_level,
+        );
+    }
+
+    if scan.mastering_display.is_none() && scan.content_light_level.is_none() {
+        println!("Warning: no HDR10 SEIs found, L6 is unchanged");
+    } else {
+        let level6 = &config.level6;
+
+        println!(
+            "L6 from HDR10 SEIs: mastering display {}/{}, MaxCLL {}, MaxFALL {}",
+            level6.max_display_mastering_luminance,
+            level6.min_display_mastering_luminance,
+            level6.max_content_light_level,
+            level6.max_frame_average_light_level
+        );
+    }
+
+    Ok(())
+}
+
 pub fn generate_metadata_from_madvr(
     madvr_path: &Path,
-    use_custom_targets: bool,
+    opts: &MadVROptions,
     config: &mut GenerateConfig,
 ) -> Result<()> {
     println!("Parsing madVR measurement file...");
@@ -206,18 +427,71 @@ pub fn generate_metadata_from_madvr(
 
     let madvr_info = madvr_parse::MadVRMeasurements::parse_file(madvr_path)?;
 
+    if madvr_info.header.version > madvr_parse::LATEST_VERSION {
+        println!(
+            "Warning: madVR measurement file version {} is newer than supported, assuming version {} measurements",
+            madvr_info.header.version,
+            madvr_parse::LATEST_VERSION
+        );
+    }
+
+    if !madvr_info.is_complete() {
+        let measured = madvr_info.frames.len();
+        let missing = madvr_info.missing_frames();
+
+        ensure!(
+            measured > 0,
+            "Incomplete madVR measurement file, no frames measured"
+        );
+
+        if missing > 0 {
+            println!(
+                "Warning: incomplete madVR measurement file, {} of {} frames measured. Generating metadata for frames 0-{}, missing frames {}-{}",
+                measured,
+                madvr_info.header.frame_count,
+                measured - 1,
+                measured,
+                measured + missing - 1
+            );
+        }
+    }
+
+    // Not written yet in incomplete files
+    let maxcll = if madvr_info.header.maxcll > 0 {
+        madvr_info.header.maxcll
+    } else {
+        madvr_info
+            .scenes
+            .iter()
+            .map(|s| s.peak_nits)
+            .max()
+            .unwrap_or(0)
+    };
+
     let level6_meta = ExtMetadataBlockLevel6 {
-        max_content_light_level: madvr_info.header.maxcll as u16,
+        max_content_light_level: maxcll as u16,
         max_frame_average_light_level: madvr_info.header.maxfall as u16,
         ..Default::default()
     };
 
     let frame_count = madvr_info.frames.len();
+    let use_custom_targets = opts.use_custom_targets && madvr_info.header.flags == 3;
+
+    let to_pq = |v: f64| (v * 4095.0).round() as u16;
 
     for s in madvr_info.scenes.iter() {
-        let min_pq = 0;
-        let max_pq = (s.max_pq * 4095.0).round() as u16;
-        let avg_pq = (s.avg_pq * 4095.0).round() as u16;
+        let frames = s.get_frames(frame_count, &madvr_info.frames)?;
+
+        let min_pq = match opts.mode {
+            MadVRMode::Scene => 0,
+            MadVRMode::Frame => frames
+                .iter()
+                .map(|f| to_pq(f.lum_histogram_min_pq(MADVR_MIN_PERCENT)))
+                .min()
+                .unwrap_or(0),
+        };
+        let max_pq = to_pq(s.max_pq);
+        let avg_pq = to_pq(s.avg_pq);
 
         let mut shot = VideoShot {
             start: s.start as usize,
@@ -228,28 +502,49 @@ pub fn generate_metadata_from_madvr(
             ..Default::default()
         };
 
-        if use_custom_targets && madvr_info.header.flags == 3 {
-            // Use peak per frame, average from scene
-            let frames = s.get_frames(frame_count, &madvr_info.frames)?;
-
+        if use_custom_targets || opts.mode == MadVRMode::Frame {
             frames.iter().enumerate().for_each(|(i, f)| {
-                let min_pq = 0;
-                let max_pq = (f.target_pq * 4095.0).round() as u16;
-                let avg_pq = (s.avg_pq * 4095.0).round() as u16;
+                let max_pq = if use_custom_targets {
+                    to_pq(f.target_pq)
+                } else {
+                    to_pq(f.peak_pq_2020)
+                };
+
+                // Scene mode: peak per frame, average from scene
+                let (min_pq, avg_pq) = match opts.mode {
+                    MadVRMode::Scene => (0, to_pq(s.avg_pq)),
+                    MadVRMode::Frame => (
+                        to_pq(f.lum_histogram_min_pq(MADVR_MIN_PERCENT)),
+                        to_pq(f.avg_pq),
+                    ),
+                };
 
                 shot.frame_edits.push(ShotFrameEdit {
                     edit_offset: i,
                     metadata_blocks: vec![ExtMetadataBlock::Level1(
-                        ExtMetadataBlockLevel1::from_stats(min_pq, max_pq, avg_pq),
+                        ExtMetadataBlockLevel1::from_stats(min_pq.min(avg_pq), max_pq, avg_pq),
                     )],
+                    ..Default::default()
                 });
             });
-        } else {
         };
 
         config.shots.push(shot);
     }
 
+    if opts.level9_from_gamut {
+        let primaries = madvr_source_primaries(&madvr_info)?;
+
+        println!(
+            "L9 source primaries from madVR gamut peaks: {:?}",
+            primaries
+        );
+
+        config.level9 = Some(ExtMetadataBlockLevel9 {
+            source_primary_index: primaries.index(),
+        });
+    }
+
     // Set MaxCLL and MaxFALL if not set in config
     if config.level6.max_content_light_level == 0 {
         config.level6.max_content_light_level = level6_meta.max_content_light_level;
@@ -263,3 +558,48 @@ pub fn generate_metadata_from_madvr(
 
     Ok(())
 }
+
+/// Smallest gamut containing the content, from the peaks of the pixels outside of DCI-P3 and BT.709
+fn madvr_source_primaries(madvr_info: &madvr_parse::MadVRMeasurements) -> Result<TargetPrimaries> {
+    ensure!(
+        madvr_info.header.version >= 6,
+        "madVR measurement file version {} has no gamut peaks, version 6 is required for L9",
+        madvr_info.header.version
+    );
+
+    let threshold = nits_to_pq(MADVR_GAMUT_PEAK_NITS);
+    let out_of_gamut = |peak: Option<f64>| peak.map_or(false, |pq| pq > threshold);
+
+    let primaries = if madvr_info
+        .frames
+        .iter()
+        .any(|f| out_of_gamut(f.peak_pq_dcip3))
+    {
+        TargetPrimaries::Bt2020
+    } else if madvr_info
+        .frames
+        .iter()
+        .any(|f| out_of_gamut(f.peak_pq_709))
+    {
+        TargetPrimaries::DciP3D65
+    } else {
+        TargetPrimaries::Bt709
+    };
+
+    Ok(primaries)
+}
+
+impl FromStr for MadVRMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "scene" => Ok(MadVRMode::Scene),
+            "frame" => Ok(MadVRMode::Frame),
+            _ => Err(format_err!(
+                "Invalid madVR mode {}, expected scene or frame",
+                s
+            )),
+        }
+    }
+}
@@ -0,0 +1,351 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+use anyhow::{bail, ensure, Result};
+use serde_json::{json, Value};
+
+use dolby_vision::rpu::extension_metadata::blocks::{ExtMetadataBlock, ExtMetadataBlockLevel2};
+use dolby_vision::utils::{nits_to_pq, pq_to_nits};
+use hevc_parser::hevc::USER_DATA_REGISTERED_ITU_T_35;
+
+use super::sei::{SeiNal, SeiPayload};
+use super::{parse_rpu_file, DoviRpu, OUT_NAL_HEADER};
+use bitvec_helpers::bitvec_writer::BitVecWriter;
+
+/// Percentages of the maxRGB distribution percentiles
+const DISTRIBUTION_INDEX: [u8; 9] = [1, 5, 10, 25, 50, 75, 90, 95, 99];
+
+/// Values are in 0.1 nits, up to 10 000 nits
+const MAX_LUMINANCE_VALUE: u32 = 100_000;
+
+pub struct Hdr10PlusConverter;
+
+/// ST 2094-40 metadata of a frame, with a single window.
+/// Luminances are in 0.1 nits.
+#[derive(Debug, Clone, PartialEq)]
+struct Hdr10PlusFrame {
+    scene_id: usize,
+    scene_frame_index: usize,
+    maxscl: [u32; 3],
+    average_maxrgb: u32,
+    distribution_values: [u32; 9],
+    bezier_curve: Option<BezierCurve>,
+}
+
+/// Tone mapping curve to the target display.
+/// The knee point is in 1/4095 units, and the anchors in 1/1023 units.
+#[derive(Debug, Clone, PartialEq)]
+struct BezierCurve {
+    knee_point_x: u16,
+    knee_point_y: u16,
+    anchors: Vec<u16>,
+}
+
+impl Hdr10PlusConverter {
+    /// Writes the HDR10+ metadata derived from the RPU, as JSON or ST 2094-40 SEI NAL units
+    pub fn convert(input: PathBuf, output: Option<PathBuf>, target_nits: u16) -> Result<()> {
+        let output = output.unwrap_or_else(|| PathBuf::from("hdr10plus_metadata.json"));
+
+        ensure!(
+            (100..=10_000).contains(&target_nits),
+            "Invalid target nits {}, must be between 100 and 10000",
+            target_nits
+        );
+
+        let rpus = match parse_rpu_file(&input)? {
+            Some(rpus) => rpus,
+            None => bail!("No RPUs parsed from {}", input.display()),
+        };
+
+        println!("Converting {} frames to HDR10+...", rpus.len());
+
+        let frames = hdr10plus_frames(&rpus, target_nits)?;
+
+        let is_json = output
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map_or(false, |ext| ext.eq_ignore_ascii_case("json"));
+
+        let mut writer = BufWriter::with_capacity(100_000, File::create(&output)?);
+
+        if is_json {
+            serde_json::to_writer_pretty(&mut writer, &hdr10plus_json(&frames, target_nits))?;
+        } else {
+            for frame in &frames {
+                let sei = SeiNal::prefix(vec![SeiPayload {
+                    payload_type: USER_DATA_REGISTERED_ITU_T_35 as u16,
+                    data: frame.sei_payload(target_nits),
+                }]);
+
+                writer.write_all(OUT_NAL_HEADER)?;
+                writer.write_all(&sei.write())?;
+            }
+        }
+
+        writer.flush()?;
+
+        println!("HDR10+ metadata written to {}", output.display());
+
+        Ok(())
+    }
+}
+
+fn hdr10plus_frames(rpus: &[DoviRpu], target_nits: u16) -> Result<Vec<Hdr10PlusFrame>> {
+    let target_max_pq = (nits_to_pq(target_nits) * 4095.0).round() as u16;
+
+    let mut frames: Vec<Hdr10PlusFrame> = Vec::with_capacity(rpus.len());
+
+    for (i, rpu) in rpus.iter().enumerate() {
+        let vdr_dm_data = match &rpu.vdr_dm_data {
+            Some(vdr_dm_data) => vdr_dm_data,
+            None => bail!("Frame {}: no DM metadata", i),
+        };
+
+        let (min_pq, max_pq, avg_pq) = match vdr_dm_data.get_block(1) {
+            Some(ExtMetadataBlock::Level1(l1)) => (l1.min_pq, l1.max_pq, l1.avg_pq),
+            _ => bail!("Frame {}: no L1 metadata", i),
+        };
+
+        let (scene_id, scene_frame_index) = match frames.last() {
+            None => (0, 0),
+            Some(_) if vdr_dm_data.scene_refresh_flag == 1 => (frames[i - 1].scene_id + 1, 0),
+            Some(previous) => (previous.scene_id, previous.scene_frame_index + 1),
+        };
+
+        let maxscl = pq_to_value(max_pq as f64 / 4095.0);
+
+        let mut distribution_values = [0; 9];
+        for (value, percentage) in distribution_values
+            .iter_mut()
+            .zip(DISTRIBUTION_INDEX.iter())
+        {
+            *value = pq_to_value(estimated_percentile(min_pq, avg_pq, max_pq, *percentage));
+        }
+
+        let trim = vdr_dm_data.interpolated_trim(target_max_pq);
+
+        frames.push(Hdr10PlusFrame {
+            scene_id,
+            scene_frame_index,
+            maxscl: [maxscl; 3],
+            average_maxrgb: pq_to_value(avg_pq as f64 / 4095.0),
+            distribution_values,
+            bezier_curve: BezierCurve::from_trim(
+                pq_to_nits(max_pq as f64 / 4095.0),
+                pq_to_nits(avg_pq as f64 / 4095.0),
+                target_nits as f64,
+                &trim,
+            ),
+        });
+    }
+
+    Ok(frames)
+}
+
+/// Common HDR10+ JSON layout, as read by the generator
+fn hdr10plus_json(frames: &[Hdr10PlusFrame], target_nits: u16) -> Value {
+    let scene_info: Vec<Value> = frames
+        .iter()
+        .enumerate()
+        .map(|(i, frame)| {
+            let mut info = json!({
+                "LuminanceParameters": {
+                    "AverageRGB": frame.average_maxrgb,
+                    "LuminanceDistributions": {
+                        "DistributionIndex": DISTRIBUTION_INDEX,
+                        "DistributionValues": frame.distribution_values,
+                    },
+                    "MaxScl": frame.maxscl,
+                },
+                "NumberOfWindows": 1,
+                "TargetedSystemDisplayMaximumLuminance": target_nits,
+                "SceneFrameIndex": frame.scene_frame_index,
+                "SceneId": frame.scene_id,
+                "SequenceFrameIndex": i,
+            });
+
+            if let Some(curve) = &frame.bezier_curve {
+                info["BezierCurveData"] = json!({
+                    "Anchors": curve.anchors,
+                    "KneePointX": curve.knee_point_x,
+                    "KneePointY": curve.knee_point_y,
+                });
+            }
+
+            info
+        })
+        .collect();
+
+    let mut scene_first_frames = Vec::new();
+    let mut scene_lengths: Vec<usize> = Vec::new();
+
+    for (i, frame) in frames.iter().enumerate() {
+        if frame.scene_frame_index == 0 {
+            scene_first_frames.push(i);
+            scene_lengths.push(1);
+        } else if let Some(length) = scene_lengths.last_mut() {
+            *length += 1;
+        }
+    }
+
+    let profile = if frames.iter().any(|f| f.bezier_curve.is_some()) {
+        "B"
+    } else {
+        "A"
+    };
+
+    json!({
+        "JSONInfo": {
+            "HDR10plusProfile": profile,
+            "Version": "1.0",
+        },
+        "SceneInfo": scene_info,
+        "SceneInfoSummary": {
+            "SceneFirstFrameIndex": scene_first_frames,
+            "SceneFrameNumbers": scene_lengths,
+        },
+        "ToolInfo": {
+            "Tool": "dovi_tool",
+            "Version": env!("CARGO_PKG_VERSION"),
+        },
+    })
+}
+
+impl Hdr10PlusFrame {
+    /// ST 2094-40 user_data_registered_itu_t_t35 SEI payload
+    fn sei_payload(&self, target_nits: u16) -> Vec<u8> {
+        let mut writer = BitVecWriter::new();
+
+        // ITU-T T.35 header: United States, Samsung, HDR10+
+        writer.write_n(&[0xB5], 8);
+        writer.write_n(&0x003C_u16.to_be_bytes(), 16);
+        writer.write_n(&0x0001_u16.to_be_bytes(), 16);
+
+        // application_identifier, application_version
+        writer.write_n(&[4], 8);
+        writer.write_n(&[1], 8);
+
+        // num_windows
+        writer.write_n(&[1], 2);
+
+        writer.write_n(&(target_nits as u32).to_be_bytes(), 27);
+
+        // targeted_system_display_actual_peak_luminance_flag
+        writer.write(false);
+
+        for maxscl in &self.maxscl {
+            writer.write_n(&maxscl.to_be_bytes(), 17);
+        }
+
+        writer.write_n(&self.average_maxrgb.to_be_bytes(), 17);
+
+        writer.write_n(&(DISTRIBUTION_INDEX.len() as u8).to_be_bytes(), 4);
+
+        for (percentage, value) in DISTRIBUTION_INDEX
+            .iter()
+            .zip(self.distribution_values.iter())
+        {
+            writer.write_n(&percentage.to_be_bytes(), 7);
+            writer.write_n(&value.to_be_bytes(), 17);
+        }
+
+        // fraction_bright_pixels
+        writer.write_n(&0_u16.to_be_bytes(), 10);
+
+        // mastering_display_actual_peak_luminance_flag
+        writer.write(false);
+
+        match &self.bezier_curve {
+            Some(curve) => {
+                writer.write(true);
+                writer.write_n(&curve.knee_point_x.to_be_bytes(), 12);
+                writer.write_n(&curve.knee_point_y.to_be_bytes(), 12);
+                writer.write_n(&(curve.anchors.len() as u8).to_be_bytes(), 4);
+
+                for anchor in &curve.anchors {
+                    writer.write_n(&anchor.to_be_bytes(), 10);
+                }
+            }
+            None => writer.write(false),
+        }
+
+        // color_saturation_mapping_flag
+        writer.write(false);
+
+        while !writer.is_aligned() {
+            writer.write(false);
+        }
+
+        writer.as_slice().to_vec()
+    }
+}
+
+impl BezierCurve {
+    /// Curve compressing the scene peak to the target display, adjusted by the trim.
+    /// The curve is linear up to the knee point, at the scene average or lower.
+    /// There is no curve when the scene fits in the target display.
+    fn from_trim(
+        peak_nits: f64,
+        avg_nits: f64,
+        target_nits: f64,
+        trim: &ExtMetadataBlockLevel2,
+    ) -> Option<BezierCurve> {
+        if peak_nits <= target_nits {
+            return None;
+        }
+
+        // Input normalized to the scene peak, output to the target display
+        let ratio = target_nits / peak_nits;
+        let knee_x = (avg_nits / peak_nits).min(ratio / 2.0);
+        let knee_y = knee_x / ratio;
+
+        // Steeper start of the curve for stronger compression, continuous with the linear part
+        let start_slope = ((1.0 - knee_x) / (1.0 - knee_y) / ratio).clamp(1.0, 10.0);
+
+        let slope = (trim.trim_slope as f64 - 2048.0) / 4096.0 + 1.0;
+        let offset = (trim.trim_offset as f64 - 2048.0) / 4096.0;
+        let power = (trim.trim_power as f64 - 2048.0) / 4096.0 + 1.0;
+
+        let apply_trim = |y: f64| (y * slope + offset).max(0.0).powf(power).min(1.0);
+
+        let trimmed_knee_y = apply_trim(knee_y).min(0.999);
+
+        let anchors = (1..10)
+            .map(|i| {
+                let t = i as f64 / 10.0;
+                let y = knee_y + (1.0 - knee_y) * (1.0 - (1.0 - t).powf(start_slope));
+                let anchor = (apply_trim(y) - trimmed_knee_y) / (1.0 - trimmed_knee_y);
+
+                (anchor.clamp(0.0, 1.0) * 1023.0).round() as u16
+            })
+            .collect();
+
+        Some(BezierCurve {
+            knee_point_x: (knee_x * 4095.0).round() as u16,
+            knee_point_y: (trimmed_knee_y * 4095.0).round() as u16,
+            anchors,
+        })
+    }
+}
+
+/// Percentile of the maxRGB distribution, in PQ.
+/// The L1 average is taken as the median, with the highlights getting closer to the peak
+fn estimated_percentile(min_pq: u16, avg_pq: u16, max_pq: u16, percentage: u8) -> f64 {
+    let (min, avg, max) = (
+        min_pq as f64 / 4095.0,
+        avg_pq as f64 / 4095.0,
+        max_pq as f64 / 4095.0,
+    );
+    let p = percentage as f64 / 100.0;
+
+    if p <= 0.5 {
+        min + (avg - min) * p / 0.5
+    } else {
+        avg + (max - avg) * ((p - 0.5) / 0.5).powi(3)
+    }
+}
+
+fn pq_to_value(pq: f64) -> u32 {
+    ((pq_to_nits(pq) * 10.0).round() as u32).min(MAX_LUMINANCE_VALUE)
+}
@@ -0,0 +1,578 @@
+use std::path::Path;
+
+use anyhow::{bail, ensure, Result};
+use bitvec_helpers::bitvec_reader::BitVecReader;
+
+use dolby_vision::rpu::extension_metadata::blocks::ExtMetadataBlock;
+use dolby_vision::utils::clear_start_code_emulation_prevention_3_byte;
+use hevc_parser::hevc::NAL_SPS;
+
+use super::io::NalReader;
+use super::DoviRpu;
+
+// Give up looking for a NAL unit after this many bytes
+const MAX_NAL_SEARCH_SIZE: usize = 50_000_000;
+
+/// Picture and colour information from the HEVC sequence parameter set
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct SpsInfo {
+    pub profile_space: u8,
+    pub profile_idc: u8,
+    pub tier_flag: bool,
+    pub level_idc: u8,
+    pub profile_compatibility_flags: u32,
+    pub constraint_indicator_flags: [u8; 6],
+
+    pub chroma_format_idc: u64,
+
+    /// Coded picture size, in luma samples
+    pub pic_width: u64,
+    pub pic_height: u64,
+
+    /// Conformance window offsets, in luma samples
+    pub conf_win_left_offset: u64,
+    pub conf_win_right_offset: u64,
+    pub conf_win_top_offset: u64,
+    pub conf_win_bottom_offset: u64,
+
+    pub bit_depth: u64,
+    pub bit_depth_chroma: u64,
+
+    pub vui: Option<VuiInfo>,
+}
+
+/// Video signal type and timing information from the VUI.
+/// The colour values default to 2 (unspecified) when not signaled.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VuiInfo {
+    pub video_full_range_flag: bool,
+    pub colour_primaries: u8,
+    pub transfer_characteristics: u8,
+    pub matrix_coeffs: u8,
+
+    pub num_units_in_tick: Option<u32>,
+    pub time_scale: Option<u32>,
+}
+
+#[derive(Default)]
+struct ShortTermRps {
+    inter_ref_pic_set_prediction_flag: bool,
+    num_delta_pocs: usize,
+}
+
+impl SpsInfo {
+    /// Finds and parses the first base layer SPS of a raw HEVC file
+    pub fn from_hevc_file(path: &Path) -> Result<SpsInfo> {
+        match find_first_nal(path, NAL_SPS)? {
+            Some(data) => SpsInfo::parse(&data),
+            None => bail!("No SPS found in HEVC file {}", path.display()),
+        }
+    }
+
+    /// Parses a SPS NAL unit, including the 2 bytes NAL header
+    pub fn parse(nal_data: &[u8]) -> Result<SpsInfo> {
+        ensure!(nal_data.len() > 2, "SPS NAL unit too short");
+
+        let bytes = clear_start_code_emulation_prevention_3_byte(nal_data);
+        let mut bs = BitVecReader::new(bytes);
+
+        // NAL header
+        bs.skip_n(16);
+
+        let mut sps = SpsInfo::default();
+
+        bs.skip_n(4); // sps_video_parameter_set_id
+        let max_sub_layers = bs.get_n::<u8>(3) + 1;
+        bs.skip_n(1); // sps_temporal_id_nesting_flag
+
+        sps.parse_profile_tier_level(&mut bs, max_sub_layers)?;
+
+        bs.get_ue()?; // sps_seq_parameter_set_id
+        sps.chroma_format_idc = bs.get_ue()?;
+
+        if sps.chroma_format_idc == 3 && bs.get()? {
+            // separate_colour_plane_flag
+            sps.chroma_format_idc = 0;
+        }
+
+        sps.pic_width = bs.get_ue()?;
+        sps.pic_height = bs.get_ue()?;
+
+        if bs.get()? {
+            let (sub_width, sub_height) = match sps.chroma_format_idc {
+                1 => (2, 2),
+                2 => (2, 1),
+                _ => (1, 1),
+            };
+
+            sps.conf_win_left_offset = bs.get_ue()? * sub_width;
+            sps.conf_win_right_offset = bs.get_ue()? * sub_width;
+            sps.conf_win_top_offset = bs.get_ue()? * sub_height;
+            sps.conf_win_bottom_offset = bs.get_ue()? * sub_height;
+        }
+
+        sps.bit_depth = bs.get_ue()? + 8;
+        sps.bit_depth_chroma = bs.get_ue()? + 8;
+
+        let log2_max_poc_lsb = bs.get_ue()? + 4;
+        let sublayer_ordering_info = bs.get()?;
+
+        let sub_layers = if sublayer_ordering_info {
+            max_sub_layers
+        } else {
+            1
+        };
+
+        for _ in 0..sub_layers {
+            bs.get_ue()?; // sps_max_dec_pic_buffering_minus1
+            bs.get_ue()?; // sps_max_num_reorder_pics
+            bs.get_ue()?; // sps_max_latency_increase_plus1
+        }
+
+        bs.get_ue()?; // log2_min_luma_coding_block_size_minus3
+        bs.get_ue()?; // log2_diff_max_min_luma_coding_block_size
+        bs.get_ue()?; // log2_min_luma_transform_block_size_minus2
+        bs.get_ue()?; // log2_diff_max_min_luma_transform_block_size
+        bs.get_ue()?; // max_transform_hierarchy_depth_inter
+        bs.get_ue()?; // max_transform_hierarchy_depth_intra
+
+        // scaling_list_enabled_flag, sps_scaling_list_data_present_flag
+        if bs.get()? && bs.get()? {
+            skip_scaling_list_data(&mut bs)?;
+        }
+
+        bs.skip_n(1); // amp_enabled_flag
+        bs.skip_n(1); // sample_adaptive_offset_enabled_flag
+
+        if bs.get()? {
+            // pcm_enabled_flag
+            bs.skip_n(4); // pcm_sample_bit_depth_luma_minus1
+            bs.skip_n(4); // pcm_sample_bit_depth_chroma_minus1
+            bs.get_ue()?; // log2_min_pcm_luma_coding_block_size_minus3
+            bs.get_ue()?; // log2_diff_max_min_pcm_luma_coding_block_size
+            bs.skip_n(1); // pcm_loop_filter_disabled_flag
+        }
+
+        let num_short_term_ref_pic_sets = bs.get_ue()? as usize;
+        let mut short_term_rps: Vec<ShortTermRps> = Vec::with_capacity(num_short_term_ref_pic_sets);
+
+        for idx in 0..num_short_term_ref_pic_sets {
+            let rps = ShortTermRps::parse(&mut bs, idx, &short_term_rps)?;
+            short_term_rps.push(rps);
+        }
+
+        if bs.get()? {
+            // long_term_ref_pics_present_flag
+            let num_long_term_ref_pics = bs.get_ue()?;
+
+            for _ in 0..num_long_term_ref_pics {
+                bs.skip_n(log2_max_poc_lsb as usize); // lt_ref_pic_poc_lsb_sps
+                bs.skip_n(1); // used_by_curr_pic_lt_sps_flag
+            }
+        }
+
+        bs.skip_n(1); // sps_temporal_mvp_enabled_flag
+        bs.skip_n(1); // strong_intra_smoothing_enabled_flag
+
+        if bs.get()? {
+            sps.vui = Some(VuiInfo::parse(&mut bs)?);
+        }
+
+        Ok(sps)
+    }
+
+    fn parse_profile_tier_level(
+        &mut self,
+        bs: &mut BitVecReader,
+        max_sub_layers: u8,
+    ) -> Result<()> {
+        self.profile_space = bs.get_n(2);
+        self.tier_flag = bs.get()?;
+        self.profile_idc = bs.get_n(5);
+        self.profile_compatibility_flags = bs.get_n(32);
+
+        // Source, constraint and reserved flags
+        for byte in self.constraint_indicator_flags.iter_mut() {
+            *byte = bs.get_n(8);
+        }
+
+        self.level_idc = bs.get_n(8);
+
+        let mut sub_layer_flags = Vec::with_capacity(max_sub_layers as usize);
+
+        for _ in 1..max_sub_layers {
+            let profile_present = bs.get()?;
+            let level_present = bs.get()?;
+
+            sub_layer_flags.push((profile_present, level_present));
+        }
+
+        if max_sub_layers > 1 {
+            for _ in max_sub_layers - 1..8 {
+                bs.skip_n(2); // reserved_zero_2bits
+            }
+        }
+
+        for (profile_present, level_present) in sub_layer_flags {
+            if profile_present {
+                bs.skip_n(88);
+            }
+
+            if level_present {
+                bs.skip_n(8);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Picture size after applying the conformance window
+    pub fn display_size(&self) -> (u64, u64) {
+        let width = self
+            .pic_width
+            .saturating_sub(self.conf_win_left_offset + self.conf_win_right_offset);
+        let height = self
+            .pic_height
+            .saturating_sub(self.conf_win_top_offset + self.conf_win_bottom_offset);
+
+        (width, height)
+    }
+
+    /// Frame rate from the VUI timing info, if present
+    pub fn frame_rate(&self) -> Option<f64> {
+        let vui = self.vui.as_ref()?;
+
+        match (vui.num_units_in_tick, vui.time_scale) {
+            (Some(num_units_in_tick), Some(time_scale)) if num_units_in_tick > 0 => {
+                Some(time_scale as f64 / num_units_in_tick as f64)
+            }
+            _ => None,
+        }
+    }
+
+    /// RFC 6381 codec string of the stream, for the `hvc1` or `hev1` sample entry
+    pub fn codec_string(&self, sample_entry: &str) -> String {
+        let profile_space = match self.profile_space {
+            1 => "A",
+            2 => "B",
+            3 => "C",
+            _ => "",
+        };
+
+        let mut codec = format!(
+            "{}.{}{}.{:X}.{}{}",
+            sample_entry,
+            profile_space,
+            self.profile_idc,
+            self.profile_compatibility_flags.reverse_bits(),
+            if self.tier_flag { 'H' } else { 'L' },
+            self.level_idc
+        );
+
+        // Trailing zero bytes are omitted
+        let len = self
+            .constraint_indicator_flags
+            .iter()
+            .rposition(|b| *b != 0)
+            .map_or(0, |i| i + 1);
+
+        for byte in &self.constraint_indicator_flags[..len] {
+            codec.push_str(&format!(".{:X}", byte));
+        }
+
+        codec
+    }
+
+    pub fn full_range(&self) -> bool {
+        self.vui
+            .as_ref()
+            .map_or(false, |vui| vui.video_full_range_flag)
+    }
+
+    pub fn print_summary(&self) {
+        let (width, height) = self.display_size();
+
+        println!(
+            "HEVC stream: {}x{}, {}-bit, chroma format {}",
+            width, height, self.bit_depth, self.chroma_format_idc
+        );
+
+        if let Some(vui) = &self.vui {
+            println!(
+                "  colour primaries {}, transfer {}, matrix {}, {} range",
+                vui.colour_primaries,
+                vui.transfer_characteristics,
+                vui.matrix_coeffs,
+                if vui.video_full_range_flag {
+                    "full"
+                } else {
+                    "limited"
+                }
+            );
+        }
+    }
+
+    /// Cross-checks the RPU against the bitstream, returning the mismatches found
+    pub fn validate_rpu(&self, rpu: &DoviRpu) -> Vec<String> {
+        let mut warnings = Vec::new();
+        let header = &rpu.header;
+
+        // Profile 5 has an IPT base layer, not described by the VUI
+        let profile5 = rpu.dovi_profile == 5;
+
+        if !profile5 && header.bl_video_full_range_flag != self.full_range() {
+            warnings.push(format!(
+                "bl_video_full_range_flag is {}, the bitstream is {} range",
+                header.bl_video_full_range_flag,
+                if self.full_range() { "full" } else { "limited" }
+            ));
+        }
+
+        if header.bl_bit_depth_minus8 + 8 != self.bit_depth {
+            warnings.push(format!(
+                "BL bit depth is {}, the bitstream is {}-bit",
+                header.bl_bit_depth_minus8 + 8,
+                self.bit_depth
+            ));
+        }
+
+        let (width, height) = self.display_size();
+
+        if let Some(vdr_dm_data) = &rpu.vdr_dm_data {
+            if vdr_dm_data.signal_chroma_format == 0 && self.chroma_format_idc != 1 {
+                warnings.push(format!(
+                    "signal_chroma_format is 4:2:0, the bitstream chroma format is {}",
+                    self.chroma_format_idc
+                ));
+            }
+
+            if let Some(vui) = &self.vui {
+                // PQ transfer, signaled by the default signal_eotf
+                if vui.transfer_characteristics == 16 && vdr_dm_data.signal_eotf != 65535 {
+                    warnings.push(format!(
+                        "signal_eotf is {}, the bitstream transfer is PQ",
+                        vdr_dm_data.signal_eotf
+                    ));
+                }
+
+                // BT.2020 non-constant luminance
+                if !profile5 && vui.matrix_coeffs == 9 && vdr_dm_data.signal_color_space != 0 {
+                    warnings.push(format!(
+                        "signal_color_space is {}, the bitstream is YCbCr",
+                        vdr_dm_data.signal_color_space
+                    ));
+                }
+            }
+
+            for block in vdr_dm_data.level_blocks_iter(5) {
+                if let ExtMetadataBlock::Level5(level5) = block {
+                    let (left, right, top, bottom) = level5.get_offsets();
+
+                    if left as u64 + right as u64 >= width || top as u64 + bottom as u64 >= height {
+                        warnings.push(format!(
+                            "L5 offsets ({}, {}, {}, {}) exceed the {}x{} picture",
+                            left, right, top, bottom, width, height
+                        ));
+                    }
+                }
+            }
+
+            // A BT.709 container can't hold wider mastering primaries
+            let bt709 = self
+                .vui
+                .as_ref()
+                .map_or(false, |vui| vui.colour_primaries == 1);
+
+            for block in vdr_dm_data.level_blocks_iter(9) {
+                if let ExtMetadataBlock::Level9(level9) = block {
+                    if bt709 && level9.source_primary_index != 1 {
+                        warnings.push(format!(
+                            "L9 source primaries {} are wider than the BT.709 bitstream",
+                            level9.source_primary_index
+                        ));
+                    }
+                }
+            }
+        }
+
+        warnings
+    }
+
+    /// Prints the RPU mismatches with the bitstream, once per distinct warning
+    pub fn validate_rpus<'a, I: IntoIterator<Item = &'a DoviRpu>>(&self, rpus: I) -> usize {
+        let mut warnings: Vec<(String, usize, usize)> = Vec::new();
+
+        for (frame, rpu) in rpus.into_iter().enumerate() {
+            for warning in self.validate_rpu(rpu) {
+                match warnings.iter_mut().find(|w| w.0 == warning) {
+                    Some(existing) => existing.2 += 1,
+                    None => warnings.push((warning, frame, 1)),
+                }
+            }
+        }
+
+        for (warning, first_frame, count) in &warnings {
+            println!(
+                "Warning: {} (frame {}, {} frames total)",
+                warning, first_frame, count
+            );
+        }
+
+        warnings.len()
+    }
+}
+
+impl VuiInfo {
+    fn parse(bs: &mut BitVecReader) -> Result<VuiInfo> {
+        let mut vui = VuiInfo {
+            video_full_range_flag: false,
+            colour_primaries: 2,
+            transfer_characteristics: 2,
+            matrix_coeffs: 2,
+            num_units_in_tick: None,
+            time_scale: None,
+        };
+
+        if bs.get()? {
+            // aspect_ratio_info_present_flag
+            let aspect_ratio_idc: u8 = bs.get_n(8);
+
+            if aspect_ratio_idc == 255 {
+                bs.skip_n(32); // sar_width, sar_height
+            }
+        }
+
+        if bs.get()? {
+            bs.skip_n(1); // overscan_appropriate_flag
+        }
+
+        if bs.get()? {
+            // video_signal_type_present_flag
+            bs.skip_n(3); // video_format
+            vui.video_full_range_flag = bs.get()?;
+
+            if bs.get()? {
+                vui.colour_primaries = bs.get_n(8);
+                vui.transfer_characteristics = bs.get_n(8);
+                vui.matrix_coeffs = bs.get_n(8);
+            }
+        }
+
+        if bs.get()? {
+            // chroma_loc_info_present_flag
+            bs.get_ue()?;
+            bs.get_ue()?;
+        }
+
+        bs.skip_n(1); // neutral_chroma_indication_flag
+        bs.skip_n(1); // field_seq_flag
+        bs.skip_n(1); // frame_field_info_present_flag
+
+        if bs.get()? {
+            // default_display_window_flag
+            for _ in 0..4 {
+                bs.get_ue()?;
+            }
+        }
+
+        if bs.get()? {
+            // vui_timing_info_present_flag
+            vui.num_units_in_tick = Some(bs.get_n(32));
+            vui.time_scale = Some(bs.get_n(32));
+        }
+
+        // The HRD parameters and bitstream restrictions are not needed
+        Ok(vui)
+    }
+}
+
+impl ShortTermRps {
+    fn parse(bs: &mut BitVecReader, idx: usize, previous: &[ShortTermRps]) -> Result<Self> {
+        let mut rps = ShortTermRps::default();
+
+        if idx > 0 {
+            rps.inter_ref_pic_set_prediction_flag = bs.get()?;
+        }
+
+        if rps.inter_ref_pic_set_prediction_flag {
+            // delta_idx_minus1 is only present in slice headers
+            bs.skip_n(1); // delta_rps_sign
+            bs.get_ue()?; // abs_delta_rps_minus1
+
+            let ref_rps = &previous[idx - 1];
+
+            for _ in 0..=ref_rps.num_delta_pocs {
+                let used_by_curr_pic_flag = bs.get()?;
+                let use_delta_flag = if used_by_curr_pic_flag {
+                    true
+                } else {
+                    bs.get()?
+                };
+
+                if use_delta_flag {
+                    rps.num_delta_pocs += 1;
+                }
+            }
+        } else {
+            let num_negative_pics = bs.get_ue()? as usize;
+            let num_positive_pics = bs.get_ue()? as usize;
+
+            for _ in 0..num_negative_pics + num_positive_pics {
+                bs.get_ue()?; // delta_poc_minus1
+                bs.skip_n(1); // used_by_curr_pic_flag
+            }
+
+            rps.num_delta_pocs = num_negative_pics + num_positive_pics;
+        }
+
+        Ok(rps)
+    }
+}
+
+fn skip_scaling_list_data(bs: &mut BitVecReader) -> Result<()> {
+    for size_id in 0..4 {
+        let matrix_count = if size_id == 3 { 2 } else { 6 };
+
+        for _ in 0..matrix_count {
+            if !bs.get()? {
+                // scaling_list_pred_matrix_id_delta
+                bs.get_ue()?;
+            } else {
+                let coef_num = 64.min(1 << (4 + (size_id << 1)));
+
+                if size_id > 1 {
+                    bs.get_se()?; // scaling_list_dc_coef_minus8
+                }
+
+                for _ in 0..coef_num {
+                    bs.get_se()?;
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the data of the first base layer NAL unit of the type in a raw HEVC file,
+/// including the NAL header. Only the start of the file is searched.
+pub fn find_first_nal(path: &Path, nal_type: u8) -> Result<Option<Vec<u8>>> {
+    let mut reader = NalReader::open(path)?;
+
+    let mut found = None;
+    let mut searched = 0;
+
+    reader.read_nals(false, |nal, data| {
+        let nuh_layer_id = ((data[0] & 1) << 5) | (data[1] >> 3);
+
+        if nal.nal_type == nal_type && nuh_layer_id == 0 {
+            found = Some(data.to_vec());
+        }
+
+        searched += data.len();
+
+        Ok(found.is_none() && searched <= MAX_NAL_SEARCH_SIZE)
+    })?;
+
+    Ok(found)
+}
@@ -10,11 +10,22 @@ use hevc_parser::HevcParser;
 
 use dolby_vision::rpu::dovi_rpu::DoviRpu;
 
+use super::sei::write_with_hdr10_seis;
 use super::{is_st2094_40_sei, CliOptions, Format, OUT_NAL_HEADER};
 
 pub struct DoviReader {
     options: CliOptions,
     rpu_nals: Vec<RpuNal>,
+    hdr10_sei_nals: Option<Vec<Vec<u8>>>,
+    dropped_nals: DroppedNals,
+}
+
+/// Counts of the NALUs not written to any output
+#[derive(Debug, Default, Clone, Copy)]
+pub struct DroppedNals {
+    pub rpu: usize,
+    pub el: usize,
+    pub hdr10plus: usize,
 }
 
 pub struct DoviWriter {
@@ -31,6 +42,12 @@ pub struct RpuNal {
     data: Vec<u8>,
 }
 
+/// Reads the NAL units of a raw HEVC file in chunks
+pub struct NalReader {
+    reader: BufReader<File>,
+    parser: HevcParser,
+}
+
 impl DoviWriter {
     pub fn new(
         bl_out: Option<&Path>,
@@ -81,9 +98,20 @@ impl DoviReader {
         DoviReader {
             options,
             rpu_nals: Vec::new(),
+            hdr10_sei_nals: None,
+            dropped_nals: DroppedNals::default(),
         }
     }
 
+    pub fn dropped_nals(&self) -> DroppedNals {
+        self.dropped_nals
+    }
+
+    /// Replaces the HDR10 SEIs of the single layer output
+    pub fn set_hdr10_sei_nals(&mut self, sei_nals: Vec<Vec<u8>>) {
+        self.hdr10_sei_nals = Some(sei_nals);
+    }
+
     pub fn read_write_from_io(
         &mut self,
         format: &Format,
@@ -205,14 +233,29 @@ impl DoviReader {
                 && nal.nal_type == NAL_SEI_PREFIX
                 && is_st2094_40_sei(&chunk[nal.start..nal.end])?
             {
+                self.dropped_nals.hdr10plus += 1;
                 continue;
             }
 
             if let Some(ref mut sl_writer) = dovi_writer.sl_writer {
                 if nal.nal_type == NAL_UNSPEC63 && self.options.discard_el {
+                    self.dropped_nals.el += 1;
                     continue;
                 }
 
+                if let Some(sei_nals) = &self.hdr10_sei_nals {
+                    if nal.nal_type != NAL_UNSPEC62 {
+                        write_with_hdr10_seis(
+                            sl_writer,
+                            nal.nal_type,
+                            &chunk[nal.start..nal.end],
+                            sei_nals,
+                        )?;
+
+                        continue;
+                    }
+                }
+
                 sl_writer.write_all(OUT_NAL_HEADER)?;
 
                 if nal.nal_type == NAL_UNSPEC62 {
@@ -245,11 +288,16 @@ impl DoviReader {
                     if let Some(ref mut el_writer) = dovi_writer.el_writer {
                         el_writer.write_all(OUT_NAL_HEADER)?;
                         el_writer.write_all(&chunk[nal.start + 2..nal.end])?;
+                    } else {
+                        self.dropped_nals.el += 1;
                     }
                 }
                 NAL_UNSPEC62 => {
                     if let Some(ref mut el_writer) = dovi_writer.el_writer {
                         el_writer.write_all(OUT_NAL_HEADER)?;
+                    } else if dovi_writer.rpu_writer.is_none() {
+                        self.dropped_nals.rpu += 1;
+                        continue;
                     }
 
                     // No mode: Copy
@@ -313,6 +361,10 @@ impl DoviReader {
             el_writer.flush()?;
         }
 
+        if let Some(ref mut sl_writer) = dovi_writer.sl_writer {
+            sl_writer.flush()?;
+        }
+
         // Reorder RPUs to display output order
         if let Some(ref mut rpu_writer) = dovi_writer.rpu_writer {
             let frames = parser.ordered_frames();
@@ -360,3 +412,74 @@ impl DoviReader {
         Ok(())
     }
 }
+
+impl NalReader {
+    const CHUNK_SIZE: usize = 100_000;
+
+    pub fn open(path: &Path) -> Result<NalReader> {
+        Ok(NalReader {
+            reader: BufReader::with_capacity(Self::CHUNK_SIZE, File::open(path)?),
+            parser: HevcParser::default(),
+        })
+    }
+
+    /// Calls `f` with every NAL unit and its data, from the NAL header and without trailing zero bytes.
+    /// Reading stops when `f` returns `false`.
+    pub fn read_nals<F>(&mut self, parse_nals: bool, mut f: F) -> Result<()>
+    where
+        F: FnMut(&NALUnit, &[u8]) -> Result<bool>,
+    {
+        let mut buf = vec![0; Self::CHUNK_SIZE];
+        let mut chunk = Vec::with_capacity(Self::CHUNK_SIZE);
+        let mut offsets = Vec::with_capacity(2048);
+
+        loop {
+            let read_bytes = self.reader.read(&mut buf)?;
+            let eof = read_bytes == 0;
+
+            chunk.extend_from_slice(&buf[..read_bytes]);
+            self.parser.get_offsets(&chunk, &mut offsets);
+
+            if offsets.is_empty() {
+                if eof {
+                    return Ok(());
+                }
+
+                continue;
+            }
+
+            // The last NAL unit is incomplete until the end of the file
+            let last = if eof {
+                *offsets.last().unwrap()
+            } else {
+                offsets.pop().unwrap()
+            };
+
+            let nals = self.parser.split_nals(&chunk, &offsets, last, parse_nals)?;
+
+            for nal in &nals {
+                let mut data = &chunk[nal.start..nal.end];
+
+                while let Some((0, rest)) = data.split_last() {
+                    data = rest;
+                }
+
+                if data.len() >= 2 && !f(nal, data)? {
+                    return Ok(());
+                }
+            }
+
+            if eof {
+                return Ok(());
+            }
+
+            // Only the incomplete NAL unit is scanned again
+            chunk.drain(..last);
+        }
+    }
+
+    /// Parser state, with the frames when the NAL units were parsed
+    pub fn parser(&mut self) -> &mut HevcParser {
+        &mut self.parser
+    }
+}
@@ -0,0 +1,276 @@
+use std::fs::File;
+use std::io::{stdout, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{ensure, Result};
+use serde_json::{json, Map, Value};
+
+use dolby_vision::rpu::generate::GenerateConfig;
+
+use super::editor::EditConfig;
+use super::video_analysis::{self, YuvFrame, YuvReader};
+use super::{parse_rpu_file, write_rpu_file, DoviRpu};
+
+pub struct LetterboxDetector {
+    video: PathBuf,
+    video_dimensions: Option<(usize, usize)>,
+    json_out: Option<PathBuf>,
+    rpu_in: Option<PathBuf>,
+    rpu_out: Option<PathBuf>,
+    opts: LetterboxOpts,
+}
+
+pub struct LetterboxOpts {
+    /// Scene cut detection threshold, refer to `video_analysis::detect_scene_cuts`
+    pub scene_threshold: f64,
+
+    /// Max 10-bit luma code value of black bars
+    pub black_level: u16,
+
+    /// Frames with a lower max luma are too dark to detect bars
+    pub dark_level: u16,
+
+    /// Bar size changes up to this amount of pixels are ignored
+    pub tolerance: u16,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Bars {
+    pub left: u16,
+    pub right: u16,
+    pub top: u16,
+    pub bottom: u16,
+}
+
+impl LetterboxDetector {
+    pub fn detect(
+        video: PathBuf,
+        video_dimensions: Option<(usize, usize)>,
+        json_out: Option<PathBuf>,
+        rpu_in: Option<PathBuf>,
+        rpu_out: Option<PathBuf>,
+        opts: LetterboxOpts,
+    ) -> Result<()> {
+        let detector = LetterboxDetector {
+            video,
+            video_dimensions,
+            json_out,
+            rpu_in,
+            rpu_out,
+            opts,
+        };
+
+        println!("Detecting letterbox bars...");
+        stdout().flush().ok();
+
+        let scene_bars = detector.detect_scene_bars()?;
+        let edit_json = LetterboxDetector::active_area_edits(&scene_bars);
+
+        if let Some(rpu_in) = &detector.rpu_in {
+            detector.apply_to_rpu(rpu_in, &edit_json)?;
+        }
+
+        if detector.json_out.is_some() || detector.rpu_in.is_none() {
+            let json_out = detector
+                .json_out
+                .clone()
+                .unwrap_or_else(|| PathBuf::from("letterbox_edits.json"));
+
+            let mut writer = File::create(&json_out)?;
+            serde_json::to_writer_pretty(&mut writer, &edit_json)?;
+
+            println!("Editor config written to {}", json_out.display());
+        }
+
+        println!("Done.");
+
+        Ok(())
+    }
+
+    /// Returns the bars of every scene, as (start, end, bars) with inclusive frame ranges
+    fn detect_scene_bars(&self) -> Result<Vec<(usize, usize, Bars)>> {
+        let mut reader = YuvReader::open(&self.video, self.video_dimensions)?;
+
+        let mut measurements = Vec::new();
+        let mut frame_bars = Vec::new();
+
+        while let Some(frame) = reader.read_frame()? {
+            measurements.push(reader.measure(&frame));
+            frame_bars.push(detect_bars(&frame, &self.opts));
+
+            if measurements.len() % 100 == 0 {
+                println!("Processed {} frames", measurements.len());
+            }
+        }
+
+        ensure!(!measurements.is_empty(), "No video frames to analyze");
+
+        let scene_cuts =
+            video_analysis::detect_scene_cuts(&measurements, self.opts.scene_threshold);
+
+        let mut scene_bars: Vec<(usize, usize, Bars)> = Vec::with_capacity(scene_cuts.len());
+
+        for (i, start) in scene_cuts.iter().enumerate() {
+            let end = scene_cuts.get(i + 1).copied().unwrap_or(measurements.len()) - 1;
+
+            // Smallest bars over the scene, as content can be darker than the black level
+            let detected =
+                frame_bars[*start..=end]
+                    .iter()
+                    .flatten()
+                    .fold(None, |acc: Option<Bars>, b| {
+                        Some(acc.map_or(*b, |acc| Bars {
+                            left: acc.left.min(b.left),
+                            right: acc.right.min(b.right),
+                            top: acc.top.min(b.top),
+                            bottom: acc.bottom.min(b.bottom),
+                        }))
+                    });
+
+            let previous = scene_bars.last().map(|s| s.2);
+
+            // Dark scenes and small changes keep the previous scene's bars
+            let bars = match (detected, previous) {
+                (Some(bars), Some(previous)) if bars.within(&previous, self.opts.tolerance) => {
+                    previous
+                }
+                (Some(bars), _) => bars,
+                (None, previous) => previous.unwrap_or_default(),
+            };
+
+            scene_bars.push((*start, end, bars));
+        }
+
+        println!(
+            "Analyzed {} frames, {} scenes",
+            measurements.len(),
+            scene_bars.len()
+        );
+
+        Ok(scene_bars)
+    }
+
+    /// Editor config with an active area preset per distinct bars, and the edits for the scenes
+    fn active_area_edits(scene_bars: &[(usize, usize, Bars)]) -> Value {
+        let mut presets: Vec<Bars> = Vec::new();
+        let mut edits: Vec<(usize, usize, usize)> = Vec::new();
+
+        for (start, end, bars) in scene_bars {
+            let id = match presets.iter().position(|p| p == bars) {
+                Some(id) => id,
+                None => {
+                    presets.push(*bars);
+                    presets.len() - 1
+                }
+            };
+
+            // Merge consecutive scenes with the same bars
+            match edits.last_mut() {
+                Some(last) if last.2 == id => last.1 = *end,
+                _ => edits.push((*start, *end, id)),
+            }
+        }
+
+        let presets: Vec<Value> = presets
+            .iter()
+            .enumerate()
+            .map(|(id, bars)| {
+                json!({
+                    "id": id,
+                    "left": bars.left,
+                    "right": bars.right,
+                    "top": bars.top,
+                    "bottom": bars.bottom,
+                })
+            })
+            .collect();
+
+        let edits: Map<String, Value> = edits
+            .iter()
+            .map(|(start, end, id)| (format!("{}-{}", start, end), json!(id)))
+            .collect();
+
+        json!({
+            "active_area": {
+                "presets": presets,
+                "edits": edits,
+            }
+        })
+    }
+
+    fn apply_to_rpu(&self, rpu_in: &Path, edit_json: &Value) -> Result<()> {
+        let rpu_out = self
+            .rpu_out
+            .clone()
+            .unwrap_or_else(|| PathBuf::from("RPU_letterbox.bin"));
+
+        let config: EditConfig = serde_json::from_value(edit_json.clone())?;
+
+        if let Some(rpus) = parse_rpu_file(rpu_in)? {
+            let mut rpus: Vec<Option<DoviRpu>> = rpus.into_iter().map(Some).collect();
+
+            config.execute(&mut rpus)?;
+
+            let data = GenerateConfig::encode_option_rpus(&mut rpus);
+            write_rpu_file(&rpu_out, data)?;
+
+            println!("Active area offsets applied to {}", rpu_out.display());
+        }
+
+        Ok(())
+    }
+}
+
+impl Bars {
+    fn within(&self, other: &Self, tolerance: u16) -> bool {
+        let close = |a: u16, b: u16| (a as i32 - b as i32).abs() <= tolerance as i32;
+
+        close(self.left, other.left)
+            && close(self.right, other.right)
+            && close(self.top, other.top)
+            && close(self.bottom, other.bottom)
+    }
+}
+
+/// Detects the black bars of a frame, from the rows and columns of black luma samples.
+/// Returns `None` for frames too dark to tell the bars from the content.
+pub fn detect_bars(frame: &YuvFrame, opts: &LetterboxOpts) -> Option<Bars> {
+    let (width, height) = (frame.width, frame.height);
+
+    let max_luma = frame.y.iter().copied().max().unwrap_or(0);
+    if max_luma < opts.dark_level {
+        return None;
+    }
+
+    let row_is_black = |row: usize| {
+        frame.y[row * width..(row + 1) * width]
+            .iter()
+            .all(|y| *y <= opts.black_level)
+    };
+
+    let top = (0..height).take_while(|row| row_is_black(*row)).count();
+    let bottom = (top..height)
+        .rev()
+        .take_while(|row| row_is_black(*row))
+        .count();
+
+    let column_is_black = |col: usize| {
+        (top..height - bottom).all(|row| frame.y[row * width + col] <= opts.black_level)
+    };
+
+    let left = (0..width).take_while(|col| column_is_black(*col)).count();
+    let right = (left..width)
+        .rev()
+        .take_while(|col| column_is_black(*col))
+        .count();
+
+    // Offsets are kept even, for chroma subsampling
+    let even = |v: usize| (v & !1) as u16;
+
+    Some(Bars {
+        left: even(left),
+        right: even(right),
+        top: even(top),
+        bottom: even(bottom),
+    })
+}
@@ -0,0 +1,217 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+use anyhow::{bail, ensure, Result};
+use madvr_parse::{MadVRFrame, MadVRHeader, MadVRMeasurements, MadVRScene};
+
+use dolby_vision::rpu::extension_metadata::blocks::{ExtMetadataBlock, ExtMetadataBlockLevel2};
+use dolby_vision::rpu::extension_metadata::target_display::TargetPrimaries;
+use dolby_vision::utils::{nits_to_pq, pq_to_nits};
+
+use super::{parse_rpu_file, DoviRpu};
+
+/// Measurement file layout written, with gamut peaks and custom target nits
+const MADVR_VERSION: u32 = 6;
+
+/// Header length after the magic code, in bytes
+const MADVR_HEADER_SIZE: u32 = 9 * 4;
+
+/// Header flags of a complete file with custom per-frame target nits
+const MADVR_FLAGS_CUSTOM_TARGETS: u32 = 3;
+
+/// Version 5+ luminance histogram
+const LUM_HISTOGRAM_BINS: usize = 256;
+const HUE_HISTOGRAM_BINS: usize = 31;
+
+/// Share of the synthetic histogram at the frame peak, in percent
+const PEAK_BIN_PERCENT: f64 = 0.5;
+
+pub struct MadVRConverter;
+
+impl MadVRConverter {
+    /// Writes a madVR measurement file from the L1 metadata and scene cuts of the RPU
+    pub fn convert(input: PathBuf, output: Option<PathBuf>, target_nits: u16) -> Result<()> {
+        let output = output.unwrap_or_else(|| PathBuf::from("madvr.measurements"));
+
+        ensure!(
+            (100..=10_000).contains(&target_nits),
+            "Invalid target nits {}, must be between 100 and 10000",
+            target_nits
+        );
+
+        let rpus = match parse_rpu_file(&input)? {
+            Some(rpus) => rpus,
+            None => bail!("No RPUs parsed from {}", input.display()),
+        };
+
+        println!("Converting {} frames to madVR measurements...", rpus.len());
+
+        let measurements = madvr_measurements(&rpus, target_nits)?;
+
+        let mut writer = BufWriter::new(File::create(&output)?);
+        writer.write_all(&measurements.write_measurements()?)?;
+        writer.flush()?;
+
+        println!("madVR measurements written to {}", output.display());
+
+        Ok(())
+    }
+}
+
+fn madvr_measurements(rpus: &[DoviRpu], target_nits: u16) -> Result<MadVRMeasurements> {
+    let target_max_pq = (nits_to_pq(target_nits) * 4095.0).round() as u16;
+
+    let mut scenes: Vec<MadVRScene> = Vec::new();
+    let mut frames: Vec<MadVRFrame> = Vec::with_capacity(rpus.len());
+
+    for (i, rpu) in rpus.iter().enumerate() {
+        let vdr_dm_data = match &rpu.vdr_dm_data {
+            Some(vdr_dm_data) => vdr_dm_data,
+            None => bail!("Frame {}: no DM metadata", i),
+        };
+
+        let (max_pq, avg_pq) = match vdr_dm_data.get_block(1) {
+            Some(ExtMetadataBlock::Level1(l1)) => {
+                (l1.max_pq as f64 / 4095.0, l1.avg_pq as f64 / 4095.0)
+            }
+            _ => bail!("Frame {}: no L1 metadata", i),
+        };
+
+        let peak_nits = pq_to_nits(max_pq).round() as u32;
+
+        match scenes.last_mut() {
+            Some(scene) if vdr_dm_data.scene_refresh_flag != 1 => {
+                scene.end = i as u32;
+                scene.length += 1;
+                scene.peak_nits = scene.peak_nits.max(peak_nits);
+            }
+            _ => scenes.push(MadVRScene {
+                start: i as u32,
+                end: i as u32,
+                peak_nits,
+                length: 1,
+                ..Default::default()
+            }),
+        }
+
+        // Peaks of the pixels outside of DCI-P3 and BT.709, assuming the content fills the source gamut
+        let source_primaries = match vdr_dm_data.get_block(9) {
+            Some(ExtMetadataBlock::Level9(l9)) => {
+                TargetPrimaries::from_index(l9.source_primary_index)
+            }
+            _ => None,
+        };
+        let (peak_pq_dcip3, peak_pq_709) =
+            match source_primaries.unwrap_or(TargetPrimaries::DciP3D65) {
+                TargetPrimaries::Bt2020 => (max_pq, max_pq),
+                TargetPrimaries::DciP3D65 => (0.0, max_pq),
+                TargetPrimaries::Bt709 => (0.0, 0.0),
+            };
+
+        let trim = vdr_dm_data.interpolated_trim(target_max_pq);
+        let frame_target_nits = trimmed_target_nits(pq_to_nits(max_pq), &trim);
+
+        frames.push(MadVRFrame {
+            peak_pq_2020: max_pq,
+            peak_pq_dcip3: Some(peak_pq_dcip3),
+            peak_pq_709: Some(peak_pq_709),
+            lum_histogram: synthetic_histogram(max_pq, avg_pq),
+            hue_histogram: Some(vec![0.0; HUE_HISTOGRAM_BINS]),
+            target_nits: Some(frame_target_nits),
+            avg_pq,
+            target_pq: nits_to_pq(frame_target_nits),
+        });
+    }
+
+    let frame_avg_nits: Vec<f64> = frames.iter().map(|f| pq_to_nits(f.avg_pq)).collect();
+
+    let header = MadVRHeader {
+        version: MADVR_VERSION,
+        header_size: MADVR_HEADER_SIZE,
+        scene_count: scenes.len() as u32,
+        frame_count: frames.len() as u32,
+        flags: MADVR_FLAGS_CUSTOM_TARGETS,
+        maxcll: scenes.iter().map(|s| s.peak_nits).max().unwrap_or(0),
+        maxfall: frame_avg_nits.iter().cloned().fold(0.0, f64::max).round() as u32,
+        avgfall: (frame_avg_nits.iter().sum::<f64>() / frames.len().max(1) as f64).round() as u32,
+        target_peak_nits: target_nits as u32,
+    };
+
+    Ok(MadVRMeasurements {
+        header,
+        scenes,
+        frames,
+    })
+}
+
+/// The frame is tone mapped from its peak, lowered by brightening trims and raised by darkening ones.
+/// The trim is compared to neutral at the middle of the range.
+fn trimmed_target_nits(peak_nits: f64, trim: &ExtMetadataBlockLevel2) -> u16 {
+    let slope = (trim.trim_slope as f64 - 2048.0) / 4096.0 + 1.0;
+    let offset = (trim.trim_offset as f64 - 2048.0) / 4096.0;
+    let power = (trim.trim_power as f64 - 2048.0) / 4096.0 + 1.0;
+
+    let trimmed_mid = (0.5 * slope + offset).max(0.01).powf(power);
+    let target_nits = peak_nits * 0.5 / trimmed_mid;
+
+    target_nits.round().clamp(100.0, 10_000.0) as u16
+}
+
+/// Luminance histogram averaging to the frame average, with a small share at the frame peak.
+/// The first bin is never used, as it can be ignored for black bars.
+fn synthetic_histogram(max_pq: f64, avg_pq: f64) -> Vec<f64> {
+    let mut histogram = vec![0.0; LUM_HISTOGRAM_BINS];
+
+    // Bin values as computed when parsing
+    let histogram_bin_pq = |bin: usize| MadVRFrame::histogram_bin_pq(LUM_HISTOGRAM_BINS, bin);
+
+    let by_pq = |a: &usize, b: &usize| {
+        histogram_bin_pq(*a)
+            .partial_cmp(&histogram_bin_pq(*b))
+            .unwrap()
+    };
+    let closest_bin = |pq: f64| {
+        (1..LUM_HISTOGRAM_BINS)
+            .min_by(|a, b| {
+                let a = (histogram_bin_pq(*a) - pq).abs();
+                let b = (histogram_bin_pq(*b) - pq).abs();
+                a.partial_cmp(&b).unwrap()
+            })
+            .unwrap()
+    };
+
+    let peak_bin = closest_bin(max_pq);
+    let peak_percent = if max_pq > avg_pq {
+        PEAK_BIN_PERCENT
+    } else {
+        0.0
+    };
+    histogram[peak_bin] = peak_percent;
+
+    // Average of the remaining share, split between the two bins around it
+    let remaining = 100.0 - peak_percent;
+    let remaining_avg = (avg_pq * 100.0 - peak_percent * histogram_bin_pq(peak_bin)) / remaining;
+
+    let lower = (1..LUM_HISTOGRAM_BINS)
+        .filter(|bin| histogram_bin_pq(*bin) <= remaining_avg)
+        .max_by(by_pq)
+        .unwrap_or_else(|| closest_bin(remaining_avg));
+    let upper = (1..LUM_HISTOGRAM_BINS)
+        .filter(|bin| histogram_bin_pq(*bin) >= remaining_avg)
+        .min_by(by_pq)
+        .unwrap_or(lower);
+
+    let (lower_pq, upper_pq) = (histogram_bin_pq(lower), histogram_bin_pq(upper));
+
+    let upper_percent = if upper_pq > lower_pq {
+        (remaining * (remaining_avg - lower_pq) / (upper_pq - lower_pq)).clamp(0.0, remaining)
+    } else {
+        remaining
+    };
+
+    histogram[lower] += remaining - upper_percent;
+    histogram[upper] += upper_percent;
+
+    histogram
+}
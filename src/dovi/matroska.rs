@@ -0,0 +1,349 @@
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use anyhow::Result;
+
+use super::muxer::{HevcTrack, Sample};
+
+// EBML header
+const EBML: u32 = 0x1A45_DFA3;
+const EBML_VERSION: u32 = 0x4286;
+const EBML_READ_VERSION: u32 = 0x42F7;
+const EBML_MAX_ID_LENGTH: u32 = 0x42F2;
+const EBML_MAX_SIZE_LENGTH: u32 = 0x42F3;
+const DOC_TYPE: u32 = 0x4282;
+const DOC_TYPE_VERSION: u32 = 0x4287;
+const DOC_TYPE_READ_VERSION: u32 = 0x4285;
+const VOID: u32 = 0xEC;
+
+const SEGMENT: u32 = 0x1853_8067;
+
+const SEEK_HEAD: u32 = 0x114D_9B74;
+const SEEK: u32 = 0x4DBB;
+const SEEK_ID: u32 = 0x53AB;
+const SEEK_POSITION: u32 = 0x53AC;
+
+const INFO: u32 = 0x1549_A966;
+const TIMESTAMP_SCALE: u32 = 0x2A_D7B1;
+const MUXING_APP: u32 = 0x4D80;
+const WRITING_APP: u32 = 0x5741;
+const DURATION: u32 = 0x4489;
+
+const TRACKS: u32 = 0x1654_AE6B;
+const TRACK_ENTRY: u32 = 0xAE;
+const TRACK_NUMBER: u32 = 0xD7;
+const TRACK_UID: u32 = 0x73C5;
+const TRACK_TYPE: u32 = 0x83;
+const FLAG_LACING: u32 = 0x9C;
+const DEFAULT_DURATION: u32 = 0x23_E383;
+const CODEC_ID: u32 = 0x86;
+const CODEC_PRIVATE: u32 = 0x63A2;
+const BLOCK_ADDITION_MAPPING: u32 = 0x41E4;
+const BLOCK_ADD_ID_TYPE: u32 = 0x41E7;
+const BLOCK_ADD_ID_EXTRA_DATA: u32 = 0x41ED;
+
+const VIDEO: u32 = 0xE0;
+const PIXEL_WIDTH: u32 = 0xB0;
+const PIXEL_HEIGHT: u32 = 0xBA;
+const COLOUR: u32 = 0x55B0;
+const MATRIX_COEFFICIENTS: u32 = 0x55B1;
+const RANGE: u32 = 0x55B9;
+const TRANSFER_CHARACTERISTICS: u32 = 0x55BA;
+const PRIMARIES: u32 = 0x55BB;
+
+const CLUSTER: u32 = 0x1F43_B675;
+const TIMESTAMP: u32 = 0xE7;
+const SIMPLE_BLOCK: u32 = 0xA3;
+
+const CUES: u32 = 0x1C53_BB6B;
+const CUE_POINT: u32 = 0xBB;
+const CUE_TIME: u32 = 0xB3;
+const CUE_TRACK_POSITIONS: u32 = 0xB7;
+const CUE_TRACK: u32 = 0xF7;
+const CUE_CLUSTER_POSITION: u32 = 0xF1;
+
+/// Timestamps are in milliseconds
+const TIMESTAMP_SCALE_NS: u64 = 1_000_000;
+
+/// Space reserved for the SeekHead, written once the Cues position is known
+const SEEK_HEAD_RESERVED_SIZE: usize = 100;
+
+/// Minimal Matroska muxer for a single HEVC video track
+pub struct MatroskaWriter {
+    writer: BufWriter<File>,
+    segment_data_start: u64,
+
+    cluster: Vec<u8>,
+    cluster_timestamp: u64,
+    cluster_position: u64,
+
+    cue_points: Vec<(u64, u64)>,
+    duration: u64,
+}
+
+impl MatroskaWriter {
+    pub fn write(track: &HevcTrack, output: &Path) -> Result<()> {
+        let file = File::create(output)?;
+        let mut writer = BufWriter::with_capacity(100_000, file);
+
+        writer.write_all(&ebml_header())?;
+
+        // Segment with its size written at the end
+        writer.write_all(&id_bytes(SEGMENT))?;
+        writer.write_all(&[0x01, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF])?;
+
+        let segment_data_start = writer.stream_position()?;
+        writer.write_all(&void_element(SEEK_HEAD_RESERVED_SIZE))?;
+
+        let info_position = writer.stream_position()?;
+        let info = info_element();
+        writer.write_all(&info)?;
+
+        // Duration is the last child of Info
+        let duration_position = writer.stream_position()? - 8;
+
+        let tracks_position = writer.stream_position()?;
+        writer.write_all(&tracks_element(track))?;
+
+        let mut mkv = MatroskaWriter {
+            writer,
+            segment_data_start,
+            cluster: Vec::new(),
+            cluster_timestamp: 0,
+            cluster_position: 0,
+            cue_points: Vec::new(),
+            duration: 0,
+        };
+
+        track.for_each_sample(|sample| mkv.add_sample(track, sample))?;
+        mkv.flush_cluster()?;
+
+        let cues_position = mkv.writer.stream_position()?;
+        mkv.write_cues()?;
+
+        let segment_end = mkv.writer.stream_position()?;
+
+        let positions = [
+            (INFO, info_position),
+            (TRACKS, tracks_position),
+            (CUES, cues_position),
+        ];
+        let seek_head = seek_head_element(&positions, segment_data_start);
+
+        let writer = &mut mkv.writer;
+
+        writer.seek(SeekFrom::Start(segment_data_start))?;
+        writer.write_all(&seek_head)?;
+        writer.write_all(&void_element(SEEK_HEAD_RESERVED_SIZE - seek_head.len()))?;
+
+        writer.seek(SeekFrom::Start(duration_position))?;
+        writer.write_all(&(mkv.duration as f64).to_be_bytes())?;
+
+        writer.seek(SeekFrom::Start(segment_data_start - 7))?;
+        writer.write_all(&(segment_end - segment_data_start).to_be_bytes()[1..])?;
+
+        writer.flush()?;
+
+        Ok(())
+    }
+
+    fn add_sample(&mut self, track: &HevcTrack, sample: Sample) -> Result<()> {
+        let timestamp = track.frame_rate.timestamp(
+            sample.presentation_number,
+            1_000_000_000 / TIMESTAMP_SCALE_NS,
+        );
+
+        let end = track.frame_rate.timestamp(
+            sample.presentation_number + 1,
+            1_000_000_000 / TIMESTAMP_SCALE_NS,
+        );
+        self.duration = self.duration.max(end);
+
+        let relative = timestamp as i64 - self.cluster_timestamp as i64;
+        let fits = relative >= i16::MIN as i64 && relative <= i16::MAX as i64;
+
+        if self.cluster.is_empty() || sample.keyframe || !fits {
+            self.flush_cluster()?;
+
+            self.cluster_timestamp = timestamp;
+            self.cluster_position = self.writer.stream_position()? - self.segment_data_start;
+            self.cluster.extend(uint_element(TIMESTAMP, timestamp));
+
+            if sample.keyframe {
+                self.cue_points.push((timestamp, self.cluster_position));
+            }
+        }
+
+        let relative = (timestamp as i64 - self.cluster_timestamp as i64) as i16;
+
+        // Track 1, keyframe flag
+        let mut block = vec![0x81];
+        block.extend_from_slice(&relative.to_be_bytes());
+        block.push(if sample.keyframe { 0x80 } else { 0 });
+        block.extend_from_slice(&sample.data);
+
+        self.cluster.extend(element(SIMPLE_BLOCK, &block));
+
+        Ok(())
+    }
+
+    fn flush_cluster(&mut self) -> Result<()> {
+        if !self.cluster.is_empty() {
+            self.writer.write_all(&element(CLUSTER, &self.cluster))?;
+            self.cluster.clear();
+        }
+
+        Ok(())
+    }
+
+    fn write_cues(&mut self) -> Result<()> {
+        let mut cues = Vec::new();
+
+        for (timestamp, cluster_position) in &self.cue_points {
+            let mut positions = uint_element(CUE_TRACK, 1);
+            positions.extend(uint_element(CUE_CLUSTER_POSITION, *cluster_position));
+
+            let mut cue_point = uint_element(CUE_TIME, *timestamp);
+            cue_point.extend(element(CUE_TRACK_POSITIONS, &positions));
+
+            cues.extend(element(CUE_POINT, &cue_point));
+        }
+
+        self.writer.write_all(&element(CUES, &cues))?;
+
+        Ok(())
+    }
+}
+
+fn ebml_header() -> Vec<u8> {
+    let mut data = uint_element(EBML_VERSION, 1);
+    data.extend(uint_element(EBML_READ_VERSION, 1));
+    data.extend(uint_element(EBML_MAX_ID_LENGTH, 4));
+    data.extend(uint_element(EBML_MAX_SIZE_LENGTH, 8));
+    data.extend(element(DOC_TYPE, b"matroska"));
+    data.extend(uint_element(DOC_TYPE_VERSION, 4));
+    data.extend(uint_element(DOC_TYPE_READ_VERSION, 2));
+
+    element(EBML, &data)
+}
+
+fn info_element() -> Vec<u8> {
+    let app = format!("dovi_tool {}", env!("CARGO_PKG_VERSION"));
+
+    let mut data = uint_element(TIMESTAMP_SCALE, TIMESTAMP_SCALE_NS);
+    data.extend(element(MUXING_APP, app.as_bytes()));
+    data.extend(element(WRITING_APP, app.as_bytes()));
+    data.extend(element(DURATION, &0.0_f64.to_be_bytes()));
+
+    element(INFO, &data)
+}
+
+fn tracks_element(track: &HevcTrack) -> Vec<u8> {
+    let (width, height) = track.sps.display_size();
+    let frame_rate = track.frame_rate;
+
+    let mut video = uint_element(PIXEL_WIDTH, width);
+    video.extend(uint_element(PIXEL_HEIGHT, height));
+
+    if let Some(vui) = &track.sps.vui {
+        let mut colour = uint_element(MATRIX_COEFFICIENTS, vui.matrix_coeffs as u64);
+        colour.extend(uint_element(
+            RANGE,
+            if vui.video_full_range_flag { 2 } else { 1 },
+        ));
+        colour.extend(uint_element(
+            TRANSFER_CHARACTERISTICS,
+            vui.transfer_characteristics as u64,
+        ));
+        colour.extend(uint_element(PRIMARIES, vui.colour_primaries as u64));
+
+        video.extend(element(COLOUR, &colour));
+    }
+
+    let mut entry = uint_element(TRACK_NUMBER, 1);
+    entry.extend(uint_element(TRACK_UID, 1));
+    entry.extend(uint_element(TRACK_TYPE, 1));
+    entry.extend(uint_element(FLAG_LACING, 0));
+    entry.extend(uint_element(
+        DEFAULT_DURATION,
+        (1_000_000_000 * frame_rate.den + frame_rate.num / 2) / frame_rate.num,
+    ));
+    entry.extend(element(CODEC_ID, b"V_MPEGH/ISO/HEVC"));
+    entry.extend(element(CODEC_PRIVATE, &track.hvcc));
+
+    if let Some(config) = &track.dovi_config {
+        let box_type = config.box_type().as_bytes();
+        let fourcc = u32::from_be_bytes([box_type[0], box_type[1], box_type[2], box_type[3]]);
+
+        let mut mapping = uint_element(BLOCK_ADD_ID_TYPE, fourcc as u64);
+        mapping.extend(element(BLOCK_ADD_ID_EXTRA_DATA, &config.write()));
+
+        entry.extend(element(BLOCK_ADDITION_MAPPING, &mapping));
+    }
+
+    entry.extend(element(VIDEO, &video));
+
+    element(TRACKS, &element(TRACK_ENTRY, &entry))
+}
+
+fn seek_head_element(positions: &[(u32, u64)], segment_data_start: u64) -> Vec<u8> {
+    let mut data = Vec::new();
+
+    for (id, position) in positions {
+        let mut seek = element(SEEK_ID, &id_bytes(*id));
+        seek.extend(element(
+            SEEK_POSITION,
+            &(position - segment_data_start).to_be_bytes(),
+        ));
+
+        data.extend(element(SEEK, &seek));
+    }
+
+    element(SEEK_HEAD, &data)
+}
+
+/// Void element taking exactly `size` bytes
+fn void_element(size: usize) -> Vec<u8> {
+    // 1 byte ID, 8 bytes size
+    let mut data = id_bytes(VOID);
+    data.push(0x01);
+    data.extend_from_slice(&(size as u64 - 9).to_be_bytes()[1..]);
+    data.resize(size, 0);
+
+    data
+}
+
+fn id_bytes(id: u32) -> Vec<u8> {
+    let bytes = id.to_be_bytes();
+    let skip = bytes.iter().take_while(|b| **b == 0).count();
+
+    bytes[skip..].to_vec()
+}
+
+fn size_vint(size: u64) -> Vec<u8> {
+    let mut len = 1;
+
+    while len < 8 && size >= (1 << (7 * len)) - 1 {
+        len += 1;
+    }
+
+    let bytes = (size | (1 << (7 * len))).to_be_bytes();
+
+    bytes[8 - len..].to_vec()
+}
+
+fn element(id: u32, payload: &[u8]) -> Vec<u8> {
+    let mut data = id_bytes(id);
+    data.extend(size_vint(payload.len() as u64));
+    data.extend_from_slice(payload);
+
+    data
+}
+
+fn uint_element(id: u32, value: u64) -> Vec<u8> {
+    let bytes = value.to_be_bytes();
+    let skip = bytes.iter().take_while(|b| **b == 0).count().min(7);
+
+    element(id, &bytes[skip..])
+}
@@ -3,9 +3,22 @@ pub mod demuxer;
 pub mod editor;
 pub mod exporter;
 pub mod generator;
+pub mod hdr10plus;
+pub mod hevc_info;
+pub mod letterbox;
+pub mod madvr;
+pub mod matroska;
+pub mod mp4;
+pub mod muxer;
+pub mod remover;
 pub mod rpu_extractor;
 pub mod rpu_info;
 pub mod rpu_injector;
+pub mod rpu_splitter;
+pub mod sei;
+pub mod sync;
+pub mod temporal;
+pub mod video_analysis;
 
 mod io;
 
@@ -30,6 +43,9 @@ use hevc_parser::{
     HevcParser, NALUStartCode,
 };
 use rpu::dovi_rpu::DoviRpu;
+use rpu::extension_metadata::TargetDisplay;
+
+use muxer::FrameRate;
 
 const OUT_NAL_HEADER: &[u8] = &[0, 0, 0, 1];
 
@@ -46,6 +62,8 @@ pub struct CliOptions {
     pub crop: bool,
     pub discard_el: bool,
     pub drop_hdr10plus: bool,
+    pub hdr10_sei: bool,
+    pub fps: Option<FrameRate>,
 }
 
 pub fn initialize_progress_bar(format: &Format, input: &Path) -> Result<ProgressBar> {
@@ -224,3 +242,50 @@ pub fn is_st2094_40_sei(sei_payload: &[u8]) -> Result<bool> {
 
     Ok(false)
 }
+
+/// Serializes a RPU to JSON, with the target displays of the L2, L8 and L10 blocks decoded
+pub fn rpu_to_json_with_targets(rpu: &DoviRpu) -> Result<serde_json::Value> {
+    let mut value = serde_json::to_value(rpu)?;
+
+    let vdr_dm_data = match &rpu.vdr_dm_data {
+        Some(vdr_dm_data) => vdr_dm_data,
+        None => return Ok(value),
+    };
+
+    for cm_key in &["cmv29_metadata", "cmv40_metadata"] {
+        let blocks = value
+            .pointer_mut(&format!("/vdr_dm_data/{}/ext_metadata_blocks", cm_key))
+            .and_then(|v| v.as_array_mut());
+
+        for block in blocks.into_iter().flatten() {
+            let (level, block) = match block.as_object_mut().and_then(|b| b.iter_mut().next()) {
+                Some((level, block)) => (level.as_str(), block),
+                None => continue,
+            };
+
+            match level {
+                "Level2" => {
+                    let max_pq = block.get("target_max_pq").and_then(|v| v.as_u64());
+
+                    if let Some(max_pq) = max_pq {
+                        let nits = TargetDisplay::peak_nits_from_max_pq(max_pq as u16);
+                        block["target_nits"] = nits.into();
+                    }
+                }
+                "Level8" | "Level10" => {
+                    let display = block
+                        .get("target_display_index")
+                        .and_then(|v| v.as_u64())
+                        .and_then(|index| vdr_dm_data.target_display(index as u8));
+
+                    if let Some(display) = display {
+                        block["target_display"] = display.to_string().into();
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+
+    Ok(value)
+}
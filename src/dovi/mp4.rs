@@ -0,0 +1,324 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use anyhow::{bail, ensure, Result};
+
+use super::muxer::HevcTrack;
+
+/// Identity transformation matrix of the movie and track headers
+const UNITY_MATRIX: [u32; 9] = [0x0001_0000, 0, 0, 0, 0x0001_0000, 0, 0, 0, 0x4000_0000];
+
+/// Minimal progressive MP4 muxer for a single HEVC video track.
+/// The `moov` box is written first, followed by the samples in a single chunk.
+pub struct Mp4Writer {
+    sample_sizes: Vec<u32>,
+    sync_samples: Vec<u32>,
+    presentation_numbers: Vec<u64>,
+}
+
+impl Mp4Writer {
+    pub fn write(track: &HevcTrack, output: &Path) -> Result<()> {
+        if let Some(config) = &track.dovi_config {
+            if config.el_present_flag {
+                bail!(
+                    "Profile {} with an enhancement layer is not supported for MP4 output",
+                    config.dv_profile
+                );
+            }
+        }
+
+        // First pass for the sample tables
+        let mut mp4 = Mp4Writer {
+            sample_sizes: Vec::with_capacity(track.frame_count()),
+            sync_samples: Vec::new(),
+            presentation_numbers: Vec::with_capacity(track.frame_count()),
+        };
+
+        track.for_each_sample(|sample| {
+            mp4.sample_sizes.push(sample.data.len() as u32);
+            mp4.presentation_numbers.push(sample.presentation_number);
+
+            if sample.keyframe {
+                mp4.sync_samples.push(mp4.sample_sizes.len() as u32);
+            }
+
+            Ok(())
+        })?;
+
+        let ftyp = ftyp_box(track.dovi_config.is_some());
+
+        // The moov size doesn't depend on the chunk offset, mdat has a 64 bits size
+        let moov_size = mp4.moov_box(track, 0).len() as u64;
+        let chunk_offset = ftyp.len() as u64 + moov_size + 16;
+        let mdat_size = 16 + mp4.sample_sizes.iter().map(|s| *s as u64).sum::<u64>();
+
+        let file = File::create(output)?;
+        let mut writer = BufWriter::with_capacity(100_000, file);
+
+        writer.write_all(&ftyp)?;
+        writer.write_all(&mp4.moov_box(track, chunk_offset))?;
+
+        writer.write_all(&[0, 0, 0, 1])?;
+        writer.write_all(b"mdat")?;
+        writer.write_all(&mdat_size.to_be_bytes())?;
+
+        let mut index = 0;
+
+        track.for_each_sample(|sample| {
+            ensure!(
+                mp4.sample_sizes.get(index) == Some(&(sample.data.len() as u32)),
+                "Sample {} changed between passes",
+                index
+            );
+
+            writer.write_all(&sample.data)?;
+            index += 1;
+
+            Ok(())
+        })?;
+
+        writer.flush()?;
+
+        Ok(())
+    }
+
+    fn moov_box(&self, track: &HevcTrack, chunk_offset: u64) -> Vec<u8> {
+        let timescale = track.frame_rate.num as u32;
+        let sample_delta = track.frame_rate.den;
+        let duration = self.sample_sizes.len() as u64 * sample_delta;
+
+        // Presentation delay so that the composition offsets are positive
+        let delay = self
+            .presentation_numbers
+            .iter()
+            .enumerate()
+            .map(|(i, pts)| i as i64 - *pts as i64)
+            .max()
+            .unwrap_or(0)
+            .max(0) as u64;
+
+        // mvhd
+        let mut mvhd = Vec::new();
+        mvhd.extend_from_slice(&[0; 16]); // creation_time, modification_time
+        mvhd.extend_from_slice(&timescale.to_be_bytes());
+        mvhd.extend_from_slice(&duration.to_be_bytes());
+        mvhd.extend_from_slice(&0x0001_0000_u32.to_be_bytes()); // rate
+        mvhd.extend_from_slice(&0x0100_u16.to_be_bytes()); // volume
+        mvhd.extend_from_slice(&[0; 10]);
+        UNITY_MATRIX
+            .iter()
+            .for_each(|v| mvhd.extend_from_slice(&v.to_be_bytes()));
+        mvhd.extend_from_slice(&[0; 24]); // pre_defined
+        mvhd.extend_from_slice(&2_u32.to_be_bytes()); // next_track_ID
+
+        let mut moov = full_box(b"mvhd", 1, 0, &mvhd);
+        moov.extend(self.trak_box(track, chunk_offset, duration, delay * sample_delta));
+
+        mp4_box(b"moov", &moov)
+    }
+
+    fn trak_box(&self, track: &HevcTrack, chunk_offset: u64, duration: u64, delay: u64) -> Vec<u8> {
+        let (width, height) = track.sps.display_size();
+
+        // tkhd, enabled and in movie
+        let mut tkhd = Vec::new();
+        tkhd.extend_from_slice(&[0; 16]); // creation_time, modification_time
+        tkhd.extend_from_slice(&1_u32.to_be_bytes()); // track_ID
+        tkhd.extend_from_slice(&[0; 4]);
+        tkhd.extend_from_slice(&duration.to_be_bytes());
+        tkhd.extend_from_slice(&[0; 16]); // reserved, layer, alternate_group, volume, reserved
+        UNITY_MATRIX
+            .iter()
+            .for_each(|v| tkhd.extend_from_slice(&v.to_be_bytes()));
+        tkhd.extend_from_slice(&((width as u32) << 16).to_be_bytes());
+        tkhd.extend_from_slice(&((height as u32) << 16).to_be_bytes());
+
+        let mut trak = full_box(b"tkhd", 1, 3, &tkhd);
+
+        if delay > 0 {
+            let mut elst = 1_u32.to_be_bytes().to_vec();
+            elst.extend_from_slice(&duration.to_be_bytes()); // segment_duration
+            elst.extend_from_slice(&delay.to_be_bytes()); // media_time
+            elst.extend_from_slice(&0x0001_0000_u32.to_be_bytes()); // media_rate
+
+            trak.extend(mp4_box(b"edts", &full_box(b"elst", 1, 0, &elst)));
+        }
+
+        // mdhd, undetermined language
+        let mut mdhd = Vec::new();
+        mdhd.extend_from_slice(&[0; 16]);
+        mdhd.extend_from_slice(&(track.frame_rate.num as u32).to_be_bytes());
+        mdhd.extend_from_slice(&duration.to_be_bytes());
+        mdhd.extend_from_slice(&[0x55, 0xC4, 0, 0]);
+
+        let mut hdlr = vec![0; 4];
+        hdlr.extend_from_slice(b"vide");
+        hdlr.extend_from_slice(&[0; 12]);
+        hdlr.extend_from_slice(b"VideoHandler\0");
+
+        let dref = full_box(b"url ", 0, 1, &[]);
+        let mut dref_entries = 1_u32.to_be_bytes().to_vec();
+        dref_entries.extend(dref);
+
+        let mut minf = full_box(b"vmhd", 0, 1, &[0; 8]);
+        minf.extend(mp4_box(b"dinf", &full_box(b"dref", 0, 0, &dref_entries)));
+        minf.extend(self.stbl_box(track, chunk_offset, delay));
+
+        let mut mdia = full_box(b"mdhd", 1, 0, &mdhd);
+        mdia.extend(full_box(b"hdlr", 0, 0, &hdlr));
+        mdia.extend(mp4_box(b"minf", &minf));
+
+        trak.extend(mp4_box(b"mdia", &mdia));
+
+        mp4_box(b"trak", &trak)
+    }
+
+    fn stbl_box(&self, track: &HevcTrack, chunk_offset: u64, delay: u64) -> Vec<u8> {
+        let sample_count = self.sample_sizes.len() as u32;
+        let sample_delta = track.frame_rate.den;
+
+        let mut stsd = 1_u32.to_be_bytes().to_vec();
+        stsd.extend(self.sample_entry(track));
+
+        let mut stts = 1_u32.to_be_bytes().to_vec();
+        stts.extend_from_slice(&sample_count.to_be_bytes());
+        stts.extend_from_slice(&(sample_delta as u32).to_be_bytes());
+
+        // Run length encoded composition offsets
+        let mut ctts_entries: Vec<(u32, u32)> = Vec::new();
+
+        for (i, pts) in self.presentation_numbers.iter().enumerate() {
+            let offset = ((*pts * sample_delta + delay) - i as u64 * sample_delta) as u32;
+
+            match ctts_entries.last_mut() {
+                Some((count, last_offset)) if *last_offset == offset => *count += 1,
+                _ => ctts_entries.push((1, offset)),
+            }
+        }
+
+        let mut stbl = full_box(b"stsd", 0, 0, &stsd);
+        stbl.extend(full_box(b"stts", 0, 0, &stts));
+
+        if ctts_entries.len() > 1 || ctts_entries.iter().any(|(_, offset)| *offset != 0) {
+            let mut ctts = (ctts_entries.len() as u32).to_be_bytes().to_vec();
+
+            for (count, offset) in ctts_entries {
+                ctts.extend_from_slice(&count.to_be_bytes());
+                ctts.extend_from_slice(&offset.to_be_bytes());
+            }
+
+            stbl.extend(full_box(b"ctts", 0, 0, &ctts));
+        }
+
+        // All samples are sync samples when absent
+        if self.sync_samples.len() < self.sample_sizes.len() {
+            let mut stss = (self.sync_samples.len() as u32).to_be_bytes().to_vec();
+            self.sync_samples
+                .iter()
+                .for_each(|s| stss.extend_from_slice(&s.to_be_bytes()));
+
+            stbl.extend(full_box(b"stss", 0, 0, &stss));
+        }
+
+        // Single chunk with all the samples
+        let mut stsc = 1_u32.to_be_bytes().to_vec();
+        stsc.extend_from_slice(&1_u32.to_be_bytes());
+        stsc.extend_from_slice(&sample_count.to_be_bytes());
+        stsc.extend_from_slice(&1_u32.to_be_bytes());
+
+        let mut stsz = 0_u32.to_be_bytes().to_vec();
+        stsz.extend_from_slice(&sample_count.to_be_bytes());
+        self.sample_sizes
+            .iter()
+            .for_each(|s| stsz.extend_from_slice(&s.to_be_bytes()));
+
+        let mut co64 = 1_u32.to_be_bytes().to_vec();
+        co64.extend_from_slice(&chunk_offset.to_be_bytes());
+
+        stbl.extend(full_box(b"stsc", 0, 0, &stsc));
+        stbl.extend(full_box(b"stsz", 0, 0, &stsz));
+        stbl.extend(full_box(b"co64", 0, 0, &co64));
+
+        mp4_box(b"stbl", &stbl)
+    }
+
+    /// `hvc1` for backwards compatible profiles, `dvh1` otherwise.
+    /// `hev1`/`dvhe` when parameter sets are also in the samples.
+    fn sample_entry(&self, track: &HevcTrack) -> Vec<u8> {
+        let (width, height) = track.sps.display_size();
+
+        let compatible = track
+            .dovi_config
+            .as_ref()
+            .map_or(true, |config| config.dv_bl_signal_compatibility_id != 0);
+
+        let entry_type = match (compatible, track.in_band_parameter_sets) {
+            (true, false) => b"hvc1",
+            (true, true) => b"hev1",
+            (false, false) => b"dvh1",
+            (false, true) => b"dvhe",
+        };
+
+        let mut entry = vec![0; 6];
+        entry.extend_from_slice(&1_u16.to_be_bytes()); // data_reference_index
+        entry.extend_from_slice(&[0; 16]);
+        entry.extend_from_slice(&(width as u16).to_be_bytes());
+        entry.extend_from_slice(&(height as u16).to_be_bytes());
+        entry.extend_from_slice(&0x0048_0000_u32.to_be_bytes()); // horizresolution
+        entry.extend_from_slice(&0x0048_0000_u32.to_be_bytes()); // vertresolution
+        entry.extend_from_slice(&[0; 4]);
+        entry.extend_from_slice(&1_u16.to_be_bytes()); // frame_count
+        entry.extend_from_slice(&[0; 32]); // compressorname
+        entry.extend_from_slice(&0x0018_u16.to_be_bytes()); // depth
+        entry.extend_from_slice(&0xFFFF_u16.to_be_bytes());
+
+        entry.extend(mp4_box(b"hvcC", &track.hvcc));
+
+        if let Some(vui) = &track.sps.vui {
+            let mut colr = b"nclx".to_vec();
+            colr.extend_from_slice(&(vui.colour_primaries as u16).to_be_bytes());
+            colr.extend_from_slice(&(vui.transfer_characteristics as u16).to_be_bytes());
+            colr.extend_from_slice(&(vui.matrix_coeffs as u16).to_be_bytes());
+            colr.push(if vui.video_full_range_flag { 0x80 } else { 0 });
+
+            entry.extend(mp4_box(b"colr", &colr));
+        }
+
+        if let Some(config) = &track.dovi_config {
+            let box_type = config.box_type().as_bytes();
+            let box_type = [box_type[0], box_type[1], box_type[2], box_type[3]];
+
+            entry.extend(mp4_box(&box_type, &config.write()));
+        }
+
+        mp4_box(entry_type, &entry)
+    }
+}
+
+fn ftyp_box(dolby_vision: bool) -> Vec<u8> {
+    let mut ftyp = b"isom".to_vec();
+    ftyp.extend_from_slice(&0x200_u32.to_be_bytes());
+    ftyp.extend_from_slice(b"isomiso6mp41");
+
+    if dolby_vision {
+        ftyp.extend_from_slice(b"dby1");
+    }
+
+    mp4_box(b"ftyp", &ftyp)
+}
+
+fn mp4_box(box_type: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+    let mut data = ((payload.len() + 8) as u32).to_be_bytes().to_vec();
+    data.extend_from_slice(box_type);
+    data.extend_from_slice(payload);
+
+    data
+}
+
+fn full_box(box_type: &[u8; 4], version: u8, flags: u32, payload: &[u8]) -> Vec<u8> {
+    let mut data = ((version as u32) << 24 | flags).to_be_bytes().to_vec();
+    data.extend_from_slice(payload);
+
+    mp4_box(box_type, &data)
+}
@@ -0,0 +1,410 @@
+use std::fs::OpenOptions;
+use std::io::{stdout, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use anyhow::{bail, format_err, Result};
+
+use dolby_vision::rpu::configuration_record::DoviDecoderConfigurationRecord;
+use hevc_parser::hevc::{NAL_AUD, NAL_PPS, NAL_SEI_PREFIX, NAL_SPS, NAL_UNSPEC62, NAL_VPS};
+
+use super::hevc_info::{find_first_nal, SpsInfo};
+use super::io::NalReader;
+use super::matroska::MatroskaWriter;
+use super::mp4::Mp4Writer;
+use super::DoviRpu;
+
+/// Frame rate as a fraction, e.g. 24000/1001
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameRate {
+    pub num: u64,
+    pub den: u64,
+}
+
+/// Container of the `convert` and `inject-rpu` output, from the file extension
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputContainer {
+    AnnexB,
+    Matroska,
+    Mp4,
+}
+
+/// Raw HEVC stream prepared for muxing, with the track level metadata
+pub struct HevcTrack {
+    path: PathBuf,
+
+    pub sps: SpsInfo,
+    pub frame_rate: FrameRate,
+    pub hvcc: Vec<u8>,
+    pub dovi_config: Option<DoviDecoderConfigurationRecord>,
+
+    /// Parameter sets that differ from the hvcC ones are in the samples
+    pub in_band_parameter_sets: bool,
+
+    parameter_sets: Vec<Vec<u8>>,
+    presentation_numbers: Vec<u64>,
+}
+
+/// One access unit, in decoding order
+pub struct Sample {
+    /// NAL units prefixed with their 4 bytes length
+    pub data: Vec<u8>,
+    pub keyframe: bool,
+    pub presentation_number: u64,
+}
+
+impl FrameRate {
+    /// Snaps NTSC rates like 23.976 to their exact fraction
+    pub fn from_f64(fps: f64) -> FrameRate {
+        let ntsc = (fps * 1.001).round();
+
+        if (fps * 1.001 - ntsc).abs() < 0.001 && (fps - fps.round()).abs() > 0.001 {
+            FrameRate {
+                num: ntsc as u64 * 1000,
+                den: 1001,
+            }
+        } else {
+            FrameRate {
+                num: (fps * 1000.0).round() as u64,
+                den: 1000,
+            }
+            .reduced()
+        }
+    }
+
+    pub fn as_f64(&self) -> f64 {
+        self.num as f64 / self.den as f64
+    }
+
+    /// Timestamp of a frame, in units of `timescale` per second
+    pub fn timestamp(&self, frame: u64, timescale: u64) -> u64 {
+        (frame * timescale * self.den + self.num / 2) / self.num
+    }
+
+    fn reduced(self) -> FrameRate {
+        let (mut a, mut b) = (self.num, self.den);
+
+        while b != 0 {
+            let t = b;
+            b = a % b;
+            a = t;
+        }
+
+        FrameRate {
+            num: self.num / a,
+            den: self.den / a,
+        }
+    }
+}
+
+impl FromStr for FrameRate {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let frame_rate = match s.find('/') {
+            Some(pos) => FrameRate {
+                num: s[..pos].trim().parse()?,
+                den: s[pos + 1..].trim().parse()?,
+            }
+            .reduced(),
+            None => FrameRate::from_f64(s.trim().parse()?),
+        };
+
+        if frame_rate.num == 0 || frame_rate.den == 0 {
+            bail!("Invalid frame rate {}", s);
+        }
+
+        Ok(frame_rate)
+    }
+}
+
+impl OutputContainer {
+    pub fn from_path(path: &Path) -> OutputContainer {
+        let extension = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| ext.to_ascii_lowercase());
+
+        match extension.as_deref() {
+            Some("mkv") => OutputContainer::Matroska,
+            Some("mp4") | Some("m4v") => OutputContainer::Mp4,
+            _ => OutputContainer::AnnexB,
+        }
+    }
+
+    /// Writes the HEVC stream with `write`, then muxes it into the output container.
+    /// For Matroska and MP4, the stream is written to a temporary file, removed afterwards.
+    pub fn write<F>(&self, output: &Path, frame_rate: Option<FrameRate>, write: F) -> Result<()>
+    where
+        F: FnOnce(&Path) -> Result<()>,
+    {
+        if *self == OutputContainer::AnnexB {
+            return write(output);
+        }
+
+        let annexb_path = OutputContainer::create_temp_path(output)?;
+
+        let result = write(&annexb_path).and_then(|_| self.mux(&annexb_path, output, frame_rate));
+        let removed = std::fs::remove_file(&annexb_path);
+
+        result?;
+        removed?;
+
+        Ok(())
+    }
+
+    /// Creates an empty temporary file next to the output, without replacing existing files
+    fn create_temp_path(output: &Path) -> Result<PathBuf> {
+        for i in 0..1000 {
+            let path = output.with_extension(format!("{}.{}.tmp.hevc", std::process::id(), i));
+
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(_) => return Ok(path),
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        bail!(
+            "Could not create a temporary file next to {}",
+            output.display()
+        )
+    }
+
+    fn mux(&self, annexb_path: &Path, output: &Path, frame_rate: Option<FrameRate>) -> Result<()> {
+        let track = HevcTrack::open(annexb_path, frame_rate)?;
+
+        println!("Muxing to {}...", output.display());
+        stdout().flush().ok();
+
+        match self {
+            OutputContainer::Matroska => MatroskaWriter::write(&track, output),
+            OutputContainer::Mp4 => Mp4Writer::write(&track, output),
+            OutputContainer::AnnexB => unreachable!(),
+        }
+    }
+}
+
+impl HevcTrack {
+    /// Reads the parameter sets, frame order and Dolby Vision configuration of a raw HEVC file
+    pub fn open(path: &Path, frame_rate: Option<FrameRate>) -> Result<HevcTrack> {
+        let mut reader = NalReader::open(path)?;
+        let mut parameter_sets: Vec<Vec<u8>> = Vec::new();
+        let mut found_slice = false;
+        let mut in_band_parameter_sets = false;
+
+        reader.read_nals(true, |nal, data| {
+            if !matches!(nal.nal_type, NAL_VPS | NAL_SPS | NAL_PPS) {
+                found_slice |= nal.nuh_layer_id == 0 && is_vcl(nal.nal_type);
+            } else if !parameter_sets.iter().any(|ps| ps == data) {
+                // The base layer parameter sets before the first slice go in the hvcC
+                if nal.nuh_layer_id == 0 && !found_slice {
+                    parameter_sets.push(data.to_vec());
+                } else {
+                    in_band_parameter_sets = true;
+                }
+            }
+
+            Ok(true)
+        })?;
+
+        let parser = reader.parser();
+        parser.finish();
+
+        let frames = parser.ordered_frames();
+
+        if frames.is_empty() {
+            bail!("No frames parsed!");
+        }
+
+        let mut presentation_numbers = vec![0; frames.len()];
+
+        for frame in frames {
+            let index = frame.decoded_number as usize;
+
+            match presentation_numbers.get_mut(index) {
+                Some(number) => *number = frame.presentation_number,
+                None => bail!("Invalid decoded frame number {}", index),
+            }
+        }
+
+        let sps = match parameter_sets.iter().find(|ps| ps[0] >> 1 == NAL_SPS) {
+            Some(data) => SpsInfo::parse(data)?,
+            None => bail!("No SPS found before the first slice"),
+        };
+
+        let frame_rate = match frame_rate.or_else(|| sps.frame_rate().map(FrameRate::from_f64)) {
+            Some(frame_rate) => frame_rate,
+            None => bail!("No timing info in the HEVC SPS, the frame rate must be set with --fps"),
+        };
+
+        let dovi_config = match find_first_nal(path, NAL_UNSPEC62)? {
+            Some(data) => {
+                let rpu = DoviRpu::parse_unspec62_nalu(&data)?;
+                let (width, height) = sps.display_size();
+
+                let level = DoviDecoderConfigurationRecord::level_from_stream(
+                    width,
+                    height,
+                    frame_rate.as_f64(),
+                )
+                .ok_or_else(|| format_err!("No Dolby Vision level supports the stream"))?;
+
+                let transfer = sps.vui.as_ref().map(|vui| vui.transfer_characteristics);
+
+                Some(DoviDecoderConfigurationRecord::from_rpu(
+                    &rpu, level, transfer,
+                )?)
+            }
+            None => {
+                println!("Warning: no RPU found, Dolby Vision will not be signalled");
+                None
+            }
+        };
+
+        let hvcc = hvcc_payload(&sps, &parameter_sets, in_band_parameter_sets);
+
+        Ok(HevcTrack {
+            path: path.to_path_buf(),
+            sps,
+            frame_rate,
+            hvcc,
+            dovi_config,
+            in_band_parameter_sets,
+            parameter_sets,
+            presentation_numbers,
+        })
+    }
+
+    pub fn frame_count(&self) -> usize {
+        self.presentation_numbers.len()
+    }
+
+    /// Calls `f` for each access unit, in decoding order.
+    /// The parameter sets already in the hvcC are removed from the samples.
+    pub fn for_each_sample<F: FnMut(Sample) -> Result<()>>(&self, mut f: F) -> Result<()> {
+        let mut current: Option<Sample> = None;
+        let mut has_vcl = false;
+        let mut decoded_index = 0;
+
+        NalReader::open(&self.path)?.read_nals(false, |nal, data| {
+            let layer_id = ((data[0] & 0x01) << 5) | (data[1] >> 3);
+            let vcl = is_vcl(nal.nal_type);
+
+            let parameter_set = matches!(nal.nal_type, NAL_VPS | NAL_SPS | NAL_PPS);
+            let first_slice = vcl && data.len() > 2 && data[2] & 0x80 != 0;
+            let prefix_nal = parameter_set
+                || matches!(nal.nal_type, NAL_AUD | NAL_SEI_PREFIX | 41..=44 | 48..=55);
+
+            let starts_au = layer_id == 0 && has_vcl && (first_slice || prefix_nal);
+
+            if starts_au {
+                if let Some(sample) = current.take() {
+                    f(sample)?;
+                }
+
+                has_vcl = false;
+            }
+
+            let sample = current.get_or_insert_with(|| {
+                let presentation_number = self
+                    .presentation_numbers
+                    .get(decoded_index)
+                    .copied()
+                    .unwrap_or(decoded_index as u64);
+
+                decoded_index += 1;
+
+                Sample {
+                    data: Vec::new(),
+                    keyframe: false,
+                    presentation_number,
+                }
+            });
+
+            if vcl && layer_id == 0 {
+                has_vcl = true;
+                sample.keyframe |= (16..=23).contains(&nal.nal_type);
+            }
+
+            if !self.parameter_sets.iter().any(|ps| ps == data) {
+                sample
+                    .data
+                    .extend_from_slice(&(data.len() as u32).to_be_bytes());
+                sample.data.extend_from_slice(data);
+            }
+
+            Ok(true)
+        })?;
+
+        if let Some(sample) = current {
+            f(sample)?;
+        }
+
+        if decoded_index != self.frame_count() {
+            bail!(
+                "Mismatched frame count: {} access units, {} frames parsed",
+                decoded_index,
+                self.frame_count()
+            );
+        }
+
+        Ok(())
+    }
+}
+
+fn is_vcl(nal_type: u8) -> bool {
+    nal_type < 32
+}
+
+/// HEVC decoder configuration record.
+/// The parameter set arrays are complete unless parameter sets are also in the samples.
+fn hvcc_payload(sps: &SpsInfo, parameter_sets: &[Vec<u8>], in_band: bool) -> Vec<u8> {
+    let mut data = vec![
+        1,
+        (sps.profile_space << 6) | ((sps.tier_flag as u8) << 5) | sps.profile_idc,
+    ];
+
+    data.extend_from_slice(&sps.profile_compatibility_flags.to_be_bytes());
+    data.extend_from_slice(&sps.constraint_indicator_flags);
+    data.push(sps.level_idc);
+
+    // min_spatial_segmentation_idc, parallelismType
+    data.extend_from_slice(&[0xF0, 0x00, 0xFC]);
+    data.push(0xFC | sps.chroma_format_idc as u8);
+    data.push(0xF8 | (sps.bit_depth - 8) as u8);
+    data.push(0xF8 | (sps.bit_depth_chroma - 8) as u8);
+
+    // avgFrameRate, unknown temporal layers, 4 bytes NAL length
+    data.extend_from_slice(&[0, 0, 0x03]);
+
+    let mut arrays = Vec::with_capacity(3);
+
+    for nal_type in &[NAL_VPS, NAL_SPS, NAL_PPS] {
+        let nals: Vec<&Vec<u8>> = parameter_sets
+            .iter()
+            .filter(|ps| ps[0] >> 1 == *nal_type)
+            .collect();
+
+        if !nals.is_empty() {
+            arrays.push((*nal_type, nals));
+        }
+    }
+
+    data.push(arrays.len() as u8);
+
+    // array_completeness, unset when parameter sets are also in the samples
+    let array_completeness = if in_band { 0 } else { 0x80 };
+
+    for (nal_type, nals) in arrays {
+        data.push(array_completeness | nal_type);
+        data.extend_from_slice(&(nals.len() as u16).to_be_bytes());
+
+        for nal in nals {
+            data.extend_from_slice(&(nal.len() as u16).to_be_bytes());
+            data.extend_from_slice(nal);
+        }
+    }
+
+    data
+}
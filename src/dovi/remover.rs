@@ -0,0 +1,79 @@
+use anyhow::{bail, Result};
+use indicatif::ProgressBar;
+use std::path::PathBuf;
+
+use super::{input_format, io, CliOptions, Format};
+
+use io::{DoviReader, DoviWriter};
+
+pub struct Remover {
+    format: Format,
+    input: PathBuf,
+    output: PathBuf,
+}
+
+impl Remover {
+    pub fn new(format: Format, input: PathBuf, output: PathBuf) -> Self {
+        Self {
+            format,
+            input,
+            output,
+        }
+    }
+
+    pub fn remove(
+        input: Option<PathBuf>,
+        stdin: Option<PathBuf>,
+        output: Option<PathBuf>,
+        options: CliOptions,
+    ) -> Result<()> {
+        let input = match input {
+            Some(input) => input,
+            None => match stdin {
+                Some(stdin) => stdin,
+                None => PathBuf::new(),
+            },
+        };
+
+        let format = input_format(&input)?;
+
+        let output = match output {
+            Some(path) => path,
+            None => PathBuf::from("BL_removed.hevc"),
+        };
+
+        let remover = Remover::new(format, input, output);
+        remover.process_input(options)
+    }
+
+    fn process_input(&self, options: CliOptions) -> Result<()> {
+        let pb = super::initialize_progress_bar(&self.format, &self.input)?;
+
+        match self.format {
+            Format::Matroska => bail!("unsupported"),
+            _ => self.remove_from_raw_hevc(Some(&pb), options),
+        }
+    }
+
+    fn remove_from_raw_hevc(&self, pb: Option<&ProgressBar>, options: CliOptions) -> Result<()> {
+        let drop_hdr10plus = options.drop_hdr10plus;
+
+        let mut dovi_reader = DoviReader::new(options);
+
+        // Everything but the RPU and EL NALUs goes to the BL output
+        let mut dovi_writer = DoviWriter::new(Some(&self.output), None, None, None);
+
+        dovi_reader.read_write_from_io(&self.format, &self.input, pb, &mut dovi_writer)?;
+
+        let dropped = dovi_reader.dropped_nals();
+
+        println!("Removed {} RPU NALUs", dropped.rpu);
+        println!("Removed {} EL NALUs", dropped.el);
+
+        if drop_hdr10plus {
+            println!("Removed {} HDR10+ SEI NALUs", dropped.hdr10plus);
+        }
+
+        Ok(())
+    }
+}
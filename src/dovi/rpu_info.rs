@@ -1,9 +1,13 @@
 use anyhow::{bail, ensure, Result};
-use std::path::PathBuf;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
 
+use dolby_vision::rpu::configuration_record::DoviDecoderConfigurationRecord;
 use dolby_vision::rpu::dovi_rpu::DoviRpu;
 
-use super::parse_rpu_file;
+use super::hevc_info::SpsInfo;
+use super::{parse_rpu_file, rpu_to_json_with_targets};
 
 pub struct RpuInfo {
     input: PathBuf,
@@ -12,7 +16,13 @@ pub struct RpuInfo {
 }
 
 impl RpuInfo {
-    pub fn info(input: PathBuf, frame: Option<usize>) -> Result<()> {
+    pub fn info(
+        input: PathBuf,
+        frame: Option<usize>,
+        hevc: Option<PathBuf>,
+        fps: Option<f64>,
+        config_out: Option<PathBuf>,
+    ) -> Result<()> {
         let mut info = RpuInfo {
             input,
             frame,
@@ -22,19 +32,106 @@ impl RpuInfo {
         info.rpus = parse_rpu_file(&info.input)?;
 
         if let Some(ref rpus) = info.rpus {
+            if let Some(hevc) = hevc {
+                print_configuration_record(&rpus[0], &hevc, fps, config_out)?;
+            } else if info.frame.is_none() {
+                bail!("No frame number to look up");
+            }
+
             if let Some(f) = info.frame {
                 ensure!(f < rpus.len(), format!("info: invalid frame number (out of range).\nNumber of valid RPUs parsed: {}", rpus.len()));
 
                 let rpu = &rpus[f];
 
-                if let Ok(rpu_serialized) = serde_json::to_string_pretty(&rpu) {
+                if let Ok(rpu_serialized) =
+                    serde_json::to_string_pretty(&rpu_to_json_with_targets(rpu)?)
+                {
                     println!("{}", rpu_serialized);
                 }
-            } else {
-                bail!("No frame number to look up");
             }
         }
 
         Ok(())
     }
 }
+
+/// Prints the dvcC/dvvC payload and the manifest codec strings for the RPU and HEVC stream
+fn print_configuration_record(
+    rpu: &DoviRpu,
+    hevc: &Path,
+    fps: Option<f64>,
+    config_out: Option<PathBuf>,
+) -> Result<()> {
+    let sps = SpsInfo::from_hevc_file(hevc)?;
+    let (width, height) = sps.display_size();
+
+    let fps = match fps.or_else(|| sps.frame_rate()) {
+        Some(fps) => fps,
+        None => bail!("No timing info in the HEVC SPS, the frame rate must be set with --fps"),
+    };
+
+    let dv_level = match DoviDecoderConfigurationRecord::level_from_stream(width, height, fps) {
+        Some(level) => level,
+        None => bail!(
+            "No Dolby Vision level supports {}x{} at {:.3} fps",
+            width,
+            height,
+            fps
+        ),
+    };
+
+    let transfer_characteristics = sps.vui.as_ref().map(|vui| vui.transfer_characteristics);
+    let record = DoviDecoderConfigurationRecord::from_rpu(rpu, dv_level, transfer_characteristics)?;
+
+    let payload = record.write();
+
+    println!(
+        "Dolby Vision profile {}, level {} ({}x{} at {:.3} fps), BL signal compatibility ID {}",
+        record.dv_profile,
+        record.dv_level,
+        width,
+        height,
+        fps,
+        record.dv_bl_signal_compatibility_id
+    );
+
+    let hex: Vec<String> = payload.iter().map(|b| format!("{:02X}", b)).collect();
+    println!("{} payload: {}", record.box_type(), hex.join(" "));
+
+    let dv_codec = record.codec_string(false);
+    println!(
+        "Codec strings: {} (hvc1), {} (hev1)",
+        dv_codec,
+        record.codec_string(true)
+    );
+
+    match record.compatibility_brand() {
+        Some(brand) => {
+            let base_codec = sps.codec_string("hvc1");
+
+            println!(
+                "HLS: CODECS=\"{}\",SUPPLEMENTAL-CODECS=\"{}/{}\"",
+                base_codec, dv_codec, brand
+            );
+            println!(
+                "DASH: codecs=\"{}\" scte214:supplementalCodecs=\"{}\" scte214:supplementalProfiles=\"{}\"",
+                base_codec, dv_codec, brand
+            );
+        }
+        None => {
+            println!("HLS: CODECS=\"{}\"", dv_codec);
+            println!("DASH: codecs=\"{}\"", dv_codec);
+        }
+    }
+
+    if let Some(config_out) = config_out {
+        File::create(&config_out)?.write_all(&payload)?;
+        println!(
+            "Wrote {} payload to {}",
+            record.box_type(),
+            config_out.display()
+        );
+    }
+
+    Ok(())
+}
@@ -10,6 +10,9 @@ use hevc_parser::hevc::*;
 use hevc_parser::HevcParser;
 
 //use crate::dovi::get_aud;
+use super::hevc_info::SpsInfo;
+use super::muxer::OutputContainer;
+use super::sei::{write_with_hdr10_seis, Hdr10Metadata};
 use super::{
     input_format, is_st2094_40_sei, parse_rpu_file, CliOptions, DoviRpu, Format, OUT_NAL_HEADER,
 };
@@ -21,6 +24,7 @@ pub struct RpuInjector {
     options: CliOptions,
 
     rpus: Option<Vec<DoviRpu>>,
+    hdr10_sei_nals: Option<Vec<Vec<u8>>>,
 }
 
 impl RpuInjector {
@@ -38,16 +42,22 @@ impl RpuInjector {
                 None => PathBuf::from("injected_output.hevc"),
             };
 
-            let mut injector = RpuInjector::new(input, rpu_in, output, cli_options)?;
-            let mut parser = HevcParser::default();
+            let container = OutputContainer::from_path(&output);
+            let fps = cli_options.fps;
 
-            injector.process_input(&mut parser, format)?;
-            parser.finish();
+            container.write(&output, fps, |annexb_output| {
+                let mut injector =
+                    RpuInjector::new(input, rpu_in, annexb_output.to_path_buf(), cli_options)?;
+                let mut parser = HevcParser::default();
+
+                injector.process_input(&mut parser, format)?;
+                parser.finish();
 
-            let frames = parser.ordered_frames();
-            let nals = parser.get_nals();
+                let frames = parser.ordered_frames();
+                let nals = parser.get_nals();
 
-            injector.interleave_rpu_nals(nals, frames)
+                injector.interleave_rpu_nals(nals, frames)
+            })
         } else {
             bail!("unsupported format")
         }
@@ -137,13 +147,44 @@ impl RpuInjector {
             output,
             options: cli_options,
             rpus: None,
+            hdr10_sei_nals: None,
         };
 
         injector.rpus = parse_rpu_file(&injector.rpu_in)?;
+        injector.validate_against_bitstream();
+
+        if injector.options.hdr10_sei {
+            let metadata = injector
+                .rpus
+                .as_ref()
+                .and_then(|rpus| rpus.first())
+                .and_then(Hdr10Metadata::from_rpu);
+
+            match metadata {
+                Some(metadata) => injector.hdr10_sei_nals = Some(metadata.sei_nals()),
+                None => bail!("No L6 metadata in the RPU to insert HDR10 SEIs from"),
+            }
+        }
 
         Ok(injector)
     }
 
+    /// Warns about RPU fields that don't match the input SPS/VUI
+    fn validate_against_bitstream(&self) {
+        let rpus = match &self.rpus {
+            Some(rpus) => rpus,
+            None => return,
+        };
+
+        match SpsInfo::from_hevc_file(&self.input) {
+            Ok(sps) => {
+                sps.print_summary();
+                sps.validate_rpus(rpus);
+            }
+            Err(e) => println!("Warning: could not read the HEVC SPS: {}", e),
+        }
+    }
+
     fn interleave_rpu_nals(&mut self, nals: &[NALUnit], frames: &[Frame]) -> Result<()> {
         if let Some(ref mut rpus) = self.rpus {
             let mismatched_length = if frames.len() != rpus.len() {
@@ -264,8 +305,17 @@ impl RpuInjector {
                     //    continue;
                     //}
 
-                    writer.write_all(OUT_NAL_HEADER)?;
-                    writer.write_all(&chunk[nal.start..nal.end])?;
+                    if let Some(sei_nals) = &self.hdr10_sei_nals {
+                        write_with_hdr10_seis(
+                            &mut writer,
+                            nal.nal_type,
+                            &chunk[nal.start..nal.end],
+                            sei_nals,
+                        )?;
+                    } else {
+                        writer.write_all(OUT_NAL_HEADER)?;
+                        writer.write_all(&chunk[nal.start..nal.end])?;
+                    }
 
                     let global_index = nals_parsed + cur_index;
 
@@ -0,0 +1,200 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, ensure, Result};
+
+use dolby_vision::rpu::generate::GenerateConfig;
+
+use super::{parse_rpu_file, write_rpu_file, DoviRpu};
+
+/// Where to split the RPU file, as the first frame of each chunk
+pub enum SplitPoints {
+    Frames(Vec<usize>),
+    SceneCuts(usize),
+}
+
+pub struct RpuSplitter;
+
+impl RpuSplitter {
+    /// Splits an RPU file into chunks, written as `<stem>_<chunk>.bin` in the output directory
+    pub fn split(
+        input: PathBuf,
+        split_points: SplitPoints,
+        output_dir: Option<PathBuf>,
+        force: bool,
+    ) -> Result<()> {
+        let output_dir = output_dir.unwrap_or_else(|| PathBuf::from("."));
+        ensure!(
+            output_dir.is_dir(),
+            "Output directory {} does not exist",
+            output_dir.display()
+        );
+
+        let rpus = match parse_rpu_file(&input)? {
+            Some(rpus) => rpus,
+            None => bail!("No RPUs parsed from {}", input.display()),
+        };
+
+        let chunk_starts = chunk_starts(&rpus, &split_points)?;
+
+        let not_scene_cuts: Vec<usize> = chunk_starts
+            .iter()
+            .copied()
+            .filter(|frame| *frame > 0 && !is_scene_cut(&rpus[*frame]))
+            .collect();
+
+        if !not_scene_cuts.is_empty() {
+            if force {
+                println!(
+                    "Warning: chunks starting at frames {:?} are not on scene cuts",
+                    not_scene_cuts
+                );
+            } else {
+                bail!(
+                    "Chunks starting at frames {:?} are not on scene cuts, use --force to split anyway",
+                    not_scene_cuts
+                );
+            }
+        }
+
+        let stem = input
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .unwrap_or("RPU")
+            .to_string();
+
+        let mut rpus = rpus;
+        let total = rpus.len();
+
+        for (i, start) in chunk_starts.iter().enumerate() {
+            let end = chunk_starts.get(i + 1).copied().unwrap_or(total);
+            let chunk = &mut rpus[*start..end];
+
+            set_chunk_scene_cut(chunk);
+
+            let path = output_dir.join(format!("{}_{:03}.bin", stem, i));
+            println!(
+                "Chunk {}: frames {} to {} ({} frames) -> {}",
+                i,
+                start,
+                end - 1,
+                end - start,
+                path.display()
+            );
+
+            write_rpu_file(&path, GenerateConfig::encode_rpus(chunk))?;
+        }
+
+        Ok(())
+    }
+
+    /// Joins RPU files, making sure every chunk starts with a scene cut
+    pub fn concat(inputs: Vec<PathBuf>, output: Option<PathBuf>) -> Result<()> {
+        ensure!(inputs.len() > 1, "At least 2 RPU files are required");
+
+        let output = output.unwrap_or_else(|| PathBuf::from("RPU_concat.bin"));
+
+        let mut rpus: Vec<DoviRpu> = Vec::new();
+        let mut profile = None;
+
+        for input in &inputs {
+            let mut chunk = match parse_rpu_file(input)? {
+                Some(chunk) if !chunk.is_empty() => chunk,
+                _ => bail!("No RPUs parsed from {}", input.display()),
+            };
+
+            check_profile(input, &chunk[0], &mut profile)?;
+
+            if !is_scene_cut(&chunk[0]) {
+                println!(
+                    "Warning: {} does not start on a scene cut (frame {}), setting it",
+                    input.display(),
+                    rpus.len()
+                );
+            }
+
+            set_chunk_scene_cut(&mut chunk);
+            rpus.append(&mut chunk);
+        }
+
+        println!("Concatenated {} frames", rpus.len());
+
+        write_rpu_file(&output, GenerateConfig::encode_rpus(&mut rpus))
+    }
+}
+
+/// First frame of each chunk, starting with 0
+fn chunk_starts(rpus: &[DoviRpu], split_points: &SplitPoints) -> Result<Vec<usize>> {
+    let mut starts = match split_points {
+        SplitPoints::Frames(frames) => {
+            let mut starts = vec![0];
+            starts.extend(frames.iter().copied().filter(|frame| *frame > 0));
+
+            starts
+        }
+        SplitPoints::SceneCuts(count) => {
+            ensure!(
+                *count > 0,
+                "The number of scene cuts per chunk must be at least 1"
+            );
+
+            let scene_cuts: Vec<usize> = rpus
+                .iter()
+                .enumerate()
+                .filter(|(i, rpu)| *i > 0 && is_scene_cut(rpu))
+                .map(|(i, _)| i)
+                .collect();
+
+            let mut starts = vec![0];
+            starts.extend(scene_cuts.iter().skip(count - 1).step_by(*count));
+
+            starts
+        }
+    };
+
+    starts.sort_unstable();
+    starts.dedup();
+
+    if let Some(last) = starts.last() {
+        ensure!(
+            *last < rpus.len(),
+            "Invalid split frame {}, {} available RPUs",
+            last,
+            rpus.len()
+        );
+    }
+
+    Ok(starts)
+}
+
+fn check_profile(input: &Path, rpu: &DoviRpu, profile: &mut Option<u8>) -> Result<()> {
+    match profile {
+        Some(profile) if *profile != rpu.dovi_profile => bail!(
+            "{} is profile {}, expected profile {}",
+            input.display(),
+            rpu.dovi_profile,
+            profile
+        ),
+        Some(_) => (),
+        None => *profile = Some(rpu.dovi_profile),
+    }
+
+    Ok(())
+}
+
+fn is_scene_cut(rpu: &DoviRpu) -> bool {
+    rpu.vdr_dm_data
+        .as_ref()
+        .map_or(false, |vdr_dm_data| vdr_dm_data.scene_refresh_flag == 1)
+}
+
+/// Encoded chunks start on an IDR, the metadata has to be refreshed
+fn set_chunk_scene_cut(chunk: &mut [DoviRpu]) {
+    if let Some(first) = chunk.first_mut() {
+        if !is_scene_cut(first) {
+            if let Some(ref mut vdr_dm_data) = first.vdr_dm_data {
+                vdr_dm_data.set_scene_cut(true);
+                first.modified = true;
+            }
+        }
+    }
+}
@@ -0,0 +1,445 @@
+use std::io::Write;
+use std::path::Path;
+
+use anyhow::{bail, ensure, Result};
+
+use dolby_vision::rpu::extension_metadata::blocks::{ExtMetadataBlock, ExtMetadataBlockLevel6};
+use dolby_vision::rpu::extension_metadata::target_display::TargetPrimaries;
+use dolby_vision::utils::{
+    add_start_code_emulation_prevention_3_byte, clear_start_code_emulation_prevention_3_byte,
+};
+use hevc_parser::hevc::{NAL_BLA_W_LP, NAL_IRAP_VCL23, NAL_SEI_PREFIX, NAL_VPS};
+
+use super::io::NalReader;
+use super::{DoviRpu, OUT_NAL_HEADER};
+
+pub const SEI_MASTERING_DISPLAY_COLOUR_VOLUME: u16 = 137;
+pub const SEI_CONTENT_LIGHT_LEVEL_INFO: u16 = 144;
+
+/// A SEI NAL unit, split into its SEI messages
+#[derive(Debug, Clone, PartialEq)]
+pub struct SeiNal {
+    pub header: [u8; 2],
+    pub messages: Vec<SeiPayload>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SeiPayload {
+    pub payload_type: u16,
+    pub data: Vec<u8>,
+}
+
+/// ST 2086 mastering display colour volume.
+/// Primaries are in 0.00002 units, in G, B, R order. Luminances are in 0.0001 nits.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct MasteringDisplayColourVolume {
+    pub display_primaries: [(u16, u16); 3],
+    pub white_point: (u16, u16),
+    pub max_luminance: u32,
+    pub min_luminance: u32,
+}
+
+/// Content light level information, in nits
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct ContentLightLevel {
+    pub max_content_light_level: u16,
+    pub max_pic_average_light_level: u16,
+}
+
+/// The HDR10 static metadata SEIs
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Hdr10Metadata {
+    pub mastering_display: MasteringDisplayColourVolume,
+    pub content_light_level: ContentLightLevel,
+}
+
+impl SeiNal {
+    /// Parses a SEI NAL unit, including the 2 bytes NAL header
+    pub fn parse(nal_data: &[u8]) -> Result<SeiNal> {
+        ensure!(nal_data.len() > 2, "SEI NAL unit too short");
+
+        let data = clear_start_code_emulation_prevention_3_byte(nal_data);
+        let mut sei = SeiNal {
+            header: [data[0], data[1]],
+            messages: Vec::new(),
+        };
+
+        let mut pos = 2;
+
+        // Stop at the rbsp_trailing_bits
+        let is_trailing = |pos: usize| data[pos] == 0x80 && data[pos + 1..].iter().all(|b| *b == 0);
+
+        while pos < data.len() && !is_trailing(pos) {
+            let payload_type = read_ff_coded(&data, &mut pos)?;
+            let payload_size = read_ff_coded(&data, &mut pos)? as usize;
+
+            if pos + payload_size > data.len() {
+                bail!("Invalid SEI payload size {}", payload_size);
+            }
+
+            sei.messages.push(SeiPayload {
+                payload_type,
+                data: data[pos..pos + payload_size].to_vec(),
+            });
+
+            pos += payload_size;
+        }
+
+        Ok(sei)
+    }
+
+    /// Encodes the NAL unit, including the header, with emulation prevention
+    pub fn write(&self) -> Vec<u8> {
+        let mut data = self.header.to_vec();
+
+        for message in &self.messages {
+            write_ff_coded(&mut data, message.payload_type as usize);
+            write_ff_coded(&mut data, message.data.len());
+
+            data.extend_from_slice(&message.data);
+        }
+
+        // rbsp_trailing_bits
+        data.push(0x80);
+
+        add_start_code_emulation_prevention_3_byte(&mut data);
+
+        data
+    }
+
+    pub fn prefix(messages: Vec<SeiPayload>) -> SeiNal {
+        SeiNal {
+            header: [NAL_SEI_PREFIX << 1, 1],
+            messages,
+        }
+    }
+}
+
+/// Removes the SEI messages of the payload types from a SEI NAL unit.
+///
+/// Returns `None` when the NAL is unchanged, otherwise the rewritten NAL data.
+/// The data is empty when no messages are left, and the NAL should be dropped.
+pub fn remove_sei_messages(nal_data: &[u8], payload_types: &[u16]) -> Result<Option<Vec<u8>>> {
+    let mut sei = SeiNal::parse(nal_data)?;
+
+    if !sei
+        .messages
+        .iter()
+        .any(|m| payload_types.contains(&m.payload_type))
+    {
+        return Ok(None);
+    }
+
+    sei.messages
+        .retain(|m| !payload_types.contains(&m.payload_type));
+
+    if sei.messages.is_empty() {
+        Ok(Some(Vec::new()))
+    } else {
+        Ok(Some(sei.write()))
+    }
+}
+
+impl MasteringDisplayColourVolume {
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        ensure!(data.len() >= 24, "Invalid mastering display SEI size");
+
+        let u16_at = |i: usize| u16::from_be_bytes([data[i], data[i + 1]]);
+        let u32_at =
+            |i: usize| u32::from_be_bytes([data[i], data[i + 1], data[i + 2], data[i + 3]]);
+
+        Ok(Self {
+            display_primaries: [
+                (u16_at(0), u16_at(2)),
+                (u16_at(4), u16_at(6)),
+                (u16_at(8), u16_at(10)),
+            ],
+            white_point: (u16_at(12), u16_at(14)),
+            max_luminance: u32_at(16),
+            min_luminance: u32_at(20),
+        })
+    }
+
+    pub fn write(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(24);
+
+        for (x, y) in &self.display_primaries {
+            data.extend_from_slice(&x.to_be_bytes());
+            data.extend_from_slice(&y.to_be_bytes());
+        }
+
+        data.extend_from_slice(&self.white_point.0.to_be_bytes());
+        data.extend_from_slice(&self.white_point.1.to_be_bytes());
+        data.extend_from_slice(&self.max_luminance.to_be_bytes());
+        data.extend_from_slice(&self.min_luminance.to_be_bytes());
+
+        data
+    }
+
+    /// D65 white point with the primaries, in G, B, R order
+    pub fn with_primaries(primaries: TargetPrimaries) -> Self {
+        let display_primaries = match primaries {
+            TargetPrimaries::DciP3D65 => [(13250, 34500), (7500, 3000), (34000, 16000)],
+            TargetPrimaries::Bt709 => [(15000, 30000), (7500, 3000), (32000, 16500)],
+            TargetPrimaries::Bt2020 => [(8500, 39850), (6550, 2300), (35400, 14600)],
+        };
+
+        Self {
+            display_primaries,
+            white_point: (15635, 16450),
+            ..Default::default()
+        }
+    }
+
+    /// The matching L9 primaries, if they are one of the presets
+    pub fn primaries(&self) -> Option<TargetPrimaries> {
+        [
+            TargetPrimaries::DciP3D65,
+            TargetPrimaries::Bt709,
+            TargetPrimaries::Bt2020,
+        ]
+        .iter()
+        .copied()
+        .find(|p| {
+            let preset = Self::with_primaries(*p);
+
+            // Allow small rounding differences
+            let close = |a: u16, b: u16| (a as i32 - b as i32).abs() <= 50;
+
+            preset
+                .display_primaries
+                .iter()
+                .zip(self.display_primaries.iter())
+                .all(|(a, b)| close(a.0, b.0) && close(a.1, b.1))
+        })
+    }
+}
+
+impl ContentLightLevel {
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        ensure!(data.len() >= 4, "Invalid content light level SEI size");
+
+        Ok(Self {
+            max_content_light_level: u16::from_be_bytes([data[0], data[1]]),
+            max_pic_average_light_level: u16::from_be_bytes([data[2], data[3]]),
+        })
+    }
+
+    pub fn write(&self) -> Vec<u8> {
+        let mut data = self.max_content_light_level.to_be_bytes().to_vec();
+        data.extend_from_slice(&self.max_pic_average_light_level.to_be_bytes());
+
+        data
+    }
+}
+
+impl Hdr10Metadata {
+    /// From the L6 metadata of the RPU, with the L9 primaries or P3 D65 by default.
+    /// Returns `None` when the RPU has no L6 metadata.
+    pub fn from_rpu(rpu: &DoviRpu) -> Option<Self> {
+        let vdr_dm_data = rpu.vdr_dm_data.as_ref()?;
+
+        let level6 = match vdr_dm_data.get_block(6) {
+            Some(ExtMetadataBlock::Level6(level6)) => level6,
+            _ => return None,
+        };
+
+        let primaries = match vdr_dm_data.get_block(9) {
+            Some(ExtMetadataBlock::Level9(level9)) => {
+                TargetPrimaries::from_index(level9.source_primary_index)
+            }
+            _ => None,
+        };
+
+        Some(Self::from_level6(level6, primaries))
+    }
+
+    pub fn from_level6(
+        level6: &ExtMetadataBlockLevel6,
+        primaries: Option<TargetPrimaries>,
+    ) -> Self {
+        let mut mastering_display = MasteringDisplayColourVolume::with_primaries(
+            primaries.unwrap_or(TargetPrimaries::DciP3D65),
+        );
+
+        mastering_display.max_luminance = level6.max_display_mastering_luminance as u32 * 10_000;
+        mastering_display.min_luminance = level6.min_display_mastering_luminance as u32;
+
+        Self {
+            mastering_display,
+            content_light_level: ContentLightLevel {
+                max_content_light_level: level6.max_content_light_level,
+                max_pic_average_light_level: level6.max_frame_average_light_level,
+            },
+        }
+    }
+
+    /// The mastering display and content light level SEI NAL units
+    pub fn sei_nals(&self) -> Vec<Vec<u8>> {
+        let mastering_display = SeiNal::prefix(vec![SeiPayload {
+            payload_type: SEI_MASTERING_DISPLAY_COLOUR_VOLUME,
+            data: self.mastering_display.write(),
+        }]);
+
+        let content_light_level = SeiNal::prefix(vec![SeiPayload {
+            payload_type: SEI_CONTENT_LIGHT_LEVEL_INFO,
+            data: self.content_light_level.write(),
+        }]);
+
+        vec![mastering_display.write(), content_light_level.write()]
+    }
+}
+
+/// HDR10 static metadata found in the SEIs of a HEVC stream
+#[derive(Debug, Default)]
+pub struct Hdr10SeiScan {
+    pub mastering_display: Option<MasteringDisplayColourVolume>,
+    pub content_light_level: Option<ContentLightLevel>,
+
+    /// First frame with values differing from the first SEIs, if any
+    pub mastering_display_changed_at: Option<usize>,
+    pub content_light_level_changed_at: Option<usize>,
+}
+
+impl Hdr10SeiScan {
+    /// Scans the prefix SEIs of a raw HEVC file.
+    /// The first values are kept, warnings are printed when they change mid-stream.
+    pub fn scan_file(path: &Path) -> Result<Self> {
+        println!("Scanning HEVC file for HDR10 SEIs...");
+
+        let mut reader = NalReader::open(path)?;
+        let mut scan = Hdr10SeiScan::default();
+        let mut frame = 0;
+        let mut found_slice = false;
+
+        reader.read_nals(false, |nal, data| {
+            // First slice of a picture
+            if nal.nal_type < NAL_VPS && data.len() > 2 && data[2] & 0x80 != 0 {
+                if found_slice {
+                    frame += 1;
+                }
+
+                found_slice = true;
+            }
+
+            if nal.nal_type != NAL_SEI_PREFIX {
+                return Ok(true);
+            }
+
+            // The SEIs precede the first slice of their picture
+            let sei_frame = if found_slice { frame + 1 } else { frame };
+
+            for message in SeiNal::parse(data)?.messages {
+                match message.payload_type {
+                    SEI_MASTERING_DISPLAY_COLOUR_VOLUME => {
+                        let mdcv = MasteringDisplayColourVolume::parse(&message.data)?;
+
+                        match &scan.mastering_display {
+                            Some(first) if *first != mdcv => {
+                                if scan.mastering_display_changed_at.is_none() {
+                                    println!(
+                                        "Warning: mastering display SEI changed at frame {}, keeping the first values",
+                                        sei_frame
+                                    );
+                                    scan.mastering_display_changed_at = Some(sei_frame);
+                                }
+                            }
+                            Some(_) => (),
+                            None => scan.mastering_display = Some(mdcv),
+                        }
+                    }
+                    SEI_CONTENT_LIGHT_LEVEL_INFO => {
+                        let cll = ContentLightLevel::parse(&message.data)?;
+
+                        match &scan.content_light_level {
+                            Some(first) if *first != cll => {
+                                if scan.content_light_level_changed_at.is_none() {
+                                    println!(
+                                        "Warning: content light level SEI changed at frame {}, keeping the first values",
+                                        sei_frame
+                                    );
+                                    scan.content_light_level_changed_at = Some(sei_frame);
+                                }
+                            }
+                            Some(_) => (),
+                            None => scan.content_light_level = Some(cll),
+                        }
+                    }
+                    _ => (),
+                }
+            }
+
+            Ok(true)
+        })?;
+
+        Ok(scan)
+    }
+}
+
+/// Writes the NAL unit, with the HDR10 SEIs replaced by `sei_nals`.
+/// The SEIs are inserted before the first slice of IRAP pictures.
+pub fn write_with_hdr10_seis<W: Write>(
+    writer: &mut W,
+    nal_type: u8,
+    nal_data: &[u8],
+    sei_nals: &[Vec<u8>],
+) -> Result<()> {
+    let first_slice_segment_in_pic_flag = nal_data.len() > 2 && nal_data[2] & 0x80 != 0;
+
+    if (NAL_BLA_W_LP..=NAL_IRAP_VCL23).contains(&nal_type) && first_slice_segment_in_pic_flag {
+        for sei in sei_nals {
+            writer.write_all(OUT_NAL_HEADER)?;
+            writer.write_all(sei)?;
+        }
+    }
+
+    if nal_type == NAL_SEI_PREFIX {
+        let payload_types = &[
+            SEI_MASTERING_DISPLAY_COLOUR_VOLUME,
+            SEI_CONTENT_LIGHT_LEVEL_INFO,
+        ];
+
+        match remove_sei_messages(nal_data, payload_types)? {
+            Some(data) if data.is_empty() => (),
+            Some(data) => {
+                writer.write_all(OUT_NAL_HEADER)?;
+                writer.write_all(&data)?;
+            }
+            None => {
+                writer.write_all(OUT_NAL_HEADER)?;
+                writer.write_all(nal_data)?;
+            }
+        }
+    } else {
+        writer.write_all(OUT_NAL_HEADER)?;
+        writer.write_all(nal_data)?;
+    }
+
+    Ok(())
+}
+
+fn read_ff_coded(data: &[u8], pos: &mut usize) -> Result<u16> {
+    let mut value: u16 = 0;
+
+    loop {
+        ensure!(*pos < data.len(), "Unexpected end of SEI data");
+
+        let byte = data[*pos];
+        *pos += 1;
+
+        value = value.saturating_add(byte as u16);
+
+        if byte != 0xFF {
+            return Ok(value);
+        }
+    }
+}
+
+fn write_ff_coded(data: &mut Vec<u8>, mut value: usize) {
+    while value >= 0xFF {
+        data.push(0xFF);
+        value -= 0xFF;
+    }
+
+    data.push(value as u8);
+}
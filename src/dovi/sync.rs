@@ -0,0 +1,494 @@
+use std::fs::File;
+use std::io::{stdout, Write};
+use std::path::PathBuf;
+
+use anyhow::{bail, ensure, Result};
+use serde_json::{json, Value};
+
+use dolby_vision::rpu::extension_metadata::blocks::ExtMetadataBlock;
+
+use super::{parse_rpu_file, DoviRpu};
+
+/// Frames correlated at once to find the offset
+const BLOCK_LENGTH: usize = 240;
+
+/// Blocks with a lower correlation have no match in the reference
+const MIN_CORRELATION: f64 = 0.9;
+
+/// Frame error outside of the reference, the errors are in standard deviations
+const MISSING_FRAME_ERROR: f64 = 4.0;
+
+/// Frames with a higher error are removed at the start and end of the input
+const GAP_FRAME_ERROR: f64 = 0.5;
+
+pub enum SyncReference {
+    Rpu(PathBuf),
+    MadVR(PathBuf),
+}
+
+pub struct RpuSync;
+
+/// Frames `src_start..src_start + length` of the input match the reference from `ref_start`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct SyncSegment {
+    src_start: usize,
+    ref_start: usize,
+    length: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BlockMatch {
+    /// Not enough variation in the block to correlate
+    Flat,
+    Unmatched,
+    Offset(isize),
+}
+
+/// L1 max and average PQ per frame, normalized to zero mean and unit variance
+struct Signal {
+    max: Vec<f64>,
+    avg: Vec<f64>,
+}
+
+impl RpuSync {
+    /// Writes the editor config aligning the input RPU to the reference
+    pub fn sync(
+        input: PathBuf,
+        reference: SyncReference,
+        json_out: Option<PathBuf>,
+        max_offset: usize,
+    ) -> Result<()> {
+        let json_out = json_out.unwrap_or_else(|| PathBuf::from("sync_edits.json"));
+
+        let src = match parse_rpu_file(&input)? {
+            Some(rpus) => Signal::from_rpus(&rpus),
+            None => bail!("No RPUs parsed from {}", input.display()),
+        };
+
+        let reference = match reference {
+            SyncReference::Rpu(path) => match parse_rpu_file(&path)? {
+                Some(rpus) => Signal::from_rpus(&rpus),
+                None => bail!("No RPUs parsed from {}", path.display()),
+            },
+            SyncReference::MadVR(path) => {
+                let madvr_info = madvr_parse::MadVRMeasurements::parse_file(&path)?;
+
+                let (max, avg) = madvr_info
+                    .frames
+                    .iter()
+                    .map(|f| (f.peak_pq_2020 * 4095.0, f.avg_pq * 4095.0))
+                    .unzip();
+
+                Signal::new(max, avg)
+            }
+        };
+
+        ensure!(
+            !src.is_empty() && !reference.is_empty(),
+            "No frames to synchronize"
+        );
+
+        println!(
+            "Synchronizing {} frames to {} reference frames...",
+            src.len(),
+            reference.len()
+        );
+        stdout().flush().ok();
+
+        let segments = find_segments(&src, &reference, max_offset as isize)?;
+
+        if let [segment] = segments.as_slice() {
+            println!(
+                "Constant offset of {} frames",
+                segment.ref_start as isize - segment.src_start as isize
+            );
+        }
+
+        for segment in &segments {
+            println!(
+                "Frames {}-{} match reference frames {}-{}",
+                segment.src_start,
+                segment.src_start + segment.length - 1,
+                segment.ref_start,
+                segment.ref_start + segment.length - 1
+            );
+        }
+
+        let edit_json = sync_edits(&segments, src.len(), reference.len());
+
+        let mut writer = File::create(&json_out)?;
+        serde_json::to_writer_pretty(&mut writer, &edit_json)?;
+
+        println!("Editor config written to {}", json_out.display());
+
+        Ok(())
+    }
+}
+
+impl Signal {
+    fn from_rpus(rpus: &[DoviRpu]) -> Signal {
+        let (max, avg) = rpus
+            .iter()
+            .map(|rpu| {
+                match rpu
+                    .vdr_dm_data
+                    .as_ref()
+                    .and_then(|vdr_dm_data| vdr_dm_data.get_block(1))
+                {
+                    Some(ExtMetadataBlock::Level1(l1)) => (l1.max_pq as f64, l1.avg_pq as f64),
+                    _ => (0.0, 0.0),
+                }
+            })
+            .unzip();
+
+        Signal::new(max, avg)
+    }
+
+    fn new(mut max: Vec<f64>, mut avg: Vec<f64>) -> Signal {
+        normalize(&mut max);
+        normalize(&mut avg);
+
+        Signal { max, avg }
+    }
+
+    fn len(&self) -> usize {
+        self.max.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.max.is_empty()
+    }
+}
+
+fn variance(values: &[f64]) -> f64 {
+    let n = values.len() as f64;
+    let mean = values.iter().sum::<f64>() / n;
+
+    values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / n
+}
+
+fn normalize(values: &mut [f64]) {
+    let n = values.len() as f64;
+    let mean = values.iter().sum::<f64>() / n;
+    let std_dev = variance(values).sqrt();
+
+    values.iter_mut().for_each(|v| {
+        *v = if std_dev > 1e-6 {
+            (*v - mean) / std_dev
+        } else {
+            0.0
+        }
+    });
+}
+
+/// Difference of a frame with the reference frame at the offset
+fn frame_error(src: &Signal, reference: &Signal, frame: usize, offset: isize) -> f64 {
+    let ref_frame = frame as isize + offset;
+
+    if ref_frame < 0 || ref_frame >= reference.len() as isize {
+        return MISSING_FRAME_ERROR;
+    }
+
+    let ref_frame = ref_frame as usize;
+
+    ((src.max[frame] - reference.max[ref_frame]).abs()
+        + (src.avg[frame] - reference.avg[ref_frame]).abs())
+        / 2.0
+}
+
+/// Pearson correlation of the block with the reference at the offset
+fn correlation(
+    src: &Signal,
+    reference: &Signal,
+    start: usize,
+    end: usize,
+    offset: isize,
+) -> Option<f64> {
+    let mut sums = [0.0; 5];
+    let mut n = 0;
+
+    for i in start..end {
+        let j = i as isize + offset;
+
+        if j < 0 || j >= reference.len() as isize {
+            continue;
+        }
+
+        let j = j as usize;
+
+        for (x, y) in [
+            (src.max[i], reference.max[j]),
+            (src.avg[i], reference.avg[j]),
+        ]
+        .iter()
+        {
+            sums[0] += x;
+            sums[1] += y;
+            sums[2] += x * x;
+            sums[3] += y * y;
+            sums[4] += x * y;
+        }
+
+        n += 2;
+    }
+
+    // At least half of the block must overlap
+    if n < end - start {
+        return None;
+    }
+
+    let n = n as f64;
+    let var_x = sums[2] - sums[0] * sums[0] / n;
+    let var_y = sums[3] - sums[1] * sums[1] / n;
+    let cov = sums[4] - sums[0] * sums[1] / n;
+
+    if var_x < 1e-6 || var_y < 1e-6 {
+        None
+    } else {
+        Some(cov / (var_x * var_y).sqrt())
+    }
+}
+
+fn match_block(
+    src: &Signal,
+    reference: &Signal,
+    start: usize,
+    end: usize,
+    previous: Option<isize>,
+    max_offset: isize,
+) -> BlockMatch {
+    if variance(&src.max[start..end]) + variance(&src.avg[start..end]) < 1e-3 {
+        return BlockMatch::Flat;
+    }
+
+    // Most blocks keep the offset of the previous block
+    if let Some(offset) = previous {
+        if correlation(src, reference, start, end, offset).map_or(false, |c| c >= MIN_CORRELATION) {
+            return BlockMatch::Offset(offset);
+        }
+    }
+
+    let best = (-max_offset..=max_offset)
+        .filter_map(|offset| correlation(src, reference, start, end, offset).map(|c| (offset, c)))
+        .fold(None, |best: Option<(isize, f64)>, (offset, c)| match best {
+            Some(best) if best.1 >= c => Some(best),
+            _ => Some((offset, c)),
+        });
+
+    match best {
+        Some((offset, c)) if c >= MIN_CORRELATION => BlockMatch::Offset(offset),
+        _ => BlockMatch::Unmatched,
+    }
+}
+
+/// Runs of frames with the same offset, as (start, end, offset)
+fn block_runs(src: &Signal, reference: &Signal, max_offset: isize) -> Vec<(usize, usize, isize)> {
+    let mut runs: Vec<(usize, usize, isize)> = Vec::new();
+    let mut previous = None;
+
+    for start in (0..src.len()).step_by(BLOCK_LENGTH) {
+        let end = (start + BLOCK_LENGTH).min(src.len());
+
+        match match_block(src, reference, start, end, previous, max_offset) {
+            BlockMatch::Offset(offset) => {
+                match runs.last_mut() {
+                    // Same timing, the unmatched frames in between are kept
+                    Some(run) if run.2 == offset => run.1 = end,
+                    _ => runs.push((start, end, offset)),
+                }
+
+                previous = Some(offset);
+            }
+            BlockMatch::Flat => {
+                if let (Some(run), Some(_)) = (runs.last_mut(), previous) {
+                    run.1 = end;
+                }
+            }
+            BlockMatch::Unmatched => previous = None,
+        }
+    }
+
+    runs
+}
+
+/// Prefix sums of the frame errors at the offset, over `start..end`
+fn error_sums(
+    src: &Signal,
+    reference: &Signal,
+    start: usize,
+    end: usize,
+    offset: isize,
+) -> Vec<f64> {
+    let mut sums = Vec::with_capacity(end - start + 1);
+    sums.push(0.0);
+
+    for i in start..end {
+        let last = sums[sums.len() - 1];
+        sums.push(last + frame_error(src, reference, i, offset));
+    }
+
+    sums
+}
+
+fn find_segments(src: &Signal, reference: &Signal, max_offset: isize) -> Result<Vec<SyncSegment>> {
+    let mut runs = block_runs(src, reference, max_offset);
+
+    ensure!(
+        !runs.is_empty(),
+        "No match found with the reference, the offset may be over {} frames",
+        max_offset
+    );
+
+    // Exact transition frame between the runs
+    for i in 1..runs.len() {
+        let (s1, e1, d1) = runs[i - 1];
+        let (s2, e2, d2) = runs[i];
+
+        let lo = e1.saturating_sub(BLOCK_LENGTH).max(s1);
+        let hi = (s2 + BLOCK_LENGTH).min(e2);
+        let gap = (d1 - d2).max(0) as usize;
+
+        let before = error_sums(src, reference, lo, hi, d1);
+        let after = error_sums(src, reference, lo, hi, d2);
+
+        let transition = (lo..=hi.saturating_sub(gap).max(lo))
+            .min_by(|a, b| {
+                let cost = |t: usize| {
+                    let resume = (t + gap).min(hi);
+                    before[t - lo] + after[hi - lo] - after[resume - lo]
+                };
+
+                cost(*a).partial_cmp(&cost(*b)).unwrap()
+            })
+            .unwrap_or(lo);
+
+        runs[i - 1].1 = transition;
+        runs[i].0 = (transition + gap).min(hi);
+    }
+
+    // Input frames missing from the reference at the start and the end
+    let (s, e, d) = runs[0];
+    let hi = (s + BLOCK_LENGTH).min(e);
+    let sums = error_sums(src, reference, 0, hi, d);
+    runs[0].0 = (0..=hi)
+        .min_by(|a, b| {
+            let cost = |t: usize| t as f64 * GAP_FRAME_ERROR + sums[hi] - sums[t];
+            cost(*a).partial_cmp(&cost(*b)).unwrap()
+        })
+        .unwrap_or(0);
+
+    let last = runs.len() - 1;
+    let (s, e, d) = runs[last];
+    let lo = e.saturating_sub(BLOCK_LENGTH).max(s);
+    let sums = error_sums(src, reference, lo, src.len(), d);
+    runs[last].1 = (lo..=src.len())
+        .min_by(|a, b| {
+            let cost = |t: usize| sums[t - lo] + (src.len() - t) as f64 * GAP_FRAME_ERROR;
+            cost(*a).partial_cmp(&cost(*b)).unwrap()
+        })
+        .unwrap_or(lo);
+
+    let mut segments: Vec<SyncSegment> = Vec::with_capacity(runs.len());
+
+    for (start, end, offset) in runs {
+        // Clip to the reference, and after the previous segment
+        let min_start = segments.last().map_or(0, |s: &SyncSegment| {
+            let src_end = s.src_start + s.length;
+            let ref_end = (s.ref_start + s.length) as isize;
+
+            src_end.max((ref_end - offset).max(0) as usize)
+        });
+
+        let start = start.max(min_start).max((-offset).max(0) as usize);
+        let end = end.min((reference.len() as isize - offset).max(0) as usize);
+
+        if end > start {
+            segments.push(SyncSegment {
+                src_start: start,
+                ref_start: (start as isize + offset) as usize,
+                length: end - start,
+            });
+        }
+    }
+
+    ensure!(!segments.is_empty(), "No match found with the reference");
+
+    Ok(segments)
+}
+
+/// Editor config removing and duplicating frames, for the input to match the reference
+fn sync_edits(segments: &[SyncSegment], src_len: usize, ref_len: usize) -> Value {
+    let mut remove: Vec<String> = Vec::new();
+    let mut duplicate: Vec<(usize, usize, usize)> = Vec::new();
+
+    let mut removed = 0;
+    let mut src_pos = 0;
+    let mut ref_pos = 0;
+
+    let end = SyncSegment {
+        src_start: src_len,
+        ref_start: ref_len,
+        length: 0,
+    };
+
+    for segment in segments.iter().chain(std::iter::once(&end)) {
+        let src_gap = segment.src_start - src_pos;
+        let ref_gap = segment.ref_start - ref_pos;
+
+        // Unmatched frames are kept when both sides have some
+        let kept = src_gap.min(ref_gap);
+
+        if src_gap > kept {
+            let first = src_pos + kept;
+            let last = segment.src_start - 1;
+
+            remove.push(if first == last {
+                first.to_string()
+            } else {
+                format!("{}-{}", first, last)
+            });
+
+            removed += src_gap - kept;
+        }
+
+        if ref_gap > kept {
+            // Position after the removals, duplicating the previous frame
+            let offset = segment.src_start - removed;
+            duplicate.push((offset.saturating_sub(1), offset, ref_gap - kept));
+        }
+
+        src_pos = segment.src_start + segment.length;
+        ref_pos = segment.ref_start + segment.length;
+    }
+
+    // Frames can only be inserted before an existing one
+    let final_len = src_len - removed;
+    let duplicate: Vec<Value> = duplicate
+        .into_iter()
+        .map(|(source, offset, length)| {
+            let (source, offset) = if offset >= final_len {
+                (final_len - 1, final_len - 1)
+            } else {
+                (source, offset)
+            };
+
+            json!({
+                "source": source,
+                "offset": offset,
+                "length": length,
+            })
+        })
+        .collect();
+
+    let mut edit_json = json!({});
+
+    if !remove.is_empty() {
+        edit_json["remove"] = json!(remove);
+    }
+
+    if !duplicate.is_empty() {
+        edit_json["duplicate"] = json!(duplicate);
+    }
+
+    edit_json
+}
@@ -0,0 +1,152 @@
+use anyhow::{ensure, Result};
+use serde::{Deserialize, Serialize};
+
+use dolby_vision::rpu::extension_metadata::blocks::{
+    ExtMetadataBlock, ExtMetadataBlockLevel1, ExtMetadataBlockLevel4,
+};
+
+use super::DoviRpu;
+
+/// Temporal processing of the L1 metadata, for metadata measured per frame
+#[derive(Serialize, Deserialize, Default, Debug, Clone)]
+pub struct TemporalConfig {
+    // Moving average window of the L1 smoothing, in frames
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub smooth_l1_window: Option<usize>,
+
+    // Smooth over scene cuts, instead of restarting at every scene
+    #[serde(default)]
+    pub smooth_across_scenes: bool,
+
+    // Time constant of the L4 temporal filter over L1, in frames
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub level4_window: Option<usize>,
+}
+
+impl TemporalConfig {
+    pub fn is_enabled(&self) -> bool {
+        self.smooth_l1_window.is_some() || self.level4_window.is_some()
+    }
+
+    pub(crate) fn execute(&self, rpus: &mut [&mut DoviRpu]) -> Result<()> {
+        if let Some(window) = self.smooth_l1_window {
+            ensure!(window > 0, "Invalid L1 smoothing window 0");
+
+            println!("Smoothing L1 metadata over {} frames...", window);
+            self.smooth_l1(window, rpus)?;
+        }
+
+        if let Some(window) = self.level4_window {
+            ensure!(window > 0, "Invalid L4 filter window 0");
+
+            println!("Generating L4 metadata...");
+            generate_level4(window, rpus)?;
+        }
+
+        Ok(())
+    }
+
+    /// Centered moving average of the L1 min and average.
+    /// The max is averaged too, but kept at least at the frame peak to avoid clipping it.
+    /// The window is cut at the scene cuts, unless smoothing across scenes.
+    fn smooth_l1(&self, window: usize, rpus: &mut [&mut DoviRpu]) -> Result<()> {
+        let stats: Vec<Option<[f64; 3]>> = rpus.iter().map(|rpu| level1_stats(rpu)).collect();
+
+        // First frame of the scene of every frame, and one past its last frame
+        let mut scene_bounds = vec![(0, rpus.len()); rpus.len()];
+
+        if !self.smooth_across_scenes {
+            let mut start = 0;
+
+            for i in 1..=rpus.len() {
+                let is_scene_cut = i == rpus.len()
+                    || rpus[i]
+                        .vdr_dm_data
+                        .as_ref()
+                        .map_or(false, |vdr_dm_data| vdr_dm_data.scene_refresh_flag == 1);
+
+                if is_scene_cut {
+                    scene_bounds[start..i]
+                        .iter_mut()
+                        .for_each(|bounds| *bounds = (start, i));
+                    start = i;
+                }
+            }
+        }
+
+        let half = window / 2;
+
+        for (i, rpu) in rpus.iter_mut().enumerate() {
+            let frame_max_pq = match stats[i] {
+                Some(frame_stats) => frame_stats[1] as u16,
+                None => continue,
+            };
+
+            let (scene_start, scene_end) = scene_bounds[i];
+            let start = i.saturating_sub(half).max(scene_start);
+            let end = (i + window - half).min(scene_end);
+
+            let window_stats: Vec<&[f64; 3]> = stats[start..end].iter().flatten().collect();
+            let count = window_stats.len() as f64;
+
+            let mean = |idx: usize| {
+                let sum: f64 = window_stats.iter().map(|s| s[idx]).sum();
+                (sum / count).round() as u16
+            };
+
+            let level1 =
+                ExtMetadataBlockLevel1::from_stats(mean(0), mean(1).max(frame_max_pq), mean(2));
+
+            if let Some(ref mut vdr_dm_data) = rpu.vdr_dm_data {
+                rpu.modified = true;
+                vdr_dm_data.replace_metadata_block(ExtMetadataBlock::Level1(level1))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// L4 anchor from an exponential moving average of the L1 average over all frames.
+/// The anchor power is the filtered deviation of the L1 average from the anchor.
+fn generate_level4(window: usize, rpus: &mut [&mut DoviRpu]) -> Result<()> {
+    let alpha = 1.0 / window as f64;
+
+    let mut anchor: Option<f64> = None;
+    let mut variance = 0.0;
+
+    for rpu in rpus.iter_mut() {
+        let avg_pq = match level1_stats(rpu) {
+            Some(stats) => stats[2],
+            None => continue,
+        };
+
+        let previous = anchor.unwrap_or(avg_pq);
+        let filtered = previous + alpha * (avg_pq - previous);
+
+        variance += alpha * ((avg_pq - filtered).powi(2) - variance);
+        anchor = Some(filtered);
+
+        let level4 = ExtMetadataBlockLevel4 {
+            anchor_pq: filtered.round().min(4095.0) as u16,
+            anchor_power: variance.sqrt().round().min(4095.0) as u16,
+        };
+
+        if let Some(ref mut vdr_dm_data) = rpu.vdr_dm_data {
+            rpu.modified = true;
+            vdr_dm_data.replace_metadata_block(ExtMetadataBlock::Level4(level4))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// L1 min, max and average PQ
+fn level1_stats(rpu: &DoviRpu) -> Option<[f64; 3]> {
+    match rpu.vdr_dm_data.as_ref()?.get_block(1) {
+        Some(ExtMetadataBlock::Level1(l1)) => {
+            Some([l1.min_pq as f64, l1.max_pq as f64, l1.avg_pq as f64])
+        }
+        _ => None,
+    }
+}
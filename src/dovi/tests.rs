@@ -17,6 +17,24 @@ pub fn _parse_file(input: PathBuf) -> Result<(Vec<u8>, DoviRpu)> {
     Ok((original_data, dovi_rpu))
 }
 
+/// Writes a 10-bit Y4M file with the luma planes, and neutral chroma
+fn _write_y4m(name: &str, width: usize, height: usize, frames: &[Vec<u16>]) -> Result<PathBuf> {
+    use std::io::Write;
+
+    let mut data = format!("YUV4MPEG2 W{} H{} F24:1 Ip C420p10\n", width, height).into_bytes();
+
+    for luma in frames {
+        data.extend(b"FRAME\n");
+        luma.iter().for_each(|y| data.extend(&y.to_le_bytes()));
+        (0..width * height / 2).for_each(|_| data.extend(&512_u16.to_le_bytes()));
+    }
+
+    let path = std::env::temp_dir().join(name);
+    File::create(&path)?.write_all(&data)?;
+
+    Ok(path)
+}
+
 fn _debug(data: &[u8]) -> Result<()> {
     use crate::dovi::OUT_NAL_HEADER;
     use std::fs::OpenOptions;
@@ -280,6 +298,7 @@ fn generated_rpu() -> Result<()> {
             max_content_light_level: 1000,
             max_frame_average_light_level: 400,
         },
+        level9: None,
         ..Default::default()
     };
 
@@ -401,6 +420,7 @@ fn cmv40_full_rpu() -> Result<()> {
             max_content_light_level: 1000,
             max_frame_average_light_level: 400,
         },
+        level9: None,
         ..Default::default()
     };
 
@@ -508,3 +528,2388 @@ fn empty_dmv1_blocks() -> Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn cmv29_to_cmv40_trims() -> Result<()> {
+    use dolby_vision::rpu::extension_metadata::blocks::*;
+    use dolby_vision::rpu::generate::{GenerateConfig, VideoShot};
+    use dolby_vision::rpu::vdr_dm_data::CmVersion;
+
+    let mut config = GenerateConfig {
+        cm_version: CmVersion::V29,
+        length: 1,
+        ..Default::default()
+    };
+
+    config.shots.push(VideoShot {
+        start: 0,
+        duration: 1,
+        metadata_blocks: vec![
+            ExtMetadataBlock::Level2(ExtMetadataBlockLevel2 {
+                trim_slope: 2013,
+                trim_offset: 2016,
+                trim_power: 1339,
+                ms_weight: -1,
+                ..ExtMetadataBlockLevel2::from_nits(100)
+            }),
+            ExtMetadataBlock::Level2(ExtMetadataBlockLevel2::from_nits(600)),
+            ExtMetadataBlock::Level2(ExtMetadataBlockLevel2::from_nits(800)),
+        ],
+        ..Default::default()
+    });
+
+    let mut rpus = config.generate_rpu_list()?;
+
+    {
+        let vdr_dm_data = rpus[0].vdr_dm_data.as_mut().unwrap();
+        assert!(vdr_dm_data.cmv40_metadata.is_none());
+
+        vdr_dm_data.convert_to_cmv40(0)?;
+
+        // Converting again does not duplicate the trims
+        vdr_dm_data.convert_to_cmv40(0)?;
+
+        assert_eq!(vdr_dm_data.level_blocks_iter(8).count(), 3);
+        assert_eq!(vdr_dm_data.level_blocks_iter(10).count(), 1);
+
+        let mut level8_iter = vdr_dm_data.level_blocks_iter(8);
+
+        if let ExtMetadataBlock::Level8(l8) = level8_iter.next().unwrap() {
+            assert_eq!(l8.target_display_index, 1);
+            assert_eq!(l8.trim_slope, 2013);
+            assert_eq!(l8.trim_offset, 2016);
+            assert_eq!(l8.trim_power, 1339);
+            assert_eq!(l8.ms_weight, 2048);
+        }
+
+        if let ExtMetadataBlock::Level8(l8) = level8_iter.next().unwrap() {
+            assert_eq!(l8.target_display_index, 27);
+        }
+
+        if let ExtMetadataBlock::Level8(l8) = level8_iter.next().unwrap() {
+            assert_eq!(l8.target_display_index, 255);
+        }
+
+        if let Some(ExtMetadataBlock::Level10(l10)) = vdr_dm_data.get_block(10) {
+            assert_eq!(l10.target_display_index, 255);
+            assert_eq!(l10.target_max_pq, 2979);
+        } else {
+            panic!("No L10 block");
+        }
+
+        if let Some(ExtMetadataBlock::Level9(l9)) = vdr_dm_data.get_block(9) {
+            assert_eq!(l9.source_primary_index, 0);
+        } else {
+            panic!("No L9 block");
+        }
+    }
+
+    let encoded_rpus = GenerateConfig::encode_rpus(&mut rpus);
+    let reparsed_rpus = DoviRpu::parse_list_of_unspec62_nalus(&encoded_rpus);
+    assert_eq!(reparsed_rpus.len(), 1);
+
+    Ok(())
+}
+
+#[test]
+fn cmv40_to_cmv29_trims() -> Result<()> {
+    use dolby_vision::rpu::extension_metadata::blocks::*;
+    use dolby_vision::rpu::generate::{GenerateConfig, VideoShot};
+    use dolby_vision::rpu::vdr_dm_data::CmVersion;
+
+    let level1 = ExtMetadataBlock::Level1(ExtMetadataBlockLevel1::from_stats(0, 2828, 1120));
+
+    let cmv40_config = GenerateConfig {
+        cm_version: CmVersion::V40,
+        length: 1,
+        shots: vec![VideoShot {
+            start: 0,
+            duration: 1,
+            metadata_blocks: vec![
+                level1.clone(),
+                ExtMetadataBlock::Level3(ExtMetadataBlockLevel3 {
+                    min_pq_offset: 2048,
+                    max_pq_offset: 1871,
+                    avg_pq_offset: 2048,
+                }),
+                ExtMetadataBlock::Level8(ExtMetadataBlockLevel8 {
+                    target_display_index: 1,
+                    trim_slope: 2068,
+                    trim_power: 1955,
+                    ..Default::default()
+                }),
+                ExtMetadataBlock::Level8(ExtMetadataBlockLevel8 {
+                    trim_slope: 2049,
+                    ..Default::default()
+                }),
+                ExtMetadataBlock::Level9(ExtMetadataBlockLevel9::default_dci_p3()),
+            ],
+            ..Default::default()
+        }],
+        ..Default::default()
+    };
+
+    let cmv29_config = GenerateConfig {
+        cm_version: CmVersion::V29,
+        length: 1,
+        shots: vec![VideoShot {
+            start: 0,
+            duration: 1,
+            metadata_blocks: vec![
+                level1,
+                ExtMetadataBlock::Level2(ExtMetadataBlockLevel2 {
+                    trim_slope: 2068,
+                    trim_power: 1955,
+                    ..ExtMetadataBlockLevel2::from_nits(100)
+                }),
+                ExtMetadataBlock::Level2(ExtMetadataBlockLevel2 {
+                    trim_slope: 2049,
+                    ..ExtMetadataBlockLevel2::from_nits(1000)
+                }),
+            ],
+            ..Default::default()
+        }],
+        ..Default::default()
+    };
+
+    let mut rpus = cmv40_config.generate_rpu_list()?;
+
+    if let Some(ref mut vdr_dm_data) = rpus[0].vdr_dm_data {
+        vdr_dm_data.convert_to_cmv29()?;
+
+        assert!(vdr_dm_data.cmv40_metadata.is_none());
+        assert_eq!(vdr_dm_data.level_blocks_iter(2).count(), 2);
+
+        vdr_dm_data.validate()?;
+    }
+
+    let mut cmv29_rpus = cmv29_config.generate_rpu_list()?;
+
+    let converted_data = GenerateConfig::encode_rpus(&mut rpus);
+    let cmv29_data = GenerateConfig::encode_rpus(&mut cmv29_rpus);
+
+    assert_eq!(converted_data, cmv29_data);
+
+    // Trim for a target display that is neither predefined nor in L10
+    let mut rpus = cmv40_config.generate_rpu_list()?;
+    let vdr_dm_data = rpus[0].vdr_dm_data.as_mut().unwrap();
+
+    vdr_dm_data.add_metadata_block(ExtMetadataBlock::Level8(ExtMetadataBlockLevel8 {
+        target_display_index: 200,
+        ..Default::default()
+    }))?;
+    assert!(vdr_dm_data.convert_to_cmv29().is_err());
+
+    Ok(())
+}
+
+#[test]
+fn generate_target_trims() -> Result<()> {
+    use dolby_vision::rpu::extension_metadata::blocks::*;
+    use dolby_vision::rpu::generate::{GenerateConfig, VideoShot};
+    use dolby_vision::rpu::vdr_dm_data::CmVersion;
+
+    let shot: VideoShot = serde_json::from_str(
+        r#"{
+            "start": 0,
+            "duration": 1,
+            "target_trims": [
+                { "target": "1000-nit P3 PQ", "trim_slope": 2200 },
+                { "target": "800-nit, BT.2020, ST.2084", "trim_power": 1900 }
+            ]
+        }"#,
+    )?;
+
+    let config = GenerateConfig {
+        cm_version: CmVersion::V40,
+        length: 1,
+        shots: vec![shot],
+        ..Default::default()
+    };
+
+    let mut rpus = config.generate_rpu_list()?;
+
+    let encoded_rpus = GenerateConfig::encode_rpus(&mut rpus);
+    let reparsed_rpus = DoviRpu::parse_list_of_unspec62_nalus(&encoded_rpus);
+    assert_eq!(reparsed_rpus.len(), 1);
+
+    let vdr_dm_data = rpus[0].vdr_dm_data.as_ref().unwrap();
+
+    assert_eq!(vdr_dm_data.level_blocks_iter(2).count(), 2);
+    assert_eq!(vdr_dm_data.level_blocks_iter(8).count(), 2);
+    assert_eq!(vdr_dm_data.level_blocks_iter(10).count(), 1);
+
+    let mut level8_iter = vdr_dm_data.level_blocks_iter(8);
+
+    if let ExtMetadataBlock::Level8(l8) = level8_iter.next().unwrap() {
+        assert_eq!(l8.target_display_index, 48);
+        assert_eq!(l8.trim_slope, 2200);
+        assert_eq!(l8.trim_power, 2048);
+    }
+
+    if let ExtMetadataBlock::Level8(l8) = level8_iter.next().unwrap() {
+        assert_eq!(l8.target_display_index, 255);
+        assert_eq!(l8.trim_power, 1900);
+    }
+
+    if let Some(ExtMetadataBlock::Level10(l10)) = vdr_dm_data.get_block(10) {
+        assert_eq!(l10.target_max_pq, 2979);
+        assert_eq!(l10.target_primary_index, 2);
+    } else {
+        panic!("No L10 block");
+    }
+
+    let display = vdr_dm_data.target_display(255).unwrap();
+    assert_eq!(display.to_string(), "800-nit, BT.2020, ST.2084, Full");
+    assert_eq!(
+        vdr_dm_data.target_display(1).unwrap().to_string(),
+        "100-nit, BT.709, BT.1886, Full"
+    );
+
+    // Out of range trims are rejected, instead of wrapping
+    for invalid_trim in &[
+        r#"{ "target": "1000-nit P3 PQ", "trim_slope": 4096 }"#,
+        r#"{ "target": "1000-nit P3 PQ", "ms_weight": 65535 }"#,
+    ] {
+        let mut vdr_dm_data = rpus[0].vdr_dm_data.clone().unwrap();
+        let trim = serde_json::from_str(invalid_trim)?;
+
+        assert!(vdr_dm_data.set_target_trim(&trim).is_err());
+    }
+
+    Ok(())
+}
+
+#[test]
+fn interpolated_trims() -> Result<()> {
+    use dolby_vision::rpu::extension_metadata::blocks::*;
+    use dolby_vision::rpu::generate::{GenerateConfig, VideoShot};
+    use dolby_vision::rpu::vdr_dm_data::CmVersion;
+
+    let config = GenerateConfig {
+        cm_version: CmVersion::V40,
+        length: 1,
+        source_max_pq: Some(3696),
+        shots: vec![VideoShot {
+            start: 0,
+            duration: 1,
+            metadata_blocks: vec![
+                ExtMetadataBlock::Level2(ExtMetadataBlockLevel2 {
+                    trim_slope: 2300,
+                    ..ExtMetadataBlockLevel2::from_nits(100)
+                }),
+                ExtMetadataBlock::Level2(ExtMetadataBlockLevel2 {
+                    trim_slope: 2100,
+                    ..ExtMetadataBlockLevel2::from_nits(1000)
+                }),
+            ],
+            ..Default::default()
+        }],
+        ..Default::default()
+    };
+
+    let mut rpus = config.generate_rpu_list()?;
+    let vdr_dm_data = rpus[0].vdr_dm_data.as_mut().unwrap();
+
+    // Between the 100 and 1000 nits trims
+    assert_eq!(vdr_dm_data.interpolated_trim(2851).trim_slope, 2146);
+    // Between the 1000 nits trim and the source max PQ
+    assert_eq!(vdr_dm_data.interpolated_trim(3388).trim_slope, 2074);
+    // Outside of the range
+    assert_eq!(vdr_dm_data.interpolated_trim(2000).trim_slope, 2300);
+    assert_eq!(vdr_dm_data.interpolated_trim(4000).trim_slope, 2048);
+
+    assert!(vdr_dm_data.add_interpolated_trim(2851)?);
+    assert!(vdr_dm_data.add_interpolated_trim(3388)?);
+    assert_eq!(vdr_dm_data.interpolated_trim(3388).trim_slope, 2074);
+
+    // Only the missing L8 trim is added
+    assert!(vdr_dm_data.add_interpolated_trim(3079)?);
+    assert!(!vdr_dm_data.add_interpolated_trim(3079)?);
+
+    assert_eq!(vdr_dm_data.level_blocks_iter(2).count(), 4);
+
+    // L8 trims are created, with predefined targets
+    let level8_indices: Vec<u8> = vdr_dm_data
+        .level_blocks_iter(8)
+        .filter_map(|b| match b {
+            ExtMetadataBlock::Level8(b) => Some(b.target_display_index),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(level8_indices, vec![27, 37, 48]);
+    assert!(vdr_dm_data.get_block(10).is_none());
+
+    Ok(())
+}
+
+#[test]
+fn editor_metadata_edits() -> Result<()> {
+    use super::editor::EditConfig;
+    use dolby_vision::rpu::extension_metadata::blocks::*;
+    use dolby_vision::rpu::generate::{GenerateConfig, VideoShot};
+
+    let config = GenerateConfig {
+        length: 10,
+        target_nits: Some(100),
+        shots: vec![VideoShot {
+            start: 0,
+            duration: 10,
+            metadata_blocks: vec![ExtMetadataBlock::Level1(
+                ExtMetadataBlockLevel1::from_stats(0, 3079, 1024),
+            )],
+            ..Default::default()
+        }],
+        ..Default::default()
+    };
+
+    let mut rpus: Vec<Option<DoviRpu>> =
+        config.generate_rpu_list()?.into_iter().map(Some).collect();
+
+    let edit_config: EditConfig = serde_json::from_reader(std::fs::File::open(
+        "./assets/editor_examples/metadata_edits.json",
+    )?)?;
+    edit_config.execute(&mut rpus)?;
+
+    for (i, rpu) in rpus.iter().enumerate() {
+        let vdr_dm_data = rpu.as_ref().unwrap().vdr_dm_data.as_ref().unwrap();
+
+        match vdr_dm_data.get_block(1) {
+            Some(ExtMetadataBlock::Level1(l1)) => {
+                assert_eq!(l1.max_pq, 2800);
+                assert_eq!(l1.avg_pq, 1120);
+            }
+            _ => panic!("No L1 block"),
+        }
+
+        match vdr_dm_data.get_block(2) {
+            Some(ExtMetadataBlock::Level2(l2)) => assert_eq!(l2.trim_slope, 1948),
+            _ => panic!("No L2 block"),
+        }
+
+        assert_eq!(vdr_dm_data.get_block(5).is_none(), i == 5);
+    }
+
+    // Adjustments must match a block and keep it valid
+    let invalid_edits = [
+        r#"{ "metadata_edits": [{ "range": "all", "adjust": [{ "level": 8, "field": "trim_slope", "delta": 1 }] }] }"#,
+        r#"{ "metadata_edits": [{ "range": "all", "adjust": [{ "level": 2, "field": "trim_slope", "set": 4096 }] }] }"#,
+    ];
+
+    for edits in invalid_edits.iter() {
+        let edit_config: EditConfig = serde_json::from_str(edits)?;
+        assert!(edit_config.execute(&mut rpus).is_err());
+    }
+
+    Ok(())
+}
+
+#[test]
+fn editor_scene_cuts() -> Result<()> {
+    use super::editor::EditConfig;
+    use dolby_vision::rpu::extension_metadata::blocks::*;
+    use dolby_vision::rpu::generate::{GenerateConfig, VideoShot};
+
+    let shot = |start, max_pq| VideoShot {
+        start,
+        duration: 5,
+        metadata_blocks: vec![ExtMetadataBlock::Level1(
+            ExtMetadataBlockLevel1::from_stats(0, max_pq, 1000),
+        )],
+        ..Default::default()
+    };
+
+    let config = GenerateConfig {
+        length: 10,
+        shots: vec![shot(0, 2081), shot(5, 3079)],
+        ..Default::default()
+    };
+
+    let generated: Vec<Option<DoviRpu>> =
+        config.generate_rpu_list()?.into_iter().map(Some).collect();
+
+    let scene_cuts = |rpus: &[Option<DoviRpu>]| -> Vec<usize> {
+        rpus.iter()
+            .enumerate()
+            .filter(|(_, rpu)| {
+                rpu.as_ref()
+                    .and_then(|rpu| rpu.vdr_dm_data.as_ref())
+                    .map_or(false, |vdr_dm_data| vdr_dm_data.scene_refresh_flag == 1)
+            })
+            .map(|(i, _)| i)
+            .collect()
+    };
+
+    let mut rpus = generated.clone();
+    let edit_config: EditConfig = serde_json::from_str(
+        r#"{ "scene_cuts": { "clear": ["all"], "set": [3], "from_l1": true } }"#,
+    )?;
+    edit_config.execute(&mut rpus)?;
+    assert_eq!(scene_cuts(&rpus), vec![0, 3, 5]);
+
+    let mut rpus = generated.clone();
+    let edit_config: EditConfig = serde_json::from_str(
+        r#"{
+            "scene_cuts": {
+                "clear": ["all"],
+                "import": "./assets/tests/scene_cuts.edl",
+                "edl_fps": 23.976
+            }
+        }"#,
+    )?;
+    edit_config.execute(&mut rpus)?;
+    assert_eq!(scene_cuts(&rpus), vec![0, 5, 7]);
+
+    // Drop-frame timecodes skip frames 00 and 01 at the minute
+    let drop_frame_config = |fps: f64| -> Result<EditConfig> {
+        Ok(serde_json::from_value(serde_json::json!({
+            "scene_cuts": {
+                "clear": ["all"],
+                "import": "./assets/tests/scene_cuts_df.edl",
+                "edl_fps": fps
+            }
+        }))?)
+    };
+
+    let mut rpus = generated.clone();
+    drop_frame_config(29.97)?.execute(&mut rpus)?;
+    assert_eq!(scene_cuts(&rpus), vec![0, 2, 5]);
+
+    assert!(drop_frame_config(23.976)?
+        .execute(&mut generated.clone())
+        .is_err());
+
+    let mut rpus = generated;
+    let edit_config: EditConfig =
+        serde_json::from_str(r#"{ "scene_cuts": { "merge": ["0-9"] } }"#)?;
+    edit_config.execute(&mut rpus)?;
+    assert_eq!(scene_cuts(&rpus), vec![0]);
+
+    for rpu in rpus.iter().flatten() {
+        let vdr_dm_data = rpu.vdr_dm_data.as_ref().unwrap();
+
+        match vdr_dm_data.get_block(1) {
+            Some(ExtMetadataBlock::Level1(l1)) => {
+                assert_eq!(l1.max_pq, 3079);
+                assert_eq!(l1.avg_pq, 1000);
+            }
+            _ => panic!("No L1 block"),
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn measure_y4m_video() -> Result<()> {
+    use super::video_analysis::{self, YuvReader};
+    use dolby_vision::rpu::extension_metadata::blocks::*;
+    use dolby_vision::rpu::generate::{GenerateConfig, VideoShot};
+
+    let (width, height) = (16, 8);
+
+    let frames: Vec<Vec<u16>> = [300, 300, 700, 700]
+        .iter()
+        .map(|luma| vec![*luma; width * height])
+        .collect();
+    let path = _write_y4m("dovi_tool_measure_test.y4m", width, height, &frames)?;
+
+    let mut reader = YuvReader::open(&path, None)?;
+    let measurements = video_analysis::measure_video(&mut reader)?;
+    std::fs::remove_file(&path)?;
+
+    assert_eq!(measurements.len(), 4);
+    assert_eq!(measurements[0].max_pq, 1103);
+    assert_eq!(measurements[3].avg_pq, 2973);
+
+    let scene_cuts = video_analysis::detect_scene_cuts(&measurements, 0.3);
+    assert_eq!(scene_cuts, vec![0, 2]);
+
+    let mut config = GenerateConfig::default();
+    video_analysis::generate_shots(&measurements, &scene_cuts, &mut config)?;
+
+    assert_eq!(config.length, 4);
+    assert_eq!(config.shots.len(), 2);
+    assert_eq!(config.shots[1].start, 2);
+    assert_eq!(config.shots[1].duration, 2);
+
+    // Flat frames, clamped to the L1 limits
+    if let ExtMetadataBlock::Level1(l1) = &config.shots[1].metadata_blocks[0] {
+        assert_eq!(l1.min_pq, 12);
+        assert_eq!(l1.max_pq, 2973);
+        assert_eq!(l1.avg_pq, 2972);
+    }
+
+    assert!(config.level6.max_content_light_level > 0);
+
+    // Shots of the config are kept, with the L1 replaced
+    let mut config = GenerateConfig {
+        shots: vec![VideoShot {
+            start: 0,
+            duration: 4,
+            metadata_blocks: vec![
+                ExtMetadataBlock::Level1(ExtMetadataBlockLevel1::from_stats(0, 4095, 1000)),
+                ExtMetadataBlock::Level2(ExtMetadataBlockLevel2::from_nits(600)),
+            ],
+            ..Default::default()
+        }],
+        ..Default::default()
+    };
+    video_analysis::generate_shots(&measurements, &scene_cuts, &mut config)?;
+
+    assert_eq!(config.shots.len(), 1);
+    assert_eq!(config.shots[0].metadata_blocks.len(), 2);
+
+    match &config.shots[0].metadata_blocks[0] {
+        ExtMetadataBlock::Level1(l1) => {
+            assert_eq!(l1.max_pq, 2973);
+            assert_eq!(l1.avg_pq, 2038);
+        }
+        _ => panic!("No L1 block"),
+    }
+
+    config.shots[0].duration = 5;
+    assert!(video_analysis::generate_shots(&measurements, &scene_cuts, &mut config).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn detect_letterbox() -> Result<()> {
+    use super::letterbox::{LetterboxDetector, LetterboxOpts};
+
+    let (width, height) = (32, 32);
+
+    let letterboxed: Vec<u16> = (0..height)
+        .flat_map(|row| {
+            let luma = if (8..height - 8).contains(&row) {
+                500
+            } else {
+                64
+            };
+            vec![luma; width]
+        })
+        .collect();
+
+    let frames = vec![
+        letterboxed.clone(),
+        letterboxed,
+        // Too dark, keeps the previous bars
+        vec![100; width * height],
+        vec![100; width * height],
+        // No bars
+        vec![700; width * height],
+        vec![700; width * height],
+    ];
+
+    let video = _write_y4m("dovi_tool_letterbox_test.y4m", width, height, &frames)?;
+    let json_out = std::env::temp_dir().join("dovi_tool_letterbox_test.json");
+
+    LetterboxDetector::detect(
+        video.clone(),
+        None,
+        Some(json_out.clone()),
+        None,
+        None,
+        LetterboxOpts {
+            scene_threshold: 0.3,
+            black_level: 80,
+            dark_level: 128,
+            tolerance: 4,
+        },
+    )?;
+
+    let edit_json: serde_json::Value = serde_json::from_reader(File::open(&json_out)?)?;
+
+    std::fs::remove_file(&video)?;
+    std::fs::remove_file(&json_out)?;
+
+    let active_area = &edit_json["active_area"];
+    assert_eq!(active_area["presets"][0]["top"], 8);
+    assert_eq!(active_area["presets"][0]["bottom"], 8);
+    assert_eq!(active_area["presets"][0]["left"], 0);
+    assert_eq!(active_area["presets"][1]["top"], 0);
+    assert_eq!(active_area["edits"]["0-3"], 0);
+    assert_eq!(active_area["edits"]["4-5"], 1);
+
+    Ok(())
+}
+
+/// Writes a 10-bit 4:2:0 HEVC SPS NAL unit, with a BT.2020 PQ VUI
+fn _sps_nal(width: u64, height: u64, conf_win_bottom: u64, full_range: bool) -> Vec<u8> {
+    use bitvec_helpers::bitvec_writer::BitVecWriter;
+    use dolby_vision::utils::add_start_code_emulation_prevention_3_byte;
+
+    let mut writer = BitVecWriter::new();
+
+    // NAL header: SPS, layer 0, temporal id 1
+    writer.write_n(&[0x42, 0x01], 16);
+
+    writer.write_n(&[0], 4); // sps_video_parameter_set_id
+    writer.write_n(&[0], 3); // sps_max_sub_layers_minus1
+    writer.write(true); // sps_temporal_id_nesting_flag
+
+    // Main 10 profile, level 5.1
+    writer.write_n(&[0], 2);
+    writer.write(false);
+    writer.write_n(&[2], 5);
+    writer.write_n(&[0x20, 0, 0, 0], 32);
+    writer.write_n(&[0x90, 0, 0, 0, 0, 0], 48);
+    writer.write_n(&[153], 8);
+
+    writer.write_ue(0); // sps_seq_parameter_set_id
+    writer.write_ue(1); // chroma_format_idc
+    writer.write_ue(width);
+    writer.write_ue(height);
+
+    writer.write(conf_win_bottom > 0);
+    if conf_win_bottom > 0 {
+        writer.write_ue(0);
+        writer.write_ue(0);
+        writer.write_ue(0);
+        writer.write_ue(conf_win_bottom / 2);
+    }
+
+    writer.write_ue(2); // bit_depth_luma_minus8
+    writer.write_ue(2); // bit_depth_chroma_minus8
+    writer.write_ue(4); // log2_max_pic_order_cnt_lsb_minus4
+    writer.write(true); // sps_sub_layer_ordering_info_present_flag
+    writer.write_ue(4);
+    writer.write_ue(2);
+    writer.write_ue(0);
+
+    writer.write_ue(0);
+    writer.write_ue(3);
+    writer.write_ue(0);
+    writer.write_ue(3);
+    writer.write_ue(4);
+    writer.write_ue(4);
+
+    writer.write(false); // scaling_list_enabled_flag
+    writer.write(true); // amp_enabled_flag
+    writer.write(true); // sample_adaptive_offset_enabled_flag
+    writer.write(false); // pcm_enabled_flag
+
+    // Two short-term RPS, the second one predicted from the first
+    writer.write_ue(2);
+    writer.write_ue(1); // num_negative_pics
+    writer.write_ue(1); // num_positive_pics
+    writer.write_ue(0);
+    writer.write(true);
+    writer.write_ue(1);
+    writer.write(true);
+
+    writer.write(true); // inter_ref_pic_set_prediction_flag
+    writer.write(false); // delta_rps_sign
+    writer.write_ue(0); // abs_delta_rps_minus1
+    writer.write(true);
+    writer.write(false);
+    writer.write(true);
+    writer.write(true);
+
+    writer.write(false); // long_term_ref_pics_present_flag
+    writer.write(true); // sps_temporal_mvp_enabled_flag
+    writer.write(true); // strong_intra_smoothing_enabled_flag
+
+    writer.write(true); // vui_parameters_present_flag
+    writer.write(false); // aspect_ratio_info_present_flag
+    writer.write(false); // overscan_info_present_flag
+    writer.write(true); // video_signal_type_present_flag
+    writer.write_n(&[5], 3);
+    writer.write(full_range);
+    writer.write(true); // colour_description_present_flag
+    writer.write_n(&[9], 8);
+    writer.write_n(&[16], 8);
+    writer.write_n(&[9], 8);
+    writer.write(false); // chroma_loc_info_present_flag
+    writer.write(false);
+    writer.write(false);
+    writer.write(false);
+    writer.write(false); // default_display_window_flag
+    writer.write(true); // vui_timing_info_present_flag
+    writer.write_n(&1001_u32.to_be_bytes(), 32);
+    writer.write_n(&24000_u32.to_be_bytes(), 32);
+    writer.write(false); // vui_poc_proportional_to_timing_flag
+    writer.write(false); // vui_hrd_parameters_present_flag
+    writer.write(false); // bitstream_restriction_flag
+
+    writer.write(false); // sps_extension_present_flag
+
+    // rbsp_trailing_bits
+    writer.write(true);
+    while !writer.is_aligned() {
+        writer.write(false);
+    }
+
+    let mut data = writer.as_slice().to_vec();
+    add_start_code_emulation_prevention_3_byte(&mut data);
+
+    data
+}
+
+#[test]
+fn hevc_sps_validation() -> Result<()> {
+    use super::hevc_info::SpsInfo;
+    use dolby_vision::rpu::extension_metadata::blocks::{ExtMetadataBlock, ExtMetadataBlockLevel5};
+    use std::io::Write;
+
+    let mut hevc = vec![0, 0, 0, 1, 0x40, 0x01, 0x0C];
+    hevc.extend(&[0, 0, 0, 1]);
+    hevc.extend(_sps_nal(3840, 2160, 0, false));
+    hevc.extend(&[0, 0, 0, 1, 0x44, 0x01, 0xC1]);
+
+    let path = std::env::temp_dir().join("dovi_tool_sps_test.hevc");
+    File::create(&path)?.write_all(&hevc)?;
+
+    let sps = SpsInfo::from_hevc_file(&path)?;
+    std::fs::remove_file(&path)?;
+
+    assert_eq!(sps.profile_idc, 2);
+    assert_eq!(sps.level_idc, 153);
+    assert_eq!(sps.display_size(), (3840, 2160));
+    assert_eq!(sps.bit_depth, 10);
+    assert!(!sps.full_range());
+
+    let vui = sps.vui.as_ref().unwrap();
+    assert_eq!(vui.colour_primaries, 9);
+    assert_eq!(vui.transfer_characteristics, 16);
+    assert_eq!(vui.matrix_coeffs, 9);
+    assert_eq!(vui.time_scale, Some(24000));
+
+    // 1088 lines coded, cropped to 1080
+    let cropped = SpsInfo::parse(&_sps_nal(1920, 1088, 8, true))?;
+    assert_eq!(cropped.display_size(), (1920, 1080));
+    assert!(cropped.full_range());
+
+    let (_, mut rpu) = _parse_file(PathBuf::from("./assets/tests/profile8.bin"))?;
+    assert!(sps.validate_rpu(&rpu).is_empty());
+
+    // Full range flag mismatch
+    assert_eq!(cropped.validate_rpu(&rpu).len(), 1);
+
+    let vdr_dm_data = rpu.vdr_dm_data.as_mut().unwrap();
+    vdr_dm_data.replace_metadata_level(ExtMetadataBlock::Level5(ExtMetadataBlockLevel5 {
+        active_area_top_offset: 1080,
+        active_area_bottom_offset: 1080,
+        ..Default::default()
+    }))?;
+
+    let warnings = sps.validate_rpu(&rpu);
+    assert_eq!(warnings.len(), 1);
+    assert!(warnings[0].starts_with("L5 offsets"));
+
+    Ok(())
+}
+
+#[test]
+fn dovi_configuration_record() -> Result<()> {
+    use super::hevc_info::SpsInfo;
+    use dolby_vision::rpu::configuration_record::DoviDecoderConfigurationRecord;
+
+    let sps = SpsInfo::parse(&_sps_nal(3840, 2160, 0, false))?;
+    assert_eq!(sps.codec_string("hvc1"), "hvc1.2.4.L153.90");
+
+    let (width, height) = sps.display_size();
+    let level =
+        DoviDecoderConfigurationRecord::level_from_stream(width, height, sps.frame_rate().unwrap());
+    assert_eq!(level, Some(6));
+
+    let transfer = sps.vui.as_ref().map(|vui| vui.transfer_characteristics);
+
+    let (_, rpu) = _parse_file(PathBuf::from("./assets/tests/profile8.bin"))?;
+    let record = DoviDecoderConfigurationRecord::from_rpu(&rpu, 6, transfer)?;
+    assert_eq!(record.dv_bl_signal_compatibility_id, 1);
+    assert_eq!(record.box_type(), "dvvC");
+    assert_eq!(record.codec_string(false), "dvh1.08.06");
+
+    // HLG base layer
+    let record = DoviDecoderConfigurationRecord::from_rpu(&rpu, 6, Some(18))?;
+    assert_eq!(record.compatibility_brand(), Some("db4h"));
+
+    // Unspecified transfer, PQ from the RPU
+    let record = DoviDecoderConfigurationRecord::from_rpu(&rpu, 6, Some(2))?;
+    assert_eq!(record.dv_bl_signal_compatibility_id, 1);
+
+    let (_, rpu) = _parse_file(PathBuf::from("./assets/tests/fel_orig.bin"))?;
+    let record = DoviDecoderConfigurationRecord::from_rpu(&rpu, 6, transfer)?;
+    assert_eq!(record.box_type(), "dvcC");
+    assert!(record.el_present_flag);
+    assert_eq!(record.dv_bl_signal_compatibility_id, 6);
+    assert_eq!(record.codec_string(true), "dvhe.07.06");
+
+    let (_, rpu) = _parse_file(PathBuf::from("./assets/tests/profile5.bin"))?;
+    let record = DoviDecoderConfigurationRecord::from_rpu(&rpu, 6, None)?;
+    assert_eq!(record.dv_bl_signal_compatibility_id, 0);
+    assert_eq!(record.compatibility_brand(), None);
+
+    Ok(())
+}
+
+/// Writes the NAL units to a raw HEVC file, with 4 bytes start codes
+fn _write_hevc(name: &str, nals: &[Vec<u8>]) -> Result<PathBuf> {
+    use std::io::Write;
+
+    let mut data = Vec::new();
+
+    for nal in nals {
+        data.extend(&[0, 0, 0, 1]);
+        data.extend(nal);
+    }
+
+    let path = std::env::temp_dir().join(name);
+    File::create(&path)?.write_all(&data)?;
+
+    Ok(path)
+}
+
+/// Splits a raw HEVC file written with 4 bytes start codes into its NAL units
+fn _read_hevc_nals(path: &std::path::Path) -> Result<Vec<Vec<u8>>> {
+    let data = std::fs::read(path)?;
+    let mut nals: Vec<Vec<u8>> = Vec::new();
+
+    let mut i = 0;
+    while i < data.len() {
+        if data[i..].starts_with(&[0, 0, 0, 1]) {
+            nals.push(Vec::new());
+            i += 4;
+        } else {
+            nals.last_mut().unwrap().push(data[i]);
+            i += 1;
+        }
+    }
+
+    Ok(nals)
+}
+
+#[test]
+fn convert_hdr10_seis() -> Result<()> {
+    use super::converter::Converter;
+    use super::sei::{SeiNal, SEI_CONTENT_LIGHT_LEVEL_INFO, SEI_MASTERING_DISPLAY_COLOUR_VOLUME};
+    use super::CliOptions;
+
+    let (_, rpu) = _parse_file(PathBuf::from("./assets/tests/data_before_crc32.bin"))?;
+    let rpu_nal = rpu.write_hevc_unspec62_nalu()?;
+
+    // Previous mastering display SEI, along with a user data unregistered SEI
+    let old_seis = SeiNal::prefix(vec![
+        super::sei::SeiPayload {
+            payload_type: SEI_MASTERING_DISPLAY_COLOUR_VOLUME,
+            data: vec![0x10; 24],
+        },
+        super::sei::SeiPayload {
+            payload_type: 5,
+            data: vec![0x20; 16],
+        },
+    ]);
+
+    let input = _write_hevc(
+        "dovi_tool_hdr10_sei_test.hevc",
+        &[
+            _sps_nal(3840, 2160, 0, false),
+            old_seis.write(),
+            vec![0x26, 0x01, 0xAF, 0x10, 0x80],
+            rpu_nal.clone(),
+            vec![0x02, 0x01, 0xD0, 0x20, 0x80],
+            rpu_nal,
+        ],
+    )?;
+    let output = std::env::temp_dir().join("dovi_tool_hdr10_sei_test_out.hevc");
+
+    let options = CliOptions {
+        mode: None,
+        crop: false,
+        discard_el: false,
+        drop_hdr10plus: false,
+        hdr10_sei: true,
+        fps: None,
+    };
+
+    Converter::convert(Some(input.clone()), None, Some(output.clone()), options)?;
+
+    let nals = _read_hevc_nals(&output)?;
+
+    std::fs::remove_file(&input)?;
+    std::fs::remove_file(&output)?;
+
+    let nal_types: Vec<u8> = nals.iter().map(|nal| nal[0] >> 1).collect();
+    assert_eq!(nal_types, vec![33, 39, 39, 39, 19, 62, 1, 62]);
+
+    // Only the unregistered user data is left in the original SEI
+    let old_seis = SeiNal::parse(&nals[1])?;
+    assert_eq!(old_seis.messages.len(), 1);
+    assert_eq!(old_seis.messages[0].payload_type, 5);
+
+    let mastering_display = SeiNal::parse(&nals[2])?;
+    assert_eq!(
+        mastering_display.messages[0].payload_type,
+        SEI_MASTERING_DISPLAY_COLOUR_VOLUME
+    );
+
+    // BT.2020 primaries from L9, 1000 nits max and 0.0001 nits min from L6
+    let payload = &mastering_display.messages[0].data;
+    assert_eq!(&payload[..4], &[0x21, 0x34, 0x9B, 0xAA]);
+    assert_eq!(&payload[16..], &[0, 0x98, 0x96, 0x80, 0, 0, 0, 1]);
+
+    let content_light_level = SeiNal::parse(&nals[3])?;
+    assert_eq!(
+        content_light_level.messages[0].payload_type,
+        SEI_CONTENT_LIGHT_LEVEL_INFO
+    );
+    assert_eq!(
+        content_light_level.messages[0].data,
+        vec![0x02, 0x8A, 0x02, 0x6D]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn generate_l6_from_hdr10_seis() -> Result<()> {
+    use super::generator::set_static_metadata_from_seis;
+    use super::sei::{Hdr10Metadata, Hdr10SeiScan};
+    use dolby_vision::rpu::extension_metadata::blocks::*;
+    use dolby_vision::rpu::extension_metadata::target_display::TargetPrimaries;
+
+    let level6 = ExtMetadataBlockLevel6 {
+        max_display_mastering_luminance: 4000,
+        min_display_mastering_luminance: 50,
+        max_content_light_level: 3000,
+        max_frame_average_light_level: 500,
+    };
+    let first = Hdr10Metadata::from_level6(&level6, Some(TargetPrimaries::Bt2020)).sei_nals();
+
+    // The mastering display and content light level change on the second picture
+    let mut changed = Hdr10Metadata::from_level6(&level6, Some(TargetPrimaries::Bt2020));
+    changed.mastering_display.max_luminance = 1000 * 10_000;
+    changed.content_light_level.max_content_light_level = 1000;
+    let changed = changed.sei_nals();
+
+    let path = _write_hevc(
+        "dovi_tool_sei_scan_test.hevc",
+        &[
+            _sps_nal(3840, 2160, 0, false),
+            first[0].clone(),
+            first[1].clone(),
+            vec![0x26, 0x01, 0xAF, 0x10, 0x80],
+            changed[0].clone(),
+            changed[1].clone(),
+            vec![0x26, 0x01, 0xAF, 0x20, 0x80],
+        ],
+    )?;
+
+    // The first values are kept, with a warning for the second frame
+    let scan = Hdr10SeiScan::scan_file(&path)?;
+    assert_eq!(scan.mastering_display_changed_at, Some(1));
+    assert_eq!(scan.content_light_level_changed_at, Some(1));
+    assert_eq!(
+        scan.content_light_level.unwrap().max_content_light_level,
+        3000
+    );
+
+    let mastering_display = scan.mastering_display.unwrap();
+    assert_eq!(mastering_display.max_luminance, 4000 * 10_000);
+    assert_eq!(mastering_display.primaries(), Some(TargetPrimaries::Bt2020));
+
+    let mut config = GenerateConfig {
+        length: 1,
+        shots: vec![dolby_vision::rpu::generate::VideoShot {
+            duration: 1,
+            ..Default::default()
+        }],
+        ..Default::default()
+    };
+
+    set_static_metadata_from_seis(&path, &mut config)?;
+    std::fs::remove_file(&path)?;
+
+    assert_eq!(config.level6.max_display_mastering_luminance, 4000);
+    assert_eq!(config.level6.min_display_mastering_luminance, 50);
+    assert_eq!(config.level6.max_content_light_level, 3000);
+    assert_eq!(config.level6.max_frame_average_light_level, 500);
+
+    let rpus = config.generate_rpu_list()?;
+    let vdr_dm_data = rpus[0].vdr_dm_data.as_ref().unwrap();
+
+    if let Some(ExtMetadataBlock::Level9(level9)) = vdr_dm_data.get_block(9) {
+        assert_eq!(level9.source_primary_index, 2);
+    } else {
+        panic!("No L9 block");
+    }
+
+    Ok(())
+}
+
+#[test]
+fn remove_dovi_nals() -> Result<()> {
+    use super::remover::Remover;
+    use super::CliOptions;
+
+    let (_, rpu) = _parse_file(PathBuf::from("./assets/tests/profile8.bin"))?;
+    let rpu_nal = rpu.write_hevc_unspec62_nalu()?;
+
+    let mut hdr10plus_sei = vec![
+        0x4E, 0x01, 0x04, 0x0A, 0xB5, 0x00, 0x3C, 0x00, 0x01, 0x04, 0x01,
+    ];
+    hdr10plus_sei.extend(&[0x40, 0x00, 0x0C, 0x80]);
+
+    let input = _write_hevc(
+        "dovi_tool_remove_test.hevc",
+        &[
+            _sps_nal(3840, 2160, 0, false),
+            hdr10plus_sei,
+            vec![0x26, 0x01, 0xAF, 0x10, 0x80],
+            vec![0x7E, 0x01, 0x40, 0x01, 0x0C, 0x80],
+            rpu_nal.clone(),
+            vec![0x02, 0x01, 0xD0, 0x20, 0x80],
+            rpu_nal,
+        ],
+    )?;
+    let output = std::env::temp_dir().join("dovi_tool_remove_test_out.hevc");
+
+    let options = CliOptions {
+        mode: None,
+        crop: false,
+        discard_el: false,
+        drop_hdr10plus: true,
+        hdr10_sei: false,
+        fps: None,
+    };
+
+    Remover::remove(Some(input.clone()), None, Some(output.clone()), options)?;
+
+    let nals = _read_hevc_nals(&output)?;
+
+    std::fs::remove_file(&input)?;
+    std::fs::remove_file(&output)?;
+
+    let nal_types: Vec<u8> = nals.iter().map(|nal| nal[0] >> 1).collect();
+    assert_eq!(nal_types, vec![33, 19, 1]);
+
+    // Untouched otherwise
+    assert_eq!(nals[1], vec![0x26, 0x01, 0xAF, 0x10, 0x80]);
+
+    Ok(())
+}
+
+fn _vps_nal() -> Vec<u8> {
+    use bitvec_helpers::bitvec_writer::BitVecWriter;
+    use dolby_vision::utils::add_start_code_emulation_prevention_3_byte;
+
+    let mut writer = BitVecWriter::new();
+
+    writer.write_n(&[0x40, 0x01], 16);
+
+    writer.write_n(&[0], 4); // vps_video_parameter_set_id
+    writer.write_n(&[3], 2); // vps_base_layer_internal_flag, vps_base_layer_available_flag
+    writer.write_n(&[0], 6); // vps_max_layers_minus1
+    writer.write_n(&[0], 3); // vps_max_sub_layers_minus1
+    writer.write(true); // vps_temporal_id_nesting_flag
+    writer.write_n(&[0xFF, 0xFF], 16);
+
+    writer.write_n(&[0], 2);
+    writer.write(false);
+    writer.write_n(&[2], 5);
+    writer.write_n(&[0x20, 0, 0, 0], 32);
+    writer.write_n(&[0x90, 0, 0, 0, 0, 0], 48);
+    writer.write_n(&[153], 8);
+
+    writer.write(true); // vps_sub_layer_ordering_info_present_flag
+    writer.write_ue(4);
+    writer.write_ue(2);
+    writer.write_ue(0);
+
+    writer.write_n(&[0], 6); // vps_max_layer_id
+    writer.write_ue(0); // vps_num_layer_sets_minus1
+    writer.write(false); // vps_timing_info_present_flag
+    writer.write(false); // vps_extension_flag
+
+    writer.write(true);
+    while !writer.is_aligned() {
+        writer.write(false);
+    }
+
+    let mut data = writer.as_slice().to_vec();
+    add_start_code_emulation_prevention_3_byte(&mut data);
+
+    data
+}
+
+fn _pps_nal() -> Vec<u8> {
+    use bitvec_helpers::bitvec_writer::BitVecWriter;
+    use dolby_vision::utils::add_start_code_emulation_prevention_3_byte;
+
+    let mut writer = BitVecWriter::new();
+
+    writer.write_n(&[0x44, 0x01], 16);
+
+    writer.write_ue(0); // pps_pic_parameter_set_id
+    writer.write_ue(0); // pps_seq_parameter_set_id
+
+    // dependent_slice_segments_enabled_flag, output_flag_present_flag,
+    // num_extra_slice_header_bits, sign_data_hiding_enabled_flag, cabac_init_present_flag
+    writer.write_n(&[0], 7);
+
+    writer.write_ue(0); // num_ref_idx_l0_default_active_minus1
+    writer.write_ue(0); // num_ref_idx_l1_default_active_minus1
+    writer.write_se(0); // init_qp_minus26
+
+    // constrained_intra_pred_flag, transform_skip_enabled_flag, cu_qp_delta_enabled_flag
+    writer.write_n(&[0], 3);
+    writer.write_se(0); // pps_cb_qp_offset
+    writer.write_se(0); // pps_cr_qp_offset
+
+    // Chroma QP offsets, weighted prediction, transquant bypass, tiles, entropy sync,
+    // loop filter across slices, deblocking control, scaling list, lists modification
+    writer.write_n(&[0, 0], 10);
+    writer.write_ue(0); // log2_parallel_merge_level_minus2
+    writer.write(false); // slice_segment_header_extension_present_flag
+    writer.write(false); // pps_extension_present_flag
+
+    writer.write(true);
+    while !writer.is_aligned() {
+        writer.write(false);
+    }
+
+    let mut data = writer.as_slice().to_vec();
+    add_start_code_emulation_prevention_3_byte(&mut data);
+
+    data
+}
+
+/// First slice segment header of a picture, with `poc_lsb` for non-IDR pictures
+fn _slice_nal(nal_type: u8, slice_type: u64, poc_lsb: u8) -> Vec<u8> {
+    use bitvec_helpers::bitvec_writer::BitVecWriter;
+    use dolby_vision::utils::add_start_code_emulation_prevention_3_byte;
+
+    let mut writer = BitVecWriter::new();
+
+    writer.write_n(&[nal_type << 1, 0x01], 16);
+
+    writer.write(true); // first_slice_segment_in_pic_flag
+
+    if (16..=23).contains(&nal_type) {
+        writer.write(false); // no_output_of_prior_pics_flag
+    }
+
+    writer.write_ue(0); // slice_pic_parameter_set_id
+    writer.write_ue(slice_type);
+
+    if nal_type != 19 && nal_type != 20 {
+        writer.write_n(&[poc_lsb], 8);
+    }
+
+    // Fake slice data
+    writer.write_n(&[0xA5, 0x5A], 16);
+
+    writer.write(true);
+    while !writer.is_aligned() {
+        writer.write(false);
+    }
+
+    let mut data = writer.as_slice().to_vec();
+    add_start_code_emulation_prevention_3_byte(&mut data);
+
+    data
+}
+
+/// Parseable stream with reordered frames: IDR, P (POC 2), B (POC 1), IDR
+fn _reordered_hevc_nals(rpu_nal: &[u8]) -> Vec<Vec<u8>> {
+    vec![
+        _vps_nal(),
+        _sps_nal(3840, 2160, 0, false),
+        _pps_nal(),
+        _slice_nal(19, 2, 0),
+        rpu_nal.to_vec(),
+        _slice_nal(1, 1, 2),
+        rpu_nal.to_vec(),
+        _slice_nal(0, 0, 1),
+        rpu_nal.to_vec(),
+        _vps_nal(),
+        _sps_nal(3840, 2160, 0, false),
+        _pps_nal(),
+        _slice_nal(19, 2, 0),
+        rpu_nal.to_vec(),
+    ]
+}
+
+/// Flattens the EBML elements of `data`, descending into the `masters` elements
+fn _ebml_elements(data: &[u8], masters: &[u32], elements: &mut Vec<(u32, Vec<u8>)>) {
+    let mut pos = 0;
+
+    while pos < data.len() {
+        let id_len = data[pos].leading_zeros() as usize + 1;
+        let id = data[pos..pos + id_len]
+            .iter()
+            .fold(0_u32, |id, b| (id << 8) | *b as u32);
+        pos += id_len;
+
+        let size_len = data[pos].leading_zeros() as usize + 1;
+        let size = data[pos..pos + size_len]
+            .iter()
+            .enumerate()
+            .fold(0_u64, |size, (i, b)| {
+                let b = if i == 0 {
+                    b & (0xFF_u16 >> size_len) as u8
+                } else {
+                    *b
+                };
+                (size << 8) | b as u64
+            }) as usize;
+        pos += size_len;
+
+        let payload = &data[pos..pos + size];
+        elements.push((id, payload.to_vec()));
+
+        if masters.contains(&id) {
+            _ebml_elements(payload, masters, elements);
+        }
+
+        pos += size;
+    }
+}
+
+#[test]
+fn convert_to_matroska() -> Result<()> {
+    use super::converter::Converter;
+    use super::muxer::FrameRate;
+    use super::CliOptions;
+    use dolby_vision::rpu::configuration_record::DoviDecoderConfigurationRecord;
+
+    let (_, rpu) = _parse_file(PathBuf::from("./assets/tests/profile8.bin"))?;
+    let rpu_nal = rpu.write_hevc_unspec62_nalu()?;
+
+    let input = _write_hevc("dovi_tool_mkv_test.hevc", &_reordered_hevc_nals(&rpu_nal))?;
+    let output = std::env::temp_dir().join("dovi_tool_mkv_test_out.mkv");
+
+    let options = CliOptions {
+        mode: None,
+        crop: false,
+        discard_el: false,
+        drop_hdr10plus: false,
+        hdr10_sei: false,
+        fps: Some("24000/1001".parse::<FrameRate>()?),
+    };
+
+    Converter::convert(Some(input.clone()), None, Some(output.clone()), options)?;
+
+    let data = std::fs::read(&output)?;
+    std::fs::remove_file(&input)?;
+    std::fs::remove_file(&output)?;
+
+    // The intermediate HEVC stream is removed
+    let leftovers = std::fs::read_dir(std::env::temp_dir())?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| {
+            let name = entry.file_name().to_string_lossy().to_string();
+            name.starts_with("dovi_tool_mkv_test_out.") && name.ends_with(".tmp.hevc")
+        })
+        .count();
+    assert_eq!(leftovers, 0);
+
+    let masters = [
+        0x1A45_DFA3,
+        0x1853_8067,
+        0x1654_AE6B,
+        0xAE,
+        0x41E4,
+        0x1F43_B675,
+        0x1C53_BB6B,
+        0xBB,
+    ];
+    let mut elements = Vec::new();
+    _ebml_elements(&data, &masters, &mut elements);
+
+    let find = |id: u32| -> Vec<&Vec<u8>> {
+        elements
+            .iter()
+            .filter(|(i, _)| *i == id)
+            .map(|(_, payload)| payload)
+            .collect()
+    };
+
+    assert_eq!(find(0x4282)[0].as_slice(), b"matroska");
+    assert_eq!(find(0x86)[0].as_slice(), b"V_MPEGH/ISO/HEVC");
+
+    // Segment size covers the rest of the file
+    let segment = find(0x1853_8067)[0];
+    assert!(data.ends_with(segment));
+
+    // hvcC with VPS, SPS and PPS arrays
+    let hvcc = find(0x63A2)[0];
+    assert_eq!(&hvcc[..2], &[1, 2]);
+    assert_eq!(hvcc[22], 3);
+
+    assert_eq!(find(0x41E7)[0].as_slice(), b"dvvC");
+    let config = DoviDecoderConfigurationRecord::parse(find(0x41ED)[0])?;
+    assert_eq!(config.codec_string(false), "dvh1.08.06");
+    assert_eq!(config.dv_bl_signal_compatibility_id, 1);
+
+    let cluster_timestamps: Vec<u64> = find(0xE7)
+        .iter()
+        .map(|ts| ts.iter().fold(0, |v, b| (v << 8) | *b as u64))
+        .collect();
+    assert_eq!(cluster_timestamps, vec![0, 125]);
+
+    let blocks = find(0xA3);
+    assert_eq!(blocks.len(), 4);
+
+    let timestamps: Vec<i16> = blocks
+        .iter()
+        .map(|b| i16::from_be_bytes([b[1], b[2]]))
+        .collect();
+    assert_eq!(timestamps, vec![0, 83, 42, 0]);
+
+    let keyframes: Vec<bool> = blocks.iter().map(|b| b[3] & 0x80 != 0).collect();
+    assert_eq!(keyframes, vec![true, false, false, true]);
+
+    // Parameter sets are only in the hvcC, the slice and RPU are length prefixed
+    let first_block = &blocks[0][4..];
+    assert_eq!(first_block[4] >> 1, 19);
+    assert_eq!(
+        &first_block[first_block.len() - rpu_nal.len()..],
+        rpu_nal.as_slice()
+    );
+
+    assert_eq!(find(0xBB).len(), 2);
+
+    Ok(())
+}
+
+/// Flattens the MP4 boxes of `data`, descending into the containers and sample entries
+fn _mp4_boxes(data: &[u8], boxes: &mut Vec<([u8; 4], Vec<u8>)>) {
+    let mut pos = 0;
+
+    while pos + 8 <= data.len() {
+        let mut size =
+            u32::from_be_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]]) as usize;
+        let box_type = [data[pos + 4], data[pos + 5], data[pos + 6], data[pos + 7]];
+        let mut header = 8;
+
+        if size == 1 {
+            let mut largesize = [0; 8];
+            largesize.copy_from_slice(&data[pos + 8..pos + 16]);
+
+            size = u64::from_be_bytes(largesize) as usize;
+            header = 16;
+        }
+
+        let payload = &data[pos + header..pos + size];
+        boxes.push((box_type, payload.to_vec()));
+
+        match &box_type {
+            b"moov" | b"trak" | b"edts" | b"mdia" | b"minf" | b"stbl" | b"dinf" => {
+                _mp4_boxes(payload, boxes)
+            }
+            b"stsd" => _mp4_boxes(&payload[8..], boxes),
+            b"hvc1" | b"hev1" | b"dvh1" | b"dvhe" => _mp4_boxes(&payload[78..], boxes),
+            _ => (),
+        }
+
+        pos += size;
+    }
+}
+
+#[test]
+fn convert_to_mp4() -> Result<()> {
+    use super::converter::Converter;
+    use super::CliOptions;
+    use dolby_vision::rpu::configuration_record::DoviDecoderConfigurationRecord;
+
+    let (_, rpu) = _parse_file(PathBuf::from("./assets/tests/profile8.bin"))?;
+    let rpu_nal = rpu.write_hevc_unspec62_nalu()?;
+
+    let input = _write_hevc("dovi_tool_mp4_test.hevc", &_reordered_hevc_nals(&rpu_nal))?;
+    let output = std::env::temp_dir().join("dovi_tool_mp4_test_out.mp4");
+
+    // Frame rate from the SPS timing info
+    let options = CliOptions {
+        mode: None,
+        crop: false,
+        discard_el: false,
+        drop_hdr10plus: false,
+        hdr10_sei: false,
+        fps: None,
+    };
+
+    Converter::convert(Some(input.clone()), None, Some(output.clone()), options)?;
+
+    let data = std::fs::read(&output)?;
+    std::fs::remove_file(&input)?;
+    std::fs::remove_file(&output)?;
+
+    let mut boxes = Vec::new();
+    _mp4_boxes(&data, &mut boxes);
+
+    let find = |box_type: &[u8; 4]| -> Vec<&Vec<u8>> {
+        boxes
+            .iter()
+            .filter(|(t, _)| t == box_type)
+            .map(|(_, payload)| payload)
+            .collect()
+    };
+
+    let u32_at = |data: &[u8], pos: usize| {
+        u32::from_be_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]])
+    };
+
+    assert!(find(b"ftyp")[0].ends_with(b"dby1"));
+
+    // moov before mdat for progressive playback
+    let top_level: Vec<&[u8; 4]> = boxes
+        .iter()
+        .map(|(t, _)| t)
+        .filter(|t| [b"ftyp", b"moov", b"mdat"].contains(t))
+        .collect();
+    assert_eq!(top_level, vec![b"ftyp", b"moov", b"mdat"]);
+
+    // Profile 8.1 is signalled as hvc1 with dvvC
+    assert_eq!(find(b"hvc1").len(), 1);
+    assert_eq!(find(b"hvcC")[0][1], 2);
+
+    // Complete VPS array, the parameter sets are only in the hvcC
+    assert_eq!(find(b"hvcC")[0][23], 0x80 | 32);
+
+    let config = DoviDecoderConfigurationRecord::parse(find(b"dvvC")[0])?;
+    assert_eq!(config.codec_string(false), "dvh1.08.06");
+
+    let colr = find(b"colr")[0];
+    assert_eq!(&colr[..10], &[b'n', b'c', b'l', b'x', 0, 9, 0, 16, 0, 9]);
+
+    // 24000/1001 timescale and sample duration
+    let mdhd = find(b"mdhd")[0];
+    assert_eq!(u32_at(mdhd, 20), 24000);
+
+    let stts = find(b"stts")[0];
+    assert_eq!((u32_at(stts, 8), u32_at(stts, 12)), (4, 1001));
+
+    // Sync samples from the IDR frames
+    let stss = find(b"stss")[0];
+    assert_eq!((u32_at(stss, 8), u32_at(stss, 12)), (1, 4));
+
+    // IDR, P (POC 2), B (POC 1), IDR with a 1 frame delay
+    let ctts = find(b"ctts")[0];
+    let offsets: Vec<(u32, u32)> = (0..u32_at(ctts, 4) as usize)
+        .map(|i| (u32_at(ctts, 8 + i * 8), u32_at(ctts, 12 + i * 8)))
+        .collect();
+    assert_eq!(offsets, vec![(1, 1001), (1, 2002), (1, 0), (1, 1001)]);
+
+    let elst = find(b"elst")[0];
+    assert_eq!(&elst[16..24], &1001_u64.to_be_bytes());
+
+    // Single chunk, samples are length prefixed without the parameter sets
+    let stsz = find(b"stsz")[0];
+    assert_eq!(u32_at(stsz, 8), 4);
+
+    let co64 = find(b"co64")[0];
+    let mut offset = [0; 8];
+    offset.copy_from_slice(&co64[8..16]);
+    let offset = u64::from_be_bytes(offset) as usize;
+
+    let first_sample = &data[offset..offset + u32_at(stsz, 12) as usize];
+    assert_eq!(first_sample[4] >> 1, 19);
+    assert!(first_sample.ends_with(&rpu_nal));
+
+    Ok(())
+}
+
+#[test]
+fn split_and_concat_rpus() -> Result<()> {
+    use super::rpu_splitter::{RpuSplitter, SplitPoints};
+    use super::{parse_rpu_file, write_rpu_file};
+    use dolby_vision::rpu::generate::VideoShot;
+
+    let shot = |start| VideoShot {
+        start,
+        duration: 4,
+        ..Default::default()
+    };
+
+    let config = GenerateConfig {
+        length: 16,
+        shots: vec![shot(0), shot(4), shot(8), shot(12)],
+        ..Default::default()
+    };
+
+    let mut generated = config.generate_rpu_list()?;
+    let dir = std::env::temp_dir().join("dovi_tool_split_test");
+    std::fs::create_dir_all(&dir)?;
+
+    let input = dir.join("RPU.bin");
+    write_rpu_file(&input, GenerateConfig::encode_rpus(&mut generated))?;
+
+    let scene_cuts = |rpus: &[DoviRpu]| -> Vec<usize> {
+        rpus.iter()
+            .enumerate()
+            .filter(|(_, rpu)| rpu.vdr_dm_data.as_ref().unwrap().scene_refresh_flag == 1)
+            .map(|(i, _)| i)
+            .collect()
+    };
+
+    RpuSplitter::split(
+        input.clone(),
+        SplitPoints::SceneCuts(2),
+        Some(dir.clone()),
+        false,
+    )?;
+
+    let chunks = vec![dir.join("RPU_000.bin"), dir.join("RPU_001.bin")];
+    for chunk in &chunks {
+        let rpus = parse_rpu_file(chunk)?.unwrap();
+        assert_eq!(rpus.len(), 8);
+        assert_eq!(scene_cuts(&rpus), vec![0, 4]);
+    }
+
+    let output = dir.join("RPU_concat.bin");
+    RpuSplitter::concat(chunks, Some(output.clone()))?;
+    let rpus = parse_rpu_file(&output)?.unwrap();
+    assert_eq!(rpus.len(), 16);
+    assert_eq!(scene_cuts(&rpus), vec![0, 4, 8, 12]);
+
+    // Not on a scene cut
+    assert!(RpuSplitter::split(
+        input.clone(),
+        SplitPoints::Frames(vec![6]),
+        Some(dir.clone()),
+        false
+    )
+    .is_err());
+
+    RpuSplitter::split(input, SplitPoints::Frames(vec![6]), Some(dir.clone()), true)?;
+    let rpus = parse_rpu_file(&dir.join("RPU_001.bin"))?.unwrap();
+    assert_eq!(rpus.len(), 10);
+    assert_eq!(scene_cuts(&rpus), vec![0, 2, 6]);
+
+    std::fs::remove_dir_all(&dir)?;
+
+    Ok(())
+}
+
+#[test]
+fn sync_rpu_offsets() -> Result<()> {
+    use super::editor::Editor;
+    use super::sync::{RpuSync, SyncReference};
+    use super::{parse_rpu_file, write_rpu_file};
+    use dolby_vision::rpu::extension_metadata::blocks::*;
+    use dolby_vision::rpu::generate::VideoShot;
+
+    // Shots of varying length and brightness
+    let generate = |seed: u64, length: usize| -> Result<Vec<DoviRpu>> {
+        let mut state = seed;
+        let mut next = |max: u64| {
+            state = state
+                .wrapping_mul(6_364_136_223_846_793_005)
+                .wrapping_add(1_442_695_040_888_963_407);
+            (state >> 33) % max
+        };
+
+        let mut shots = Vec::new();
+        let mut start = 0;
+
+        while start < length {
+            let duration = (8 + next(32) as usize).min(length - start);
+            let max_pq = 1000 + next(2500) as u16;
+
+            shots.push(VideoShot {
+                start,
+                duration,
+                metadata_blocks: vec![ExtMetadataBlock::Level1(
+                    ExtMetadataBlockLevel1::from_stats(0, max_pq, max_pq / 2 + next(300) as u16),
+                )],
+                ..Default::default()
+            });
+
+            start += duration;
+        }
+
+        GenerateConfig {
+            length,
+            shots,
+            ..Default::default()
+        }
+        .generate_rpu_list()
+    };
+
+    let src = generate(1, 2000)?;
+    let inserted = generate(2, 90)?;
+
+    // Extra intro and scene, and a cut scene in the reference
+    let mut reference = inserted[..30].to_vec();
+    reference.extend_from_slice(&src[..700]);
+    reference.extend_from_slice(&inserted[30..]);
+    reference.extend_from_slice(&src[700..1300]);
+    reference.extend_from_slice(&src[1420..]);
+
+    let dir = std::env::temp_dir();
+    let src_path = dir.join("dovi_tool_sync_src.bin");
+    let ref_path = dir.join("dovi_tool_sync_ref.bin");
+    let json_path = dir.join("dovi_tool_sync_edits.json");
+    let out_path = dir.join("dovi_tool_sync_out.bin");
+
+    write_rpu_file(&src_path, GenerateConfig::encode_rpus(&mut src.clone()))?;
+    write_rpu_file(
+        &ref_path,
+        GenerateConfig::encode_rpus(&mut reference.clone()),
+    )?;
+
+    RpuSync::sync(
+        src_path.clone(),
+        SyncReference::Rpu(ref_path),
+        Some(json_path.clone()),
+        2400,
+    )?;
+
+    let edits: serde_json::Value = serde_json::from_reader(File::open(&json_path)?)?;
+    assert_eq!(edits["remove"], serde_json::json!(["1300-1419"]));
+    assert_eq!(
+        edits["duplicate"],
+        serde_json::json!([
+            { "source": 0, "offset": 0, "length": 30 },
+            { "source": 699, "offset": 700, "length": 60 },
+        ])
+    );
+
+    Editor::edit(src_path, json_path, Some(out_path.clone()))?;
+
+    let l1 = |rpu: &DoviRpu| match rpu.vdr_dm_data.as_ref().unwrap().get_block(1) {
+        Some(ExtMetadataBlock::Level1(b)) => (b.max_pq, b.avg_pq),
+        _ => (0, 0),
+    };
+
+    let synced = parse_rpu_file(&out_path)?.unwrap();
+    assert_eq!(synced.len(), reference.len());
+
+    for (i, (a, b)) in synced.iter().zip(reference.iter()).enumerate() {
+        if (30..730).contains(&i) || i >= 790 {
+            assert_eq!(l1(a), l1(b), "frame {}", i);
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn editor_frame_rate_conversion() -> Result<()> {
+    use super::editor::EditConfig;
+    use dolby_vision::rpu::generate::VideoShot;
+
+    let config = GenerateConfig {
+        length: 10,
+        shots: vec![
+            VideoShot {
+                start: 0,
+                duration: 4,
+                ..Default::default()
+            },
+            VideoShot {
+                start: 4,
+                duration: 6,
+                ..Default::default()
+            },
+        ],
+        ..Default::default()
+    };
+
+    let generated: Vec<Option<DoviRpu>> =
+        config.generate_rpu_list()?.into_iter().map(Some).collect();
+
+    let convert = |json: &str| -> Result<(usize, Vec<usize>)> {
+        let edit_config: EditConfig = serde_json::from_str(json)?;
+        let rpus = edit_config.convert_frame_rate(generated.clone())?;
+
+        let scene_cuts = rpus
+            .iter()
+            .enumerate()
+            .filter(|(_, rpu)| {
+                rpu.as_ref()
+                    .and_then(|rpu| rpu.vdr_dm_data.as_ref())
+                    .map_or(false, |vdr_dm_data| vdr_dm_data.scene_refresh_flag == 1)
+            })
+            .map(|(i, _)| i)
+            .collect();
+
+        Ok((rpus.len(), scene_cuts))
+    };
+
+    // 2:3 pulldown
+    assert_eq!(
+        convert(r#"{ "frame_rate_conversion": { "cadence": [2, 3] } }"#)?,
+        (25, vec![0, 10])
+    );
+
+    assert_eq!(
+        convert(
+            r#"{ "frame_rate_conversion": { "source_fps": "24000/1001", "target_fps": "59.94" } }"#
+        )?,
+        (25, vec![0, 10])
+    );
+
+    // Doubling
+    assert_eq!(
+        convert(r#"{ "frame_rate_conversion": { "cadence": [2] } }"#)?,
+        (20, vec![0, 8])
+    );
+
+    // Decimation drops the scene cut frame 4, the cut moves to the next frame
+    assert_eq!(
+        convert(
+            r#"{ "frame_rate_conversion": { "source_fps": "29.97", "target_fps": "23.976" } }"#
+        )?,
+        (8, vec![0, 4])
+    );
+
+    assert!(convert(r#"{ "frame_rate_conversion": { "source_fps": "29.97" } }"#).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn editor_merge_rpu() -> Result<()> {
+    use super::editor::EditConfig;
+    use super::{parse_rpu_file, write_rpu_file};
+    use dolby_vision::rpu::extension_metadata::blocks::*;
+    use dolby_vision::rpu::generate::VideoShot;
+    use dolby_vision::rpu::vdr_dm_data::CmVersion;
+
+    let (_, fel_rpu) = _parse_file(PathBuf::from("./assets/tests/fel_orig.bin"))?;
+
+    // BT.2020 source, the CM v2.9 profile 7 RPU has no L9
+    let config = GenerateConfig {
+        cm_version: CmVersion::V40,
+        length: 10,
+        level9: Some(ExtMetadataBlockLevel9 {
+            source_primary_index: 2,
+        }),
+        shots: vec![VideoShot {
+            start: 0,
+            duration: 10,
+            metadata_blocks: vec![
+                ExtMetadataBlock::Level1(ExtMetadataBlockLevel1::from_stats(0, 2081, 819)),
+                ExtMetadataBlock::Level8(ExtMetadataBlockLevel8 {
+                    target_display_index: 1,
+                    trim_slope: 2100,
+                    ..Default::default()
+                }),
+                ExtMetadataBlock::Level11(ExtMetadataBlockLevel11::default_reference_cinema()),
+            ],
+            ..Default::default()
+        }],
+        ..Default::default()
+    };
+
+    let dir = std::env::temp_dir();
+    let base_path = dir.join("dovi_tool_merge_base.bin");
+    let other_path = dir.join("dovi_tool_merge_other.bin");
+    let short_path = dir.join("dovi_tool_merge_short.bin");
+
+    let mut base = vec![fel_rpu; 10];
+    write_rpu_file(&base_path, GenerateConfig::encode_rpus(&mut base))?;
+
+    let mut generated = config.generate_rpu_list()?;
+    write_rpu_file(&other_path, GenerateConfig::encode_rpus(&mut generated))?;
+    write_rpu_file(
+        &short_path,
+        GenerateConfig::encode_rpus(&mut generated[..9]),
+    )?;
+
+    let merge = |base_path: &std::path::Path, merge: serde_json::Value| -> Result<Vec<DoviRpu>> {
+        let edit_config: EditConfig = serde_json::from_value(serde_json::json!({
+            "merge": [merge]
+        }))?;
+
+        let mut rpus: Vec<Option<DoviRpu>> = parse_rpu_file(base_path)?
+            .unwrap()
+            .into_iter()
+            .map(Some)
+            .collect();
+        edit_config.execute(&mut rpus)?;
+
+        // Re-encoded and parsed back
+        let mut data = GenerateConfig::encode_option_rpus(&mut rpus);
+        assert_eq!(data.len(), 10);
+
+        data.iter_mut()
+            .map(|rpu| DoviRpu::parse_unspec62_nalu(rpu))
+            .collect()
+    };
+
+    // CM v4.0 trims of the generated RPU in the profile 7 RPU
+    let merged = merge(
+        &base_path,
+        serde_json::json!({
+            "rpu": other_path,
+            "ranges": ["0-4"],
+            "levels": [1, 8, 11]
+        }),
+    )?;
+
+    for (i, rpu) in merged.iter().enumerate() {
+        assert_eq!(rpu.dovi_profile, 7);
+        assert!(rpu.rpu_data_nlq.is_some());
+
+        let vdr_dm_data = rpu.vdr_dm_data.as_ref().unwrap();
+
+        if i < 5 {
+            match vdr_dm_data.get_block(1) {
+                Some(ExtMetadataBlock::Level1(l1)) => assert_eq!(l1.max_pq, 2081),
+                _ => panic!("missing L1"),
+            }
+
+            match vdr_dm_data.get_block(8) {
+                Some(ExtMetadataBlock::Level8(l8)) => assert_eq!(l8.trim_slope, 2100),
+                _ => panic!("missing L8 trim"),
+            }
+
+            assert!(vdr_dm_data.get_block(11).is_some());
+        }
+
+        // Every frame is converted to CM v4.0, with the source primaries of the merged RPU
+        assert!(vdr_dm_data.cmv40_metadata.is_some());
+
+        match vdr_dm_data.get_block(9) {
+            Some(ExtMetadataBlock::Level9(l9)) => assert_eq!(l9.source_primary_index, 2),
+            _ => panic!("missing L9"),
+        }
+    }
+
+    // Profile 7 mapping with the generated DM metadata
+    let merged = merge(
+        &other_path,
+        serde_json::json!({ "rpu": base_path, "mapping": true }),
+    )?;
+
+    for rpu in &merged {
+        assert_eq!(rpu.dovi_profile, 7);
+        assert!(rpu.rpu_data_nlq.is_some());
+        assert!(rpu.vdr_dm_data.as_ref().unwrap().get_block(11).is_some());
+    }
+
+    // Frame counts must match
+    assert!(merge(
+        &base_path,
+        serde_json::json!({ "rpu": short_path, "vdr_dm_data": true }),
+    )
+    .is_err());
+
+    Ok(())
+}
+
+#[test]
+fn export_csv() -> Result<()> {
+    use super::exporter::Exporter;
+    use super::write_rpu_file;
+    use dolby_vision::rpu::extension_metadata::blocks::*;
+    use dolby_vision::rpu::generate::VideoShot;
+    use dolby_vision::rpu::vdr_dm_data::CmVersion;
+
+    let config = GenerateConfig {
+        cm_version: CmVersion::V40,
+        length: 4,
+        shots: vec![
+            VideoShot {
+                start: 0,
+                duration: 2,
+                metadata_blocks: vec![
+                    ExtMetadataBlock::Level1(ExtMetadataBlockLevel1::from_stats(0, 2081, 819)),
+                    ExtMetadataBlock::Level2(ExtMetadataBlockLevel2::from_nits(600)),
+                    ExtMetadataBlock::Level8(ExtMetadataBlockLevel8 {
+                        target_display_index: 1,
+                        trim_slope: 2100,
+                        ..Default::default()
+                    }),
+                ],
+                ..Default::default()
+            },
+            VideoShot {
+                start: 2,
+                duration: 2,
+                ..Default::default()
+            },
+        ],
+        ..Default::default()
+    };
+
+    let dir = std::env::temp_dir();
+    let input = dir.join("dovi_tool_export_csv.bin");
+    let output = dir.join("dovi_tool_export.csv");
+
+    write_rpu_file(
+        &input,
+        GenerateConfig::encode_rpus(&mut config.generate_rpu_list()?),
+    )?;
+
+    Exporter::export(input.clone(), Some(output.clone()), None)?;
+
+    let csv = std::fs::read_to_string(&output)?;
+    let rows: Vec<Vec<&str>> = csv.lines().map(|l| l.split(',').collect()).collect();
+    assert_eq!(rows.len(), 5);
+    assert!(rows.iter().all(|row| row.len() == rows[0].len()));
+
+    let cell = |row: usize, column: &str| {
+        let index = rows[0].iter().position(|c| *c == column).unwrap();
+        rows[row][index]
+    };
+
+    assert_eq!(cell(1, "scene_cut"), "1");
+    assert_eq!(cell(2, "scene_cut"), "0");
+    assert_eq!(cell(1, "cm_version"), "4.0");
+    assert_eq!(cell(1, "l1_max_pq"), "2081");
+    assert_eq!(cell(1, "l1_max_nits"), "100.1020");
+    assert_eq!(cell(1, "l2_600_slope"), "2048");
+    assert_eq!(cell(1, "l8_1_slope"), "2100");
+    assert_eq!(cell(3, "l8_1_slope"), "");
+
+    // Selected columns, as TSV
+    let output = dir.join("dovi_tool_export.tsv");
+    Exporter::export(
+        input.clone(),
+        Some(output.clone()),
+        Some(vec!["frame".into(), "l1".into()]),
+    )?;
+
+    let tsv = std::fs::read_to_string(&output)?;
+    assert_eq!(
+        tsv.lines().next(),
+        Some("frame\tl1_min_pq\tl1_avg_pq\tl1_max_pq\tl1_min_nits\tl1_avg_nits\tl1_max_nits")
+    );
+
+    assert!(Exporter::export(input, Some(output), Some(vec!["l7".into()])).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn convert_to_hdr10plus() -> Result<()> {
+    use super::hdr10plus::Hdr10PlusConverter;
+    use super::{is_st2094_40_sei, write_rpu_file};
+    use dolby_vision::rpu::extension_metadata::blocks::*;
+    use dolby_vision::rpu::generate::VideoShot;
+
+    let config = GenerateConfig {
+        length: 5,
+        shots: vec![
+            VideoShot {
+                start: 0,
+                duration: 3,
+                metadata_blocks: vec![ExtMetadataBlock::Level1(
+                    ExtMetadataBlockLevel1::from_stats(0, 3079, 1228),
+                )],
+                ..Default::default()
+            },
+            VideoShot {
+                start: 3,
+                duration: 2,
+                metadata_blocks: vec![ExtMetadataBlock::Level1(
+                    ExtMetadataBlockLevel1::from_stats(0, 2081, 819),
+                )],
+                ..Default::default()
+            },
+        ],
+        ..Default::default()
+    };
+
+    let dir = std::env::temp_dir();
+    let input = dir.join("dovi_tool_to_hdr10plus.bin");
+
+    write_rpu_file(
+        &input,
+        GenerateConfig::encode_rpus(&mut config.generate_rpu_list()?),
+    )?;
+
+    let json_out = dir.join("dovi_tool_hdr10plus.json");
+    Hdr10PlusConverter::convert(input.clone(), Some(json_out.clone()), 400)?;
+
+    let json: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&json_out)?)?;
+    assert_eq!(json["JSONInfo"]["HDR10plusProfile"], "B");
+    assert_eq!(
+        json["SceneInfoSummary"]["SceneFirstFrameIndex"],
+        serde_json::json!([0, 3])
+    );
+    assert_eq!(
+        json["SceneInfoSummary"]["SceneFrameNumbers"],
+        serde_json::json!([3, 2])
+    );
+
+    let scene_info = json["SceneInfo"].as_array().unwrap();
+    assert_eq!(scene_info.len(), 5);
+
+    // ~1000 nits peak, tone mapped to 400 nits
+    let max_scl = scene_info[0]["LuminanceParameters"]["MaxScl"][0]
+        .as_u64()
+        .unwrap();
+    assert!((9900..=10100).contains(&max_scl));
+    assert!(scene_info[0]["BezierCurveData"].is_object());
+    assert_eq!(scene_info[4]["SceneFrameIndex"], 1);
+
+    // ~100 nits peak, no tone mapping needed
+    assert!(scene_info[3]["BezierCurveData"].is_null());
+
+    let sei_out = dir.join("dovi_tool_hdr10plus.hevc");
+    Hdr10PlusConverter::convert(input.clone(), Some(sei_out.clone()), 400)?;
+
+    let nals = _read_hevc_nals(&sei_out)?;
+    assert_eq!(nals.len(), 5);
+    for nal in &nals {
+        assert!(is_st2094_40_sei(nal)?);
+    }
+
+    assert!(Hdr10PlusConverter::convert(input, Some(json_out), 50).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn convert_to_madvr() -> Result<()> {
+    use super::madvr::MadVRConverter;
+    use super::write_rpu_file;
+    use dolby_vision::rpu::extension_metadata::blocks::*;
+    use dolby_vision::rpu::generate::VideoShot;
+    use madvr_parse::MadVRMeasurements;
+
+    let config = GenerateConfig {
+        length: 5,
+        shots: vec![
+            VideoShot {
+                start: 0,
+                duration: 3,
+                metadata_blocks: vec![
+                    ExtMetadataBlock::Level1(ExtMetadataBlockLevel1::from_stats(0, 3079, 1228)),
+                    ExtMetadataBlock::Level2(ExtMetadataBlockLevel2 {
+                        trim_slope: 2548,
+                        ..ExtMetadataBlockLevel2::from_nits(400)
+                    }),
+                ],
+                ..Default::default()
+            },
+            VideoShot {
+                start: 3,
+                duration: 2,
+                metadata_blocks: vec![ExtMetadataBlock::Level1(
+                    ExtMetadataBlockLevel1::from_stats(0, 2081, 819),
+                )],
+                ..Default::default()
+            },
+        ],
+        ..Default::default()
+    };
+
+    let dir = std::env::temp_dir();
+    let input = dir.join("dovi_tool_to_madvr.bin");
+    let output = dir.join("dovi_tool_to_madvr.measurements");
+
+    write_rpu_file(
+        &input,
+        GenerateConfig::encode_rpus(&mut config.generate_rpu_list()?),
+    )?;
+
+    MadVRConverter::convert(input, Some(output.clone()), 400)?;
+
+    let madvr_info = MadVRMeasurements::parse_file(&output)?;
+    assert_eq!(madvr_info.header.flags, 3);
+    assert_eq!(madvr_info.header.target_peak_nits, 400);
+    assert_eq!(madvr_info.header.maxcll, 1001);
+    assert_eq!(madvr_info.frames.len(), 5);
+
+    let scenes: Vec<(u32, u32)> = madvr_info.scenes.iter().map(|s| (s.start, s.end)).collect();
+    assert_eq!(scenes, vec![(0, 2), (3, 4)]);
+    assert_eq!(madvr_info.scenes[1].peak_nits, 100);
+
+    // Synthetic histograms keep the L1 average
+    for (frame, avg_pq) in madvr_info
+        .frames
+        .iter()
+        .zip([1228, 1228, 1228, 819, 819].iter())
+    {
+        assert!((frame.avg_pq * 4095.0 - *avg_pq as f64).abs() < 2.0);
+    }
+
+    // No L9, DCI-P3 source without pixels outside of it
+    let first_frame = &madvr_info.frames[0];
+    assert_eq!(first_frame.peak_pq_dcip3, Some(0.0));
+    assert_eq!(first_frame.peak_pq_709, Some(first_frame.peak_pq_2020));
+
+    // Brightening trim lowers the target
+    let first_target = madvr_info.frames[0].target_nits.unwrap();
+    assert!(first_target > 400 && first_target < 1000);
+    assert_eq!(madvr_info.frames[3].target_nits, Some(100));
+
+    Ok(())
+}
+
+#[test]
+fn generate_from_madvr_frames() -> Result<()> {
+    use super::generator::{generate_metadata_from_madvr, MadVRMode, MadVROptions};
+    use super::madvr::MadVRConverter;
+    use super::write_rpu_file;
+    use dolby_vision::rpu::extension_metadata::blocks::*;
+    use dolby_vision::rpu::generate::{ShotFrameEdit, VideoShot};
+
+    let frame_l1 = |max_pq: u16, avg_pq: u16| {
+        vec![ExtMetadataBlock::Level1(
+            ExtMetadataBlockLevel1::from_stats(0, max_pq, avg_pq),
+        )]
+    };
+
+    let config = GenerateConfig {
+        length: 4,
+        level9: Some(ExtMetadataBlockLevel9 {
+            source_primary_index: 2,
+        }),
+        shots: vec![VideoShot {
+            start: 0,
+            duration: 4,
+            metadata_blocks: frame_l1(3079, 1228),
+            frame_edits: vec![
+                ShotFrameEdit {
+                    edit_offset: 1,
+                    metadata_blocks: frame_l1(2800, 1500),
+                    ..Default::default()
+                },
+                ShotFrameEdit {
+                    edit_offset: 3,
+                    metadata_blocks: frame_l1(2500, 900),
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        }],
+        ..Default::default()
+    };
+
+    let dir = std::env::temp_dir();
+    let input = dir.join("dovi_tool_madvr_frames.bin");
+    let madvr_path = dir.join("dovi_tool_madvr_frames.measurements");
+
+    write_rpu_file(
+        &input,
+        GenerateConfig::encode_rpus(&mut config.generate_rpu_list()?),
+    )?;
+    MadVRConverter::convert(input, Some(madvr_path.clone()), 400)?;
+
+    let opts = MadVROptions {
+        mode: MadVRMode::Frame,
+        use_custom_targets: false,
+        level9_from_gamut: true,
+    };
+
+    let mut generated = GenerateConfig::default();
+    generate_metadata_from_madvr(&madvr_path, &opts, &mut generated)?;
+
+    assert_eq!(generated.length, 4);
+    assert_eq!(generated.level9.as_ref().unwrap().source_primary_index, 2);
+
+    let shot = &generated.shots[0];
+    assert_eq!(shot.frame_edits.len(), 4);
+
+    let expected: [(u16, u16); 4] = [(3079, 1228), (2800, 1500), (3079, 1228), (2500, 900)];
+
+    for (edit, (max_pq, avg_pq)) in shot.frame_edits.iter().zip(expected.iter()) {
+        if let ExtMetadataBlock::Level1(l1) = &edit.metadata_blocks[0] {
+            assert!((l1.max_pq as i32 - *max_pq as i32).abs() <= 1);
+            assert!((l1.avg_pq as i32 - *avg_pq as i32).abs() <= 2);
+            assert!(l1.min_pq <= l1.avg_pq);
+        } else {
+            panic!("No L1 for frame {}", edit.edit_offset);
+        }
+    }
+
+    // Scene mode keeps a single L1 per scene
+    let opts = MadVROptions {
+        mode: MadVRMode::Scene,
+        ..opts
+    };
+    generate_metadata_from_madvr(&madvr_path, &opts, &mut generated)?;
+    assert!(generated.shots[0].frame_edits.is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn parse_partial_and_newer_madvr_files() -> Result<()> {
+    use super::generator::{generate_metadata_from_madvr, MadVRMode, MadVROptions};
+    use super::madvr::MadVRConverter;
+    use super::write_rpu_file;
+    use dolby_vision::rpu::extension_metadata::blocks::*;
+    use dolby_vision::rpu::generate::VideoShot;
+    use madvr_parse::MadVRMeasurements;
+
+    let config = GenerateConfig {
+        length: 5,
+        shots: vec![
+            VideoShot {
+                start: 0,
+                duration: 3,
+                metadata_blocks: vec![ExtMetadataBlock::Level1(
+                    ExtMetadataBlockLevel1::from_stats(0, 3079, 1228),
+                )],
+                ..Default::default()
+            },
+            VideoShot {
+                start: 3,
+                duration: 2,
+                metadata_blocks: vec![ExtMetadataBlock::Level1(
+                    ExtMetadataBlockLevel1::from_stats(0, 2081, 819),
+                )],
+                ..Default::default()
+            },
+        ],
+        ..Default::default()
+    };
+
+    let dir = std::env::temp_dir();
+    let input = dir.join("dovi_tool_madvr_partial.bin");
+    let madvr_path = dir.join("dovi_tool_madvr_partial.measurements");
+
+    write_rpu_file(
+        &input,
+        GenerateConfig::encode_rpus(&mut config.generate_rpu_list()?),
+    )?;
+    MadVRConverter::convert(input, Some(madvr_path.clone()), 400)?;
+
+    let complete = std::fs::read(&madvr_path)?;
+
+    // Header of 40 bytes, 2 scenes of 12 bytes, then 580 bytes per frame
+    let frame_size = 580;
+    let frames_offset = 40 + 2 * 12;
+
+    // Interrupted during the 2nd scene, with its end and peak not written
+    let mut partial = complete[..frames_offset + 4 * frame_size].to_vec();
+    partial[20..24].copy_from_slice(&0_u32.to_le_bytes());
+    partial[52..56].copy_from_slice(&0_u32.to_le_bytes());
+    partial[60..64].copy_from_slice(&0_u32.to_le_bytes());
+
+    let madvr_info = MadVRMeasurements::parse_measurements(&partial)?;
+    assert!(!madvr_info.is_complete());
+    assert_eq!(madvr_info.frames.len(), 4);
+    assert_eq!(madvr_info.missing_frames(), 1);
+
+    let scenes: Vec<(u32, u32)> = madvr_info.scenes.iter().map(|s| (s.start, s.end)).collect();
+    assert_eq!(scenes, vec![(0, 2), (3, 3)]);
+    assert_eq!(madvr_info.scenes[1].peak_nits, 100);
+
+    std::fs::write(&madvr_path, &partial)?;
+
+    let opts = MadVROptions {
+        mode: MadVRMode::Scene,
+        use_custom_targets: false,
+        level9_from_gamut: false,
+    };
+
+    let mut generated = GenerateConfig::default();
+    generate_metadata_from_madvr(&madvr_path, &opts, &mut generated)?;
+    assert_eq!(generated.length, 4);
+    assert_eq!(generated.shots.len(), 2);
+
+    // Newer version, with an unknown header field
+    let mut newer = complete[..40].to_vec();
+    newer[4..8].copy_from_slice(&7_u32.to_le_bytes());
+    newer[8..12].copy_from_slice(&40_u32.to_le_bytes());
+    newer.extend(&[0xFF; 4]);
+    newer.extend(&complete[40..]);
+
+    let madvr_info = MadVRMeasurements::parse_measurements(&newer)?;
+    assert_eq!(madvr_info.header.version, 7);
+    assert_eq!(madvr_info.header.target_peak_nits, 400);
+    assert_eq!(madvr_info.frames.len(), 5);
+    assert_eq!(madvr_info.scenes.len(), 2);
+    assert_eq!(madvr_info.frames[4].target_nits, Some(100));
+
+    Ok(())
+}
+
+#[test]
+fn temporal_smoothing_and_level4() -> Result<()> {
+    use super::editor::EditConfig;
+    use dolby_vision::rpu::extension_metadata::blocks::*;
+    use dolby_vision::rpu::generate::{ShotFrameEdit, VideoShot};
+
+    let l1 = |max_pq: u16, avg_pq: u16| {
+        vec![ExtMetadataBlock::Level1(
+            ExtMetadataBlockLevel1::from_stats(0, max_pq, avg_pq),
+        )]
+    };
+
+    // Flickering max in the first scene, then a brighter scene
+    let config = GenerateConfig {
+        length: 8,
+        shots: vec![
+            VideoShot {
+                start: 0,
+                duration: 4,
+                metadata_blocks: l1(2200, 1000),
+                frame_edits: vec![ShotFrameEdit {
+                    edit_offset: 1,
+                    metadata_blocks: l1(2800, 1000),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            },
+            VideoShot {
+                start: 4,
+                duration: 4,
+                metadata_blocks: l1(3000, 2000),
+                ..Default::default()
+            },
+        ],
+        ..Default::default()
+    };
+
+    // L1 max and average, L4 anchor PQ and power
+    type FrameStats = (u16, u16, Option<(u16, u16)>);
+
+    let edit = |temporal: serde_json::Value| -> Result<Vec<FrameStats>> {
+        let edit_config: EditConfig = serde_json::from_value(serde_json::json!({
+            "temporal": temporal
+        }))?;
+
+        let mut rpus: Vec<Option<DoviRpu>> =
+            config.generate_rpu_list()?.into_iter().map(Some).collect();
+        edit_config.execute(&mut rpus)?;
+
+        let mut data = GenerateConfig::encode_option_rpus(&mut rpus);
+
+        data.iter_mut()
+            .map(|data| {
+                let rpu = DoviRpu::parse_unspec62_nalu(data)?;
+                let vdr_dm_data = rpu.vdr_dm_data.unwrap();
+
+                let (max_pq, avg_pq) = match vdr_dm_data.get_block(1) {
+                    Some(ExtMetadataBlock::Level1(b)) => (b.max_pq, b.avg_pq),
+                    _ => panic!("No L1"),
+                };
+                let level4 = match vdr_dm_data.get_block(4) {
+                    Some(ExtMetadataBlock::Level4(b)) => Some((b.anchor_pq, b.anchor_power)),
+                    _ => None,
+                };
+
+                Ok((max_pq, avg_pq, level4))
+            })
+            .collect()
+    };
+
+    // Scene aware smoothing: the flicker is spread without clipping the peak,
+    // the second scene is unchanged
+    let frames = edit(serde_json::json!({ "smooth_l1_window": 3 }))?;
+    let max_pq: Vec<u16> = frames.iter().map(|f| f.0).collect();
+    assert_eq!(max_pq, vec![2500, 2800, 2400, 2200, 3000, 3000, 3000, 3000]);
+    assert!(frames.iter().all(|f| f.2.is_none()));
+
+    // Across scenes, the cut is spread too
+    let frames = edit(serde_json::json!({
+        "smooth_l1_window": 3,
+        "smooth_across_scenes": true
+    }))?;
+    assert_eq!(frames[3].0, 2467);
+    assert_eq!(frames[4].0, 3000);
+    assert_eq!((frames[3].1, frames[4].1), (1333, 1667));
+
+    // L4 follows the average progressively over the cut
+    let frames = edit(serde_json::json!({ "level4_window": 4 }))?;
+    let level4: Vec<(u16, u16)> = frames.iter().map(|f| f.2.unwrap()).collect();
+
+    assert_eq!(level4[0], (1000, 0));
+    assert_eq!(level4[3], (1000, 0));
+    assert_eq!(level4[4], (1250, 375));
+    assert!(level4[5].0 > level4[4].0 && level4[7].0 < 2000);
+
+    Ok(())
+}
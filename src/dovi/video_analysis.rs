@@ -0,0 +1,351 @@
+use std::fs::File;
+use std::io::{stdin, BufRead, BufReader, Read};
+use std::path::Path;
+
+use anyhow::{bail, ensure, format_err, Result};
+use rayon::prelude::*;
+
+use dolby_vision::rpu::extension_metadata::blocks::{ExtMetadataBlock, ExtMetadataBlockLevel1};
+use dolby_vision::rpu::generate::{GenerateConfig, VideoShot};
+use dolby_vision::utils::pq_to_nits;
+
+const HISTOGRAM_BINS: usize = 64;
+
+/// Reads 10-bit 4:2:0 frames from a Y4M stream, or raw YUV420P10LE frames
+pub struct YuvReader {
+    reader: Box<dyn BufRead>,
+    y4m: bool,
+
+    // Linear value of every 12-bit PQ code, in nits
+    nits_lut: Vec<f64>,
+
+    pub width: usize,
+    pub height: usize,
+    pub full_range: bool,
+}
+
+pub struct YuvFrame {
+    pub width: usize,
+    pub height: usize,
+    pub y: Vec<u16>,
+    pub u: Vec<u16>,
+    pub v: Vec<u16>,
+}
+
+/// Statistics of a frame, in 12-bit PQ
+#[derive(Debug, Clone)]
+pub struct FrameMeasurement {
+    pub min_pq: u16,
+    pub max_pq: u16,
+    pub avg_pq: u16,
+
+    /// Average of the linear maxRGB values, in nits
+    pub avg_nits: f64,
+
+    histogram: Vec<u32>,
+}
+
+impl YuvReader {
+    /// Opens a Y4M file, or raw YUV420P10LE when the dimensions are specified.
+    /// The path `-` reads from stdin.
+    pub fn open(path: &Path, raw_dimensions: Option<(usize, usize)>) -> Result<Self> {
+        let reader: Box<dyn BufRead> = if path.to_str() == Some("-") {
+            Box::new(BufReader::with_capacity(100_000, stdin()))
+        } else {
+            Box::new(BufReader::with_capacity(100_000, File::open(path)?))
+        };
+
+        let mut yuv_reader = YuvReader {
+            reader,
+            y4m: raw_dimensions.is_none(),
+            nits_lut: (0..4096).map(|pq| pq_to_nits(pq as f64 / 4095.0)).collect(),
+            width: 0,
+            height: 0,
+            full_range: false,
+        };
+
+        if let Some((width, height)) = raw_dimensions {
+            ensure!(width > 0 && height > 0, "Invalid raw video dimensions");
+
+            yuv_reader.width = width;
+            yuv_reader.height = height;
+        } else {
+            yuv_reader.parse_y4m_header()?;
+        }
+
+        Ok(yuv_reader)
+    }
+
+    fn parse_y4m_header(&mut self) -> Result<()> {
+        let mut header = String::new();
+        self.reader.read_line(&mut header)?;
+
+        let mut params = header.split_whitespace();
+
+        ensure!(
+            params.next() == Some("YUV4MPEG2"),
+            "Invalid Y4M file, expected YUV4MPEG2 header"
+        );
+
+        let mut colorspace = "420";
+
+        for param in params {
+            let (key, value) = param.split_at(1);
+
+            match key {
+                "W" => self.width = value.parse()?,
+                "H" => self.height = value.parse()?,
+                "C" => colorspace = value,
+                "X" if value == "COLORRANGE=FULL" => self.full_range = true,
+                _ => (),
+            }
+        }
+
+        ensure!(
+            self.width > 0 && self.height > 0,
+            "Missing Y4M frame dimensions"
+        );
+
+        if !colorspace.starts_with("420p10") {
+            bail!(
+                "Unsupported Y4M colorspace {}, only 10-bit 4:2:0 is supported",
+                colorspace
+            );
+        }
+
+        Ok(())
+    }
+
+    pub fn read_frame(&mut self) -> Result<Option<YuvFrame>> {
+        if self.y4m {
+            let mut frame_header = String::new();
+
+            if self.reader.read_line(&mut frame_header)? == 0 {
+                return Ok(None);
+            }
+
+            ensure!(
+                frame_header.starts_with("FRAME"),
+                "Invalid Y4M frame header"
+            );
+        } else if self.reader.fill_buf()?.is_empty() {
+            return Ok(None);
+        }
+
+        let chroma_width = (self.width + 1) / 2;
+        let chroma_height = (self.height + 1) / 2;
+
+        let y = self.read_plane(self.width * self.height)?;
+        let u = self.read_plane(chroma_width * chroma_height)?;
+        let v = self.read_plane(chroma_width * chroma_height)?;
+
+        Ok(Some(YuvFrame {
+            width: self.width,
+            height: self.height,
+            y,
+            u,
+            v,
+        }))
+    }
+
+    /// Measures a frame read from this video
+    pub fn measure(&self, frame: &YuvFrame) -> FrameMeasurement {
+        frame.measure(self.full_range, &self.nits_lut)
+    }
+
+    fn read_plane(&mut self, samples: usize) -> Result<Vec<u16>> {
+        let mut buf = vec![0; samples * 2];
+        self.reader
+            .read_exact(&mut buf)
+            .map_err(|_| format_err!("Unexpected end of video stream"))?;
+
+        Ok(buf
+            .chunks_exact(2)
+            .map(|b| u16::from_le_bytes([b[0], b[1]]))
+            .collect())
+    }
+}
+
+impl YuvFrame {
+    /// Measures the min/max/avg of maxRGB, in the PQ domain.
+    /// The frame is expected to be BT.2020 non-constant luminance YCbCr.
+    fn measure(&self, full_range: bool, nits_lut: &[f64]) -> FrameMeasurement {
+        let chroma_width = (self.width + 1) / 2;
+
+        let (y_offset, y_range, c_range) = if full_range {
+            (0.0, 1023.0, 1023.0)
+        } else {
+            (64.0, 876.0, 896.0)
+        };
+
+        let rows: Vec<(u16, u16, u64, f64, Vec<u32>)> = (0..self.height)
+            .into_par_iter()
+            .map(|row| {
+                let mut min = u16::MAX;
+                let mut max = 0;
+                let mut sum = 0;
+                let mut sum_nits = 0.0;
+                let mut histogram = vec![0; HISTOGRAM_BINS];
+
+                let luma = &self.y[row * self.width..(row + 1) * self.width];
+                let chroma_row = (row / 2) * chroma_width;
+
+                for (x, luma_sample) in luma.iter().enumerate() {
+                    let y = (*luma_sample as f64 - y_offset) / y_range;
+                    let cb = (self.u[chroma_row + x / 2] as f64 - 512.0) / c_range;
+                    let cr = (self.v[chroma_row + x / 2] as f64 - 512.0) / c_range;
+
+                    let r = y + 1.4746 * cr;
+                    let g = y - 0.16455 * cb - 0.57135 * cr;
+                    let b = y + 1.8814 * cb;
+
+                    let max_rgb = r.max(g).max(b).clamp(0.0, 1.0);
+                    let pq = (max_rgb * 4095.0).round() as u16;
+
+                    min = min.min(pq);
+                    max = max.max(pq);
+                    sum += pq as u64;
+                    sum_nits += nits_lut[pq as usize];
+
+                    histogram[(*luma_sample as usize >> 4).min(HISTOGRAM_BINS - 1)] += 1;
+                }
+
+                (min, max, sum, sum_nits, histogram)
+            })
+            .collect();
+
+        let pixels = (self.width * self.height) as f64;
+
+        let mut histogram = vec![0; HISTOGRAM_BINS];
+        rows.iter().for_each(|(_, _, _, _, h)| {
+            histogram.iter_mut().zip(h).for_each(|(a, b)| *a += b);
+        });
+
+        FrameMeasurement {
+            min_pq: rows.iter().map(|r| r.0).min().unwrap_or(0),
+            max_pq: rows.iter().map(|r| r.1).max().unwrap_or(0),
+            avg_pq: (rows.iter().map(|r| r.2).sum::<u64>() as f64 / pixels).round() as u16,
+            avg_nits: rows.iter().map(|r| r.3).sum::<f64>() / pixels,
+            histogram,
+        }
+    }
+}
+
+impl FrameMeasurement {
+    /// Normalized difference between the luma histograms of two frames, from 0 to 1
+    pub fn histogram_difference(&self, other: &Self) -> f64 {
+        let total: u32 = self.histogram.iter().sum();
+
+        if total == 0 {
+            return 0.0;
+        }
+
+        let diff: u32 = self
+            .histogram
+            .iter()
+            .zip(&other.histogram)
+            .map(|(a, b)| if a > b { a - b } else { b - a })
+            .sum();
+
+        diff as f64 / (2.0 * total as f64)
+    }
+}
+
+/// Measures every frame of the video
+pub fn measure_video(reader: &mut YuvReader) -> Result<Vec<FrameMeasurement>> {
+    let mut measurements = Vec::new();
+
+    while let Some(frame) = reader.read_frame()? {
+        measurements.push(reader.measure(&frame));
+
+        if measurements.len() % 100 == 0 {
+            println!("Measured {} frames", measurements.len());
+        }
+    }
+
+    Ok(measurements)
+}
+
+/// Detects the scene cuts from the luma histogram changes between frames.
+/// Returns the start frame of every scene.
+pub fn detect_scene_cuts(measurements: &[FrameMeasurement], threshold: f64) -> Vec<usize> {
+    let mut cuts = Vec::new();
+
+    if !measurements.is_empty() {
+        cuts.push(0);
+    }
+
+    cuts.extend(
+        measurements
+            .windows(2)
+            .enumerate()
+            .filter(|(_, w)| w[0].histogram_difference(&w[1]) > threshold)
+            .map(|(i, _)| i + 1),
+    );
+
+    cuts
+}
+
+/// Sets the L1 of the config shots from the measurements, aggregated per shot.
+/// Without shots in the config, the shots are created from the scene cuts.
+/// L6 MaxCLL and MaxFALL are also set when they are zero in the config.
+pub fn generate_shots(
+    measurements: &[FrameMeasurement],
+    scene_cuts: &[usize],
+    config: &mut GenerateConfig,
+) -> Result<()> {
+    if config.shots.is_empty() {
+        config.shots = scene_cuts
+            .iter()
+            .enumerate()
+            .map(|(i, start)| {
+                let end = scene_cuts.get(i + 1).copied().unwrap_or(measurements.len());
+
+                VideoShot {
+                    start: *start,
+                    duration: end - start,
+                    ..Default::default()
+                }
+            })
+            .collect();
+    }
+
+    for shot in config.shots.iter_mut() {
+        let end = shot.start + shot.duration;
+
+        ensure!(
+            shot.duration > 0 && end <= measurements.len(),
+            "Invalid shot {}-{}, {} frames measured",
+            shot.start,
+            end,
+            measurements.len()
+        );
+
+        let frames = &measurements[shot.start..end];
+
+        let min_pq = frames.iter().map(|f| f.min_pq).min().unwrap_or(0);
+        let max_pq = frames.iter().map(|f| f.max_pq).max().unwrap_or(0);
+        let avg_pq = (frames.iter().map(|f| f.avg_pq as f64).sum::<f64>() / frames.len() as f64)
+            .round() as u16;
+
+        shot.metadata_blocks.retain(|b| b.level() != 1);
+        shot.metadata_blocks.insert(
+            0,
+            ExtMetadataBlock::Level1(ExtMetadataBlockLevel1::from_stats(min_pq, max_pq, avg_pq)),
+        );
+    }
+
+    config.length = measurements.len();
+
+    if config.level6.max_content_light_level == 0 {
+        let max_pq = measurements.iter().map(|f| f.max_pq).max().unwrap_or(0);
+        config.level6.max_content_light_level = pq_to_nits(max_pq as f64 / 4095.0).round() as u16;
+    }
+
+    if config.level6.max_frame_average_light_level == 0 {
+        let max_fall = measurements.iter().map(|f| f.avg_nits).fold(0.0, f64::max);
+        config.level6.max_frame_average_light_level = max_fall.round() as u16;
+    }
+
+    Ok(())
+}
@@ -10,9 +10,22 @@ use commands::Command;
 
 mod dovi;
 use dovi::{
-    converter::Converter, demuxer::Demuxer, editor::Editor, exporter::Exporter,
-    generator::Generator, rpu_extractor::RpuExtractor, rpu_info::RpuInfo,
-    rpu_injector::RpuInjector, CliOptions, Format,
+    converter::Converter,
+    demuxer::Demuxer,
+    editor::Editor,
+    exporter::Exporter,
+    generator::Generator,
+    hdr10plus::Hdr10PlusConverter,
+    letterbox::LetterboxDetector,
+    letterbox::LetterboxOpts,
+    madvr::MadVRConverter,
+    remover::Remover,
+    rpu_extractor::RpuExtractor,
+    rpu_info::RpuInfo,
+    rpu_injector::RpuInjector,
+    rpu_splitter::{RpuSplitter, SplitPoints},
+    sync::{RpuSync, SyncReference},
+    CliOptions, Format,
 };
 
 #[derive(StructOpt, Debug)]
@@ -48,6 +61,8 @@ fn main() -> Result<()> {
         crop: opt.crop,
         discard_el: false,
         drop_hdr10plus: opt.drop_hdr10plus,
+        hdr10_sei: false,
+        fps: None,
     };
 
     // Set mode 0 by default if cropping, otherwise it has no effect
@@ -73,8 +88,12 @@ fn main() -> Result<()> {
             stdin,
             output,
             discard,
+            hdr10_sei,
+            fps,
         } => {
             cli_options.discard_el = discard;
+            cli_options.hdr10_sei = hdr10_sei;
+            cli_options.fps = fps;
             Converter::convert(input, stdin, output, cli_options)
         }
         Command::ExtractRpu {
@@ -86,10 +105,95 @@ fn main() -> Result<()> {
             input,
             rpu_in,
             output,
-        } => RpuInjector::inject_rpu(input, rpu_in, output, cli_options),
-        Command::Info { input, frame } => RpuInfo::info(input, frame),
+            hdr10_sei,
+            fps,
+        } => {
+            cli_options.hdr10_sei = hdr10_sei;
+            cli_options.fps = fps;
+            RpuInjector::inject_rpu(input, rpu_in, output, cli_options)
+        }
+        Command::Info {
+            input,
+            frame,
+            hevc,
+            fps,
+            config_out,
+        } => RpuInfo::info(input, frame, hevc, fps, config_out),
         Command::Generate { .. } => Generator::generate(opt.cmd),
-        Command::Export { input, output } => Exporter::export(input, output),
+        Command::Export {
+            input,
+            output,
+            columns,
+        } => Exporter::export(input, output, columns),
+        Command::Remove {
+            input,
+            stdin,
+            output,
+        } => Remover::remove(input, stdin, output, cli_options),
+        Command::Split {
+            input,
+            frames,
+            scene_cuts,
+            output_dir,
+            force,
+        } => {
+            let split_points = match scene_cuts {
+                Some(count) => SplitPoints::SceneCuts(count),
+                None => SplitPoints::Frames(frames),
+            };
+
+            RpuSplitter::split(input, split_points, output_dir, force)
+        }
+        Command::Concat { inputs, output } => RpuSplitter::concat(inputs, output),
+        Command::ToHdr10plus {
+            input,
+            output,
+            target_nits,
+        } => Hdr10PlusConverter::convert(input, output, target_nits),
+        Command::ToMadvr {
+            input,
+            output,
+            target_nits,
+        } => MadVRConverter::convert(input, output, target_nits),
+        Command::Sync {
+            input,
+            reference_rpu,
+            madvr_file,
+            json_out,
+            max_offset,
+        } => {
+            let reference = match (reference_rpu, madvr_file) {
+                (Some(path), _) => SyncReference::Rpu(path),
+                (None, Some(path)) => SyncReference::MadVR(path),
+                (None, None) => bail!("A reference RPU or madVR file is required"),
+            };
+
+            RpuSync::sync(input, reference, json_out, max_offset)
+        }
+        Command::DetectLetterbox {
+            video,
+            video_width,
+            video_height,
+            json_out,
+            rpu_in,
+            rpu_out,
+            scene_threshold,
+            black_level,
+            dark_level,
+            tolerance,
+        } => LetterboxDetector::detect(
+            video,
+            video_width.zip(video_height),
+            json_out,
+            rpu_in,
+            rpu_out,
+            LetterboxOpts {
+                scene_threshold,
+                black_level,
+                dark_level,
+                tolerance,
+            },
+        ),
     };
 
     if let Err(e) = res {